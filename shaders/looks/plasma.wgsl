@@ -0,0 +1,18 @@
+// Plasma swirl, the bass stretches the rings and every beat flashes.
+//
+// A look is a `look` function that returns the colour for one pixel. Everything
+// in `inputs` (time, levels, bands, spectrum, beat phase, resolution) is listed
+// under "Shader inputs" in the Custom Shader settings. Saving the file reloads it.
+fn look(uv: vec2<f32>, frag_coord: vec2<f32>) -> vec4<f32> {
+    let aspect = inputs.resolution.x / max(inputs.resolution.y, 1.0);
+    let p = (uv - 0.5) * vec2<f32>(aspect, 1.0) * 4.0;
+    let bass = inputs.bands[0].x;
+    let t = inputs.time * 0.5;
+
+    let v = (sin(p.x + t) + sin(p.y * 1.3 - t) + sin(length(p) * (2.0 + bass * 8.0) - t * 2.0)) / 3.0;
+    let color = 0.5 + 0.5 * cos(6.2831 * (vec3<f32>(0.0, 0.33, 0.67) + v + inputs.time * 0.05));
+
+    let decay = 1.0 - inputs.beat_phase;
+    let flash = 1.0 + decay * decay * 0.8;
+    return vec4<f32>(color * (0.35 + inputs.rms * 2.0) * flash, 1.0);
+}
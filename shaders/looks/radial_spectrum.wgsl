@@ -0,0 +1,17 @@
+// The spectrum wrapped around a circle, bass at the top, mirrored left / right
+fn look(uv: vec2<f32>, frag_coord: vec2<f32>) -> vec4<f32> {
+    let aspect = inputs.resolution.x / max(inputs.resolution.y, 1.0);
+    let p = (uv - 0.5) * vec2<f32>(aspect, 1.0);
+    let r = length(p);
+    // 0 straight up, 1 straight down, on both sides
+    let around = abs(atan2(p.x, p.y)) / 3.14159;
+
+    let level = spectrum_at(around);
+    let edge = 0.15 + level * 0.3 + inputs.peak * 0.05;
+    let inside = smoothstep(edge, edge - 0.01, r) * smoothstep(0.13, 0.14, r);
+    let glow = exp(-abs(r - edge) * 60.0);
+
+    let hue = vec3<f32>(0.0, 0.33, 0.67) + around * 0.6 + inputs.time * 0.03;
+    let color = 0.5 + 0.5 * cos(6.2831 * hue);
+    return vec4<f32>(color * (inside * 0.6 + glow), 1.0);
+}
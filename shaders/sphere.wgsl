@@ -0,0 +1,150 @@
+// === UNIFORMS ===
+// Model view and projection are kept apart so sprites can be offset in view space
+struct Camera {
+    model_view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// Group 1: Visual parameters (Color and sprite shape)
+struct VisualParams { // Define struct as VisualParams
+    color: vec4<f32>,
+    point_size: f32,
+    softness: f32,
+    size_reactivity: f32,
+    opacity: f32,
+    depth_fade: f32,
+    fade_near: f32,
+    fade_far: f32,
+    alpha_cutoff: f32,
+    color_source: u32,
+    gradient_shift: f32,
+    _padding: vec2<f32>,
+};
+@group(1) @binding(0)
+var<uniform> visual_params: VisualParams; // Use the defined struct name VisualParams
+// Palette gradient baked into a lookup table
+@group(1) @binding(1)
+var<storage, read> gradient: array<vec4<f32>>;
+
+fn gradient_color(t: f32) -> vec3<f32> {
+    // Only wrap when the palette is cycling, otherwise the ends would meet at 1
+    let position = select(clamp(t, 0.0, 1.0), fract(t + visual_params.gradient_shift), visual_params.gradient_shift != 0.0);
+    let last = arrayLength(&gradient) - 1u;
+    let x = position * f32(last);
+    let i = min(u32(floor(x)), last);
+    let j = min(i + 1u, last);
+    return mix(gradient[i].rgb, gradient[j].rgb, fract(x));
+}
+
+// Group 2: Spectrum, one 0..1 value per band, bass first
+struct SpectrumParams {
+    mapping: u32,
+    num_bands: u32,
+    displacement: f32,
+    _padding: f32,
+};
+@group(2) @binding(0)
+var<storage, read> spectrum: array<f32>;
+@group(2) @binding(1)
+var<uniform> spectrum_params: SpectrumParams;
+
+// Where on the spectrum (0 = bass, 1 = treble) a point with this uv sits
+fn spectrum_coordinate(uv: vec2<f32>) -> f32 {
+    switch spectrum_params.mapping {
+        case 1u: {
+            return 1.0 - abs(uv.y * 2.0 - 1.0);
+        }
+        case 2u: {
+            return 1.0 - abs(uv.x * 2.0 - 1.0);
+        }
+        default: {
+            return uv.y;
+        }
+    }
+}
+
+// Linear interpolation between neighbouring bands, so band edges don't show up as rings
+fn spectrum_energy(coordinate: f32) -> f32 {
+    let last = spectrum_params.num_bands - 1u;
+    let x = coordinate * f32(last);
+    let i = min(u32(floor(x)), last);
+    let j = min(i + 1u, last);
+    return mix(spectrum[i], spectrum[j], fract(x));
+}
+
+// === VERTEX SHADER ===
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // -1..1 across the sprite
+    @location(0) local: vec2<f32>,
+    // 0 at the front of the shape, 1 at the back
+    @location(1) depth: f32,
+    @location(2) color: vec3<f32>,
+};
+
+// One camera facing quad per point, the points are per instance
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32, model: VertexInput) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(-1.0, 1.0),
+        vec2<f32>(-1.0, 1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+
+    let coordinate = spectrum_coordinate(model.uv);
+    let energy = spectrum_energy(coordinate);
+    let displaced = model.position + model.normal * energy * spectrum_params.displacement;
+    let size = visual_params.point_size * (1.0 + energy * visual_params.size_reactivity);
+
+    // Offsetting after the model view transform keeps the quad facing the camera
+    let view_position = camera.model_view * vec4<f32>(displaced, 1.0);
+    let corner_position = view_position + vec4<f32>(corner * size * 0.5, 0.0, 0.0);
+
+    var out: VertexOutput;
+    out.clip_position = camera.projection * corner_position;
+    out.local = corner;
+    let depth_range = max(visual_params.fade_far - visual_params.fade_near, 0.001);
+    out.depth = clamp((-view_position.z - visual_params.fade_near) / depth_range, 0.0, 1.0);
+    switch visual_params.color_source {
+        case 1u: {
+            out.color = gradient_color(coordinate);
+        }
+        case 2u: {
+            out.color = gradient_color(energy);
+        }
+        case 3u: {
+            out.color = gradient_color(out.depth);
+        }
+        default: {
+            out.color = visual_params.color.rgb;
+        }
+    }
+    return out;
+}
+
+// === FRAGMENT SHADER ===
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let distance = length(in.local);
+    if distance > 1.0 {
+        discard;
+    }
+    // Softness 0 is a hard disc, 1 fades all the way from the centre.
+    // smoothstep needs its edges apart, hence the max.
+    let edge = max(visual_params.softness, 0.01);
+    let falloff = 1.0 - smoothstep(1.0 - edge, 1.0, distance);
+    if falloff < visual_params.alpha_cutoff {
+        discard;
+    }
+    // Points towards the back of the shape fade out
+    let fade = 1.0 - visual_params.depth_fade * in.depth;
+    return vec4<f32>(in.color, visual_params.opacity * falloff * fade);
+}
@@ -1,48 +1,24 @@
+use crate::audio::crossover::DEFAULT_BAND_NAMES;
+use crate::audio::effects::{AnalysisTap, FilterSettings, FxSettings};
+use crate::audio::time_stretch::{StretchMode, MAX_PLAYBACK_RATE, MIN_PLAYBACK_RATE};
 use crate::audio::{AudioAnalysisData, AudioManager, PlaybackState};
-use crate::visualization::{
-    renderer::WgpuSphereRenderer, sphere_geometry::generate_sphere_points_fibonacci,
-};
-use eframe::{egui, egui_wgpu::CallbackTrait, App, Frame};
+use crate::visualization::capture::FrameCapture;
+use crate::visualization::{VisualizerInput, VisualizerPaintCallback, VisualizerRegistry};
+use eframe::{egui, App, Frame};
 use parking_lot::Mutex;
 use std::path::Path;
 use std::sync::{mpsc, Arc};
-use type_map::concurrent::TypeMap;
-use wgpu;
+use std::time::Duration;
 
-const NUM_SPHERE_POINTS: usize = 2000;
-const SPHERE_RADIUS: f32 = 1.0;
 const DEFAULT_VOLUME: Option<f32> = Some(0.25);
-
-struct Custom3DPaintCallback {
-    primitive: Arc<crate::visualization::renderer::SphereWgpuPrimitive>,
-    mvp_matrix: glam::Mat4,
-    queue: Arc<wgpu::Queue>,
-    color: [f32; 3],
-}
-
-impl CallbackTrait for Custom3DPaintCallback {
-    fn paint<'a>(
-        &'a self,
-        _info: egui::PaintCallbackInfo,
-        render_pass: &mut wgpu::RenderPass<'a>,
-        _resources: &'a TypeMap,
-    ) {
-        crate::visualization::renderer::WgpuSphereRenderer::paint_primitive(
-            &self.primitive,
-            &self.mvp_matrix,
-            &self.color,
-            render_pass,
-            &self.queue,
-        );
-    }
-}
+const MAX_CROSSFADE_SECONDS: f32 = 10.0;
 
 pub struct AudioVisualizerApp {
     file_path_input: String,
     audio_manager: Result<AudioManager, String>,
     action_error_message: Option<String>,
-    sphere_renderer: Arc<Mutex<WgpuSphereRenderer>>,
-    #[allow(dead_code)]
+    visualizers: Arc<Mutex<VisualizerRegistry>>,
+    capture: FrameCapture,
     wgpu_device: Option<Arc<wgpu::Device>>,
     wgpu_queue: Option<Arc<wgpu::Queue>>,
     audio_analysis_receiver: mpsc::Receiver<AudioAnalysisData>,
@@ -51,35 +27,50 @@ pub struct AudioVisualizerApp {
     volume: f32,
     pre_mute_volume: f32,
     is_muted: bool,
+    crossfade_seconds: f32,
+    playback_rate: f32,
+    stretch_mode: StretchMode,
+    cue_name_input: String,
+    fx_settings: FxSettings,
+    analysis_tap: AnalysisTap,
 }
 
 impl AudioVisualizerApp {
     pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
-        let sphere_points = generate_sphere_points_fibonacci(SPHERE_RADIUS, NUM_SPHERE_POINTS);
-        let mut local_sphere_renderer = WgpuSphereRenderer::new(sphere_points);
+        let mut local_visualizers = VisualizerRegistry::with_defaults();
         let mut app_wgpu_device_arc = None;
         let mut app_wgpu_queue_arc = None;
+        let mut target_format = None;
         if let Some(wgpu_render_state) = &cc.wgpu_render_state {
             let device_arc = wgpu_render_state.device.clone();
             let queue_arc = wgpu_render_state.queue.clone();
-            let target_format = wgpu_render_state.target_format;
-            if let Err(e) = local_sphere_renderer.prepare(&device_arc, target_format) {
-                tracing::error!("Failed to prepare WGPU sphere renderer: {}", e);
-            } else {
-                app_wgpu_device_arc = Some(device_arc);
-                app_wgpu_queue_arc = Some(queue_arc);
-            }
+            target_format = Some(wgpu_render_state.target_format);
+            local_visualizers.set_gpu(
+                device_arc.clone(),
+                &wgpu_render_state.adapter,
+                wgpu_render_state.target_format,
+            );
+            app_wgpu_device_arc = Some(device_arc);
+            app_wgpu_queue_arc = Some(queue_arc);
         } else {
             tracing::warn!("WGPU render state not available at creation.");
         }
-        let sphere_renderer_shared = Arc::new(Mutex::new(local_sphere_renderer));
+        let visualizers_shared = Arc::new(Mutex::new(local_visualizers));
         let (analysis_sender, audio_analysis_receiver) = mpsc::sync_channel(10);
+        let audio_manager = AudioManager::new(DEFAULT_VOLUME);
+        let fx_settings = audio_manager
+            .as_ref()
+            .map_or_else(|_| FxSettings::default(), |m| m.get_fx_settings());
+        let analysis_tap = audio_manager
+            .as_ref()
+            .map_or(AnalysisTap::PostFx, |m| m.get_analysis_tap());
 
         Self {
             file_path_input: "/Users/donald/Downloads/example.mp3".to_string(),
-            audio_manager: AudioManager::new(DEFAULT_VOLUME),
+            audio_manager,
             action_error_message: None,
-            sphere_renderer: sphere_renderer_shared,
+            visualizers: visualizers_shared,
+            capture: FrameCapture::new(target_format),
             wgpu_device: app_wgpu_device_arc,
             wgpu_queue: app_wgpu_queue_arc,
             audio_analysis_receiver,
@@ -88,10 +79,287 @@ impl AudioVisualizerApp {
             volume: DEFAULT_VOLUME.unwrap_or(0.25),
             pre_mute_volume: DEFAULT_VOLUME.unwrap_or(0.25),
             is_muted: false,
+            crossfade_seconds: 0.0,
+            playback_rate: 1.0,
+            stretch_mode: StretchMode::Resample,
+            cue_name_input: String::new(),
+            fx_settings,
+            analysis_tap,
+        }
+    }
+
+    // Message box along the top of the visualization, for errors the mode wants seen
+    fn overlay_ui(ui: &egui::Ui, rect: egui::Rect, message: &str) {
+        let margin = egui::vec2(8.0, 6.0);
+        let galley = ui.painter().layout(
+            message.to_string(),
+            egui::FontId::monospace(12.0),
+            egui::Color32::from_rgb(255, 120, 120),
+            rect.width() - margin.x * 2.0,
+        );
+        let height = (galley.size().y + margin.y * 2.0).min(rect.height());
+        let background = egui::Rect::from_min_size(rect.min, egui::vec2(rect.width(), height));
+        let painter = ui.painter_at(background);
+        painter.rect_filled(background, 0.0, egui::Color32::from_black_alpha(200));
+        painter.galley(rect.min + margin, galley, egui::Color32::WHITE);
+    }
+
+    // Per band meters from the crossover bank: RMS as the bar, peak and onset as text
+    fn band_levels_ui(&self, ui: &mut egui::Ui) {
+        let Some(data) = &self.current_audio_data else {
+            ui.label("No analysis data yet.");
+            return;
+        };
+        for (name, levels) in DEFAULT_BAND_NAMES.iter().zip(&data.bands) {
+            ui.horizontal(|ui| {
+                ui.add_sized([40.0, 16.0], egui::Label::new(*name));
+                ui.add(
+                    egui::ProgressBar::new((levels.rms * 3.0).clamp(0.0, 1.0)).desired_width(160.0),
+                );
+                ui.label(format!("peak {:.2}", levels.peak));
+                let onset_color = if levels.onset > 0.5 {
+                    egui::Color32::LIGHT_GREEN
+                } else {
+                    ui.visuals().weak_text_color()
+                };
+                ui.colored_label(onset_color, format!("onset {:.2}", levels.onset));
+            });
+        }
+    }
+
+    // Effect chain parameters. Changes are pushed to the audio thread as a whole.
+    fn effects_ui(&mut self, ui: &mut egui::Ui) {
+        let mut changed = false;
+        let fx = &mut self.fx_settings;
+
+        ui.horizontal(|ui| {
+            changed |= ui.checkbox(&mut fx.enabled, "Enable effects").changed();
+            ui.separator();
+            ui.label("Analyze:");
+            let mut tap_changed = ui
+                .radio_value(&mut self.analysis_tap, AnalysisTap::PreFx, "Pre-FX")
+                .changed();
+            tap_changed |= ui
+                .radio_value(&mut self.analysis_tap, AnalysisTap::PostFx, "Post-FX")
+                .changed();
+            if tap_changed {
+                if let Ok(manager) = &mut self.audio_manager {
+                    manager.set_analysis_tap(self.analysis_tap);
+                }
+            }
+        });
+
+        ui.add_enabled_ui(fx.enabled, |ui| {
+            changed |= filter_ui(ui, "High-pass", &mut fx.high_pass);
+            changed |= filter_ui(ui, "Low-pass", &mut fx.low_pass);
+
+            for (index, band) in fx.eq_bands.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    changed |= ui
+                        .checkbox(&mut band.enabled, format!("EQ {}", index + 1))
+                        .changed();
+                    changed |= ui
+                        .add(
+                            egui::Slider::new(&mut band.frequency_hz, 20.0..=20_000.0)
+                                .logarithmic(true)
+                                .suffix(" Hz")
+                                .max_decimals(0),
+                        )
+                        .changed();
+                    changed |= ui
+                        .add(egui::Slider::new(&mut band.gain_db, -18.0..=18.0).suffix(" dB"))
+                        .changed();
+                    changed |= ui
+                        .add(egui::Slider::new(&mut band.q, 0.1..=10.0).text("Q"))
+                        .changed();
+                });
+            }
+
+            let compressor = &mut fx.compressor;
+            ui.horizontal(|ui| {
+                changed |= ui.checkbox(&mut compressor.enabled, "Compressor").changed();
+                changed |= ui
+                    .add(
+                        egui::Slider::new(&mut compressor.threshold_db, -60.0..=0.0)
+                            .text("Thresh")
+                            .suffix(" dB"),
+                    )
+                    .changed();
+                changed |= ui
+                    .add(egui::Slider::new(&mut compressor.ratio, 1.0..=20.0).text("Ratio"))
+                    .changed();
+            });
+            ui.horizontal(|ui| {
+                ui.add_space(90.0);
+                changed |= ui
+                    .add(
+                        egui::Slider::new(&mut compressor.attack_ms, 0.1..=200.0)
+                            .logarithmic(true)
+                            .text("Attack")
+                            .suffix(" ms"),
+                    )
+                    .changed();
+                changed |= ui
+                    .add(
+                        egui::Slider::new(&mut compressor.release_ms, 5.0..=2000.0)
+                            .logarithmic(true)
+                            .text("Release")
+                            .suffix(" ms"),
+                    )
+                    .changed();
+                changed |= ui
+                    .add(
+                        egui::Slider::new(&mut compressor.makeup_db, 0.0..=24.0)
+                            .text("Makeup")
+                            .suffix(" dB"),
+                    )
+                    .changed();
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Gain:");
+                changed |= ui
+                    .add(egui::Slider::new(&mut fx.gain_db, -24.0..=24.0).suffix(" dB"))
+                    .changed();
+            });
+        });
+
+        if changed {
+            if let Ok(manager) = &mut self.audio_manager {
+                manager.set_fx_settings(self.fx_settings.clone());
+            }
+        }
+    }
+
+    // A-B loop controls and the cue point list for the current file
+    fn loop_and_cue_ui(&mut self, ui: &mut egui::Ui) {
+        let Ok(manager) = &mut self.audio_manager else {
+            return;
+        };
+        let has_file = manager.get_current_file_path().is_some();
+
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "Position: {}",
+                format_position(manager.get_playback_position())
+            ));
+            ui.separator();
+            if ui
+                .add_enabled(has_file, egui::Button::new("Set A"))
+                .clicked()
+            {
+                manager.set_loop_start();
+            }
+            if ui
+                .add_enabled(has_file, egui::Button::new("Set B"))
+                .clicked()
+            {
+                manager.set_loop_end();
+            }
+            let (loop_a, loop_b) = manager.get_loop_points();
+            let mut loop_enabled = manager.is_loop_enabled();
+            if ui
+                .add_enabled(
+                    loop_a.is_some() && loop_b.is_some(),
+                    egui::Checkbox::new(&mut loop_enabled, "Loop"),
+                )
+                .changed()
+            {
+                manager.set_loop_enabled(loop_enabled);
+            }
+            if ui
+                .add_enabled(
+                    loop_a.is_some() || loop_b.is_some(),
+                    egui::Button::new("Clear"),
+                )
+                .clicked()
+            {
+                manager.clear_loop();
+            }
+            ui.label(format!(
+                "A: {}  B: {}",
+                loop_a.map_or_else(|| "--".to_string(), format_position),
+                loop_b.map_or_else(|| "--".to_string(), format_position)
+            ));
+        });
+
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.cue_name_input)
+                    .hint_text("Cue name")
+                    .desired_width(150.0),
+            );
+            if ui
+                .add_enabled(has_file, egui::Button::new("Add Cue"))
+                .clicked()
+            {
+                match manager.add_cue_point(&self.cue_name_input) {
+                    Ok(()) => self.cue_name_input.clear(),
+                    Err(e) => self.action_error_message = Some(e),
+                }
+            }
+        });
+
+        let mut jump_to = None;
+        let mut remove = None;
+        ui.horizontal_wrapped(|ui| {
+            for (index, cue) in manager.get_cue_points().iter().enumerate() {
+                let label = format!(
+                    "{} @ {}",
+                    cue.name,
+                    format_position(Duration::from_secs_f64(cue.position.max(0.0)))
+                );
+                if ui.button(label).clicked() {
+                    jump_to = Some(index);
+                }
+                if ui.small_button("x").on_hover_text("Remove cue").clicked() {
+                    remove = Some(index);
+                }
+                ui.add_space(6.0);
+            }
+        });
+        if let Some(index) = jump_to {
+            manager.jump_to_cue_point(index);
+        }
+        if let Some(index) = remove {
+            if let Err(e) = manager.remove_cue_point(index) {
+                self.action_error_message = Some(e);
+            }
         }
     }
 }
 
+// Enable / cutoff / Q row for one of the pass filters. Returns true if anything changed.
+fn filter_ui(ui: &mut egui::Ui, label: &str, filter: &mut FilterSettings) -> bool {
+    let mut changed = false;
+    ui.horizontal(|ui| {
+        changed |= ui.checkbox(&mut filter.enabled, label).changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut filter.cutoff_hz, 20.0..=20_000.0)
+                    .logarithmic(true)
+                    .suffix(" Hz")
+                    .max_decimals(0),
+            )
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut filter.q, 0.3..=5.0).text("Q"))
+            .changed();
+    });
+    changed
+}
+
+// mm:ss.mmm
+fn format_position(position: Duration) -> String {
+    let millis = position.as_millis();
+    format!(
+        "{:02}:{:02}.{:03}",
+        millis / 60_000,
+        (millis / 1000) % 60,
+        millis % 1000
+    )
+}
+
 impl App for AudioVisualizerApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut Frame) {
         let playback_state = self
@@ -105,12 +373,21 @@ impl App for AudioVisualizerApp {
             self.current_audio_data = Some(data);
         }
 
-        let current_color = {
-            let mut renderer_guard = self.sphere_renderer.lock();
-            renderer_guard.time += ctx.input(|i| i.stable_dt);
-            renderer_guard.update_visual_state(playback_state, &self.current_audio_data);
-            renderer_guard.current_color_rgb
+        let input = VisualizerInput {
+            playback_state,
+            analysis: self.current_audio_data.as_ref(),
         };
+        let dt = ctx.input(|i| i.stable_dt);
+        self.visualizers.lock().update(&input, dt);
+        if let Some(device) = &self.wgpu_device {
+            self.capture.update(device, dt);
+        }
+        if ctx.input(|i| i.key_pressed(egui::Key::F9)) {
+            self.capture.request_screenshot();
+        }
+        if ctx.input(|i| i.key_pressed(egui::Key::F10)) {
+            self.capture.save_clip();
+        }
 
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("Audio Visualizer");
@@ -158,12 +435,63 @@ impl App for AudioVisualizerApp {
                     }
                 }
             });
+            ui.horizontal(|ui| {
+                ui.label("Crossfade:");
+                let crossfade_slider = ui.add(
+                    egui::Slider::new(&mut self.crossfade_seconds, 0.0..=MAX_CROSSFADE_SECONDS)
+                        .suffix(" s")
+                        .clamp_to_range(true)
+                        .min_decimals(1),
+                );
+                if crossfade_slider.changed() {
+                    if let Ok(manager) = &mut self.audio_manager {
+                        manager.set_crossfade_duration(Duration::from_secs_f32(
+                            self.crossfade_seconds,
+                        ));
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Speed:");
+                let mut rate_changed = ui
+                    .add(
+                        egui::Slider::new(
+                            &mut self.playback_rate,
+                            MIN_PLAYBACK_RATE..=MAX_PLAYBACK_RATE,
+                        )
+                        .suffix("x")
+                        .clamp_to_range(true)
+                        .min_decimals(2),
+                    )
+                    .changed();
+                if ui.button("1x").clicked() {
+                    self.playback_rate = 1.0;
+                    rate_changed = true;
+                }
+                let mut mode_changed = false;
+                egui::ComboBox::from_id_source("stretch_mode")
+                    .selected_text(self.stretch_mode.label())
+                    .show_ui(ui, |ui| {
+                        for mode in StretchMode::ALL {
+                            mode_changed |= ui
+                                .selectable_value(&mut self.stretch_mode, mode, mode.label())
+                                .changed();
+                        }
+                    });
+                if let Ok(manager) = &mut self.audio_manager {
+                    if rate_changed {
+                        manager.set_playback_rate(self.playback_rate);
+                    }
+                    if mode_changed {
+                        manager.set_stretch_mode(self.stretch_mode);
+                    }
+                }
+            });
             ui.add_space(5.0);
             let (play_button_text, play_button_enabled) = match &self.audio_manager {
                 Ok(manager) => {
-                    let current_path_is_target = manager
-                        .get_current_file_path()
-                        .map_or(false, |p| p == &self.file_path_input);
+                    let current_path_is_target =
+                        manager.get_current_file_path() == Some(&self.file_path_input);
                     match manager.get_state() {
                         PlaybackState::Idle => ("Play", !self.file_path_input.is_empty()),
                         PlaybackState::Loaded => {
@@ -209,9 +537,8 @@ impl App for AudioVisualizerApp {
                     if let Ok(manager) = &mut self.audio_manager {
                         self.action_error_message = None;
                         let current_manager_state = manager.get_state();
-                        let manager_knows_current_file = manager
-                            .get_current_file_path()
-                            .map_or(false, |p| p == &self.file_path_input);
+                        let manager_knows_current_file =
+                            manager.get_current_file_path() == Some(&self.file_path_input);
                         let input_is_empty_but_manager_has_file = self.file_path_input.is_empty()
                             && manager.get_current_file_path().is_some();
                         let mut op_result: Result<(), String> = Ok(());
@@ -269,6 +596,22 @@ impl App for AudioVisualizerApp {
                         manager.pause_playback();
                     }
                 }
+                if ui
+                    .add_enabled(
+                        self.audio_manager.is_ok() && !self.file_path_input.is_empty(),
+                        egui::Button::new("Queue Next"),
+                    )
+                    .clicked()
+                {
+                    if let Ok(manager) = &mut self.audio_manager {
+                        self.action_error_message = None;
+                        if let Err(e) =
+                            manager.queue_file(&self.file_path_input, self.analysis_sender.clone())
+                        {
+                            self.action_error_message = Some(e);
+                        }
+                    }
+                }
             });
             let status_message = if let Some(err_msg) = &self.action_error_message {
                 format!("Error: {}", err_msg)
@@ -291,39 +634,94 @@ impl App for AudioVisualizerApp {
                 }
             };
             ui.label(status_message);
+            if let Ok(manager) = &mut self.audio_manager {
+                let queued_files = manager.get_queued_files();
+                if !queued_files.is_empty() {
+                    ui.horizontal(|ui| {
+                        let queued_names: Vec<String> = queued_files
+                            .iter()
+                            .map(|p_str| {
+                                Path::new(p_str).file_name().map_or_else(
+                                    || p_str.clone(),
+                                    |os_str| os_str.to_string_lossy().into_owned(),
+                                )
+                            })
+                            .collect();
+                        ui.label(format!("Up next: {}", queued_names.join(", ")));
+                        if ui.small_button("Clear").clicked() {
+                            manager.clear_queue();
+                        }
+                    });
+                }
+            }
+            egui::CollapsingHeader::new("Loop & Cue Points").show(ui, |ui| {
+                self.loop_and_cue_ui(ui);
+            });
+            egui::CollapsingHeader::new("Effects").show(ui, |ui| {
+                self.effects_ui(ui);
+            });
+            egui::CollapsingHeader::new("Band Levels").show(ui, |ui| {
+                self.band_levels_ui(ui);
+            });
             ui.separator();
 
-            ui.label("3D Point Sphere Visualization:");
+            ui.horizontal(|ui| {
+                let mut visualizers = self.visualizers.lock();
+                ui.label("Visualization:");
+                let names = visualizers.names();
+                let mut selected = visualizers.active_index();
+                egui::ComboBox::from_id_source("visualizer_mode")
+                    .selected_text(names.get(selected).copied().unwrap_or("None"))
+                    .show_ui(ui, |ui| {
+                        for (index, name) in names.iter().enumerate() {
+                            ui.selectable_value(&mut selected, index, *name);
+                        }
+                    });
+                visualizers.set_active(selected);
+            });
+            egui::CollapsingHeader::new("Visualization Settings").show(ui, |ui| {
+                if let Some(visualizer) = self.visualizers.lock().active_mut() {
+                    visualizer.settings_ui(ui);
+                }
+            });
+            egui::CollapsingHeader::new("Post Processing").show(ui, |ui| {
+                self.visualizers.lock().post_mut().settings_ui(ui);
+            });
+            egui::CollapsingHeader::new("Capture").show(ui, |ui| {
+                self.capture.settings_ui(ui);
+            });
             let desired_size = ui.available_size_before_wrap() * egui::vec2(1.0, 0.75);
-            let (rect, _response) = ui.allocate_exact_size(desired_size, egui::Sense::hover());
-
-            let (primitive_is_initialized, mvp_matrix_option) = {
-                let renderer_guard = self.sphere_renderer.lock();
-                let is_init = renderer_guard.get_primitive_arc().is_some();
-                let mvp = if is_init {
-                    let aspect_ratio = rect.width() / rect.height();
-                    Some(renderer_guard.calculate_mvp(aspect_ratio))
-                } else {
-                    None
-                };
-                (is_init, mvp)
-            };
+            let (available, response) =
+                ui.allocate_exact_size(desired_size, egui::Sense::click_and_drag());
+            if let Some(visualizer) = self.visualizers.lock().active_mut() {
+                visualizer.handle_input(&response);
+            }
+            // Custom capture sizes letterbox the view to their aspect ratio
+            let rect = self.capture.view_rect(available);
+            if rect != available {
+                ui.painter()
+                    .rect_filled(available, 0.0, egui::Color32::BLACK);
+            }
+            let pixels = rect.size() * ctx.pixels_per_point();
+            let size_in_pixels = self.capture.frame_size([
+                (pixels.x.round() as u32).max(1),
+                (pixels.y.round() as u32).max(1),
+            ]);
 
-            if primitive_is_initialized {
-                if let (Some(queue_arc), Some(mvp_matrix)) = (&self.wgpu_queue, mvp_matrix_option) {
-                    let primitive_arc = self
-                        .sphere_renderer
-                        .lock()
-                        .get_primitive_arc()
-                        .expect("Primitive should be initialized here");
+            let frame = self
+                .visualizers
+                .lock()
+                .paint(size_in_pixels[0] as f32 / size_in_pixels[1] as f32);
 
+            if let Some(frame) = frame {
+                if let (Some(queue_arc), Some(device)) = (&self.wgpu_queue, &self.wgpu_device) {
                     let cb = eframe::egui_wgpu::Callback::new_paint_callback(
                         rect,
-                        Custom3DPaintCallback {
-                            primitive: primitive_arc,
-                            mvp_matrix,
+                        VisualizerPaintCallback {
+                            frame,
                             queue: queue_arc.clone(),
-                            color: current_color,
+                            size_in_pixels,
+                            capture: self.capture.take_target(device, size_in_pixels),
                         },
                     );
                     ui.painter().add(cb);
@@ -348,6 +746,14 @@ impl App for AudioVisualizerApp {
                     egui::Color32::WHITE,
                 );
             }
+            let message = self
+                .visualizers
+                .lock()
+                .active_mut()
+                .and_then(|visualizer| visualizer.overlay_message());
+            if let Some(message) = message {
+                Self::overlay_ui(ui, rect, &message);
+            }
         });
 
         ctx.request_repaint();
@@ -0,0 +1,119 @@
+use std::f32::consts::{FRAC_1_SQRT_2, TAU};
+
+// Butterworth Q, two of these in series make a Linkwitz-Riley section
+pub const BUTTERWORTH_Q: f32 = FRAC_1_SQRT_2;
+
+// Normalised biquad coefficients (a0 already divided out).
+// Formulas from the RBJ "Audio EQ Cookbook".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BiquadCoefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl BiquadCoefficients {
+    // Passes everything through untouched
+    pub const IDENTITY: BiquadCoefficients = BiquadCoefficients {
+        b0: 1.0,
+        b1: 0.0,
+        b2: 0.0,
+        a1: 0.0,
+        a2: 0.0,
+    };
+
+    pub fn low_pass(sample_rate: f32, cutoff_hz: f32, q: f32) -> Self {
+        let (cos_w0, alpha) = prewarp(sample_rate, cutoff_hz, q);
+        Self::normalised(
+            (1.0 - cos_w0) / 2.0,
+            1.0 - cos_w0,
+            (1.0 - cos_w0) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos_w0,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn high_pass(sample_rate: f32, cutoff_hz: f32, q: f32) -> Self {
+        let (cos_w0, alpha) = prewarp(sample_rate, cutoff_hz, q);
+        Self::normalised(
+            (1.0 + cos_w0) / 2.0,
+            -(1.0 + cos_w0),
+            (1.0 + cos_w0) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos_w0,
+            1.0 - alpha,
+        )
+    }
+
+    // Bell boost / cut around `center_hz`
+    pub fn peaking(sample_rate: f32, center_hz: f32, q: f32, gain_db: f32) -> Self {
+        let (cos_w0, alpha) = prewarp(sample_rate, center_hz, q);
+        let a = 10.0f32.powf(gain_db / 40.0);
+        Self::normalised(
+            1.0 + alpha * a,
+            -2.0 * cos_w0,
+            1.0 - alpha * a,
+            1.0 + alpha / a,
+            -2.0 * cos_w0,
+            1.0 - alpha / a,
+        )
+    }
+
+    fn normalised(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> Self {
+        BiquadCoefficients {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+// Single channel biquad, transposed direct form II.
+// Coefficients can be swapped at any time without resetting the state.
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad {
+    coefficients: BiquadCoefficients,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    pub fn new(coefficients: BiquadCoefficients) -> Self {
+        Biquad {
+            coefficients,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    pub fn set_coefficients(&mut self, coefficients: BiquadCoefficients) {
+        self.coefficients = coefficients;
+    }
+
+    #[inline]
+    pub fn process(&mut self, input: f32) -> f32 {
+        let c = &self.coefficients;
+        let output = c.b0 * input + self.z1;
+        self.z1 = c.b1 * input - c.a1 * output + self.z2;
+        self.z2 = c.b2 * input - c.a2 * output;
+        output
+    }
+
+    pub fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+}
+
+fn prewarp(sample_rate: f32, frequency_hz: f32, q: f32) -> (f32, f32) {
+    // Keep the frequency below Nyquist, the formulas fall apart above it
+    let frequency_hz = frequency_hz.clamp(1.0, sample_rate * 0.49);
+    let w0 = TAU * frequency_hz / sample_rate;
+    let alpha = w0.sin() / (2.0 * q.max(0.01));
+    (w0.cos(), alpha)
+}
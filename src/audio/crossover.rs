@@ -0,0 +1,271 @@
+use crate::audio::biquad::{Biquad, BiquadCoefficients, BUTTERWORTH_Q};
+use crate::audio::effects::time_constant;
+
+// Split points between sub / low / mid / high
+pub const DEFAULT_CROSSOVER_FREQUENCIES: [f32; 3] = [60.0, 250.0, 4000.0];
+pub const DEFAULT_BAND_NAMES: [&str; 4] = ["Sub", "Low", "Mid", "High"];
+
+// Onset detection compares a fast and a slow power envelope per band.
+// The fast one has to average over a couple of periods of the lowest frequency in the band,
+// otherwise it just follows the waveform and a steady tone reads as a stream of onsets.
+const FAST_ENVELOPE_PERIODS: f32 = 2.0;
+const FAST_ENVELOPE_MIN_MS: f32 = 5.0;
+const SLOW_ENVELOPE_MS: f32 = 250.0;
+// Lowest frequency assumed for the bottom band
+const BOTTOM_BAND_FLOOR_HZ: f32 = 30.0;
+// Keeps silence from turning tiny wobbles into huge relative onsets (power, so -60dB)
+const ONSET_FLOOR: f32 = 1e-6;
+
+// Levels of a single band over one analysis window
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BandLevels {
+    pub rms: f32,
+    pub peak: f32,
+    // How far the band jumped above its recent average level, 0 when nothing new is happening.
+    // 1.0 means the short term level hit twice the long term one somewhere in the window.
+    pub onset: f32,
+}
+
+// 4th order Linkwitz-Riley split: two Butterworth sections in series on each side
+struct LinkwitzRileySplit {
+    low: [Biquad; 2],
+    high: [Biquad; 2],
+}
+
+impl LinkwitzRileySplit {
+    fn new(sample_rate: f32, frequency_hz: f32) -> Self {
+        let low = Biquad::new(BiquadCoefficients::low_pass(
+            sample_rate,
+            frequency_hz,
+            BUTTERWORTH_Q,
+        ));
+        let high = Biquad::new(BiquadCoefficients::high_pass(
+            sample_rate,
+            frequency_hz,
+            BUTTERWORTH_Q,
+        ));
+        LinkwitzRileySplit {
+            low: [low; 2],
+            high: [high; 2],
+        }
+    }
+
+    #[inline]
+    fn split(&mut self, input: f32) -> (f32, f32) {
+        let low = self.low.iter_mut().fold(input, |x, f| f.process(x));
+        let high = self.high.iter_mut().fold(input, |x, f| f.process(x));
+        (low, high)
+    }
+
+    fn reset(&mut self) {
+        self.low
+            .iter_mut()
+            .chain(&mut self.high)
+            .for_each(Biquad::reset);
+    }
+}
+
+// Fast and slow power envelope of one band
+#[derive(Clone, Copy)]
+struct OnsetDetector {
+    fast_coefficient: f32,
+    fast: f32,
+    slow: f32,
+    // False until a block has gone through since the last reset. Without any history the slow
+    // envelope would start at 0 and make whatever comes first look like a huge onset.
+    seeded: bool,
+}
+
+impl OnsetDetector {
+    fn new(lowest_frequency_hz: f32, sample_rate: f32) -> Self {
+        let fast_ms =
+            (FAST_ENVELOPE_PERIODS * 1000.0 / lowest_frequency_hz).max(FAST_ENVELOPE_MIN_MS);
+        OnsetDetector {
+            fast_coefficient: time_constant(fast_ms, sample_rate),
+            fast: 0.0,
+            slow: 0.0,
+            seeded: false,
+        }
+    }
+
+    #[inline]
+    fn process(&mut self, power: f32, slow_coefficient: f32) -> f32 {
+        self.fast = power + (self.fast - power) * self.fast_coefficient;
+        self.slow = power + (self.slow - power) * slow_coefficient;
+        // Back to amplitude, so the value reads as a level ratio
+        ((self.fast / self.slow.max(ONSET_FLOOR)).sqrt() - 1.0).max(0.0)
+    }
+
+    // Starts the slow envelope at the first block's level instead of at 0
+    fn seed(&mut self, mean_power: f32) {
+        self.slow = mean_power;
+        self.seeded = true;
+    }
+
+    fn reset(&mut self) {
+        self.fast = 0.0;
+        self.slow = 0.0;
+        self.seeded = false;
+    }
+}
+
+// Splits mono audio into bands sample by sample (no FFT windowing), so the levels
+// follow transients much more closely than grouped FFT bins do.
+// The splits are chained: everything above split N is fed into split N+1.
+pub struct CrossoverBank {
+    splits: Vec<LinkwitzRileySplit>,
+    detectors: Vec<OnsetDetector>,
+    slow_coefficient: f32,
+}
+
+impl CrossoverBank {
+    // `frequencies` must be ascending, N frequencies give N+1 bands
+    pub fn new(sample_rate: u32, frequencies: &[f32]) -> Self {
+        let sample_rate = sample_rate as f32;
+        CrossoverBank {
+            splits: frequencies
+                .iter()
+                .map(|&f| LinkwitzRileySplit::new(sample_rate, f))
+                .collect(),
+            detectors: std::iter::once(BOTTOM_BAND_FLOOR_HZ)
+                .chain(frequencies.iter().copied())
+                .map(|lowest| OnsetDetector::new(lowest, sample_rate))
+                .collect(),
+            slow_coefficient: time_constant(SLOW_ENVELOPE_MS, sample_rate),
+        }
+    }
+
+    pub fn num_bands(&self) -> usize {
+        self.detectors.len()
+    }
+
+    // Runs a block through the bank and returns per band levels for it, lowest band first
+    pub fn process_block(&mut self, samples: &[f32]) -> Vec<BandLevels> {
+        let mut levels = vec![BandLevels::default(); self.num_bands()];
+        let mut band_samples = vec![0.0f32; self.num_bands()];
+
+        for &sample in samples {
+            let mut rest = sample;
+            for (split, band_sample) in self.splits.iter_mut().zip(band_samples.iter_mut()) {
+                let (low, high) = split.split(rest);
+                *band_sample = low;
+                rest = high;
+            }
+            if let Some(last) = band_samples.last_mut() {
+                *last = rest;
+            }
+
+            for ((level, detector), &band_sample) in levels
+                .iter_mut()
+                .zip(self.detectors.iter_mut())
+                .zip(&band_samples)
+            {
+                let power = band_sample * band_sample;
+                level.rms += power;
+                level.peak = level.peak.max(band_sample.abs());
+                level.onset = level
+                    .onset
+                    .max(detector.process(power, self.slow_coefficient));
+            }
+        }
+
+        if !samples.is_empty() {
+            for (level, detector) in levels.iter_mut().zip(self.detectors.iter_mut()) {
+                let mean_power = level.rms / samples.len() as f32;
+                level.rms = mean_power.sqrt();
+                // Nothing to compare the first block after a reset against
+                if !detector.seeded {
+                    detector.seed(mean_power);
+                    level.onset = 0.0;
+                }
+            }
+        }
+        levels
+    }
+
+    // Forget filter memory and envelopes, for when the stream jumps
+    pub fn reset(&mut self) {
+        self.splits.iter_mut().for_each(LinkwitzRileySplit::reset);
+        self.detectors.iter_mut().for_each(OnsetDetector::reset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    fn sine(frequency_hz: f32, seconds: f32) -> Vec<f32> {
+        let length = (seconds * SAMPLE_RATE as f32) as usize;
+        (0..length)
+            .map(|i| (std::f32::consts::TAU * frequency_hz * i as f32 / SAMPLE_RATE as f32).sin())
+            .collect()
+    }
+
+    fn loudest_band(levels: &[BandLevels]) -> usize {
+        (0..levels.len())
+            .max_by(|&a, &b| levels[a].rms.total_cmp(&levels[b].rms))
+            .unwrap()
+    }
+
+    #[test]
+    fn tones_land_in_their_band() {
+        for (frequency, band) in [(35.0, 0), (120.0, 1), (1000.0, 2), (10000.0, 3)] {
+            let mut bank = CrossoverBank::new(SAMPLE_RATE, &DEFAULT_CROSSOVER_FREQUENCIES);
+            // Let the filters settle before measuring
+            bank.process_block(&sine(frequency, 0.5));
+            let levels = bank.process_block(&sine(frequency, 0.25));
+            assert_eq!(loudest_band(&levels), band, "{frequency} Hz: {levels:?}");
+            for (other, level) in levels.iter().enumerate() {
+                if other != band {
+                    assert!(
+                        level.rms < levels[band].rms * 0.25,
+                        "{frequency} Hz leaks into band {other}: {levels:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn steady_tone_has_no_onset_but_a_hit_does() {
+        let mut bank = CrossoverBank::new(SAMPLE_RATE, &DEFAULT_CROSSOVER_FREQUENCIES);
+        bank.process_block(&vec![0.0; SAMPLE_RATE as usize / 2]);
+        let hit = bank.process_block(&sine(1000.0, 0.05));
+        assert!(hit[2].onset > 1.0, "{hit:?}");
+
+        // A couple of slow envelope time constants later the tone is old news
+        bank.process_block(&sine(1000.0, 2.0));
+        let steady = bank.process_block(&sine(1000.0, 0.1));
+        assert!(steady[2].onset < 0.1, "{steady:?}");
+    }
+
+    #[test]
+    fn reset_forgets_the_envelopes() {
+        let mut bank = CrossoverBank::new(SAMPLE_RATE, &DEFAULT_CROSSOVER_FREQUENCIES);
+        bank.process_block(&sine(1000.0, 1.0));
+        bank.reset();
+        let levels = bank.process_block(&vec![0.0; 480]);
+        assert!(levels
+            .iter()
+            .all(|level| level.rms == 0.0 && level.onset == 0.0));
+    }
+
+    #[test]
+    fn steady_tone_after_a_reset_is_not_an_onset() {
+        // What the processor does after a dropped chunk, in the middle of a held note
+        let mut bank = CrossoverBank::new(SAMPLE_RATE, &DEFAULT_CROSSOVER_FREQUENCIES);
+        let tone: Vec<f32> = sine(40.0, 2.0)
+            .iter()
+            .zip(sine(1000.0, 2.0))
+            .map(|(low, mid)| (low + mid) * 0.5)
+            .collect();
+        bank.process_block(&tone[..SAMPLE_RATE as usize]);
+        bank.reset();
+        for block in tone[SAMPLE_RATE as usize..].chunks(1024) {
+            let levels = bank.process_block(block);
+            assert!(levels.iter().all(|level| level.onset < 0.25), "{levels:?}");
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+const CUE_FILE_NAME: &str = "cues.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CuePoint {
+    pub name: String,
+    // Seconds from the start of the track
+    pub position: f64,
+}
+
+// Everything we remember about a single audio file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrackMarkers {
+    // A-B loop, in seconds. Looping itself always starts switched off.
+    #[serde(default)]
+    pub loop_region: Option<(f64, f64)>,
+    #[serde(default)]
+    pub cues: Vec<CuePoint>,
+}
+
+// Loop regions and cue points per file, persisted as JSON in the user config dir
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CueStore {
+    files: BTreeMap<String, TrackMarkers>,
+    #[serde(skip)]
+    store_path: Option<PathBuf>,
+}
+
+impl CueStore {
+    // Loads the store from disk. A missing or broken file just gives an empty store.
+    pub fn load() -> Self {
+        let store_path = config_dir().map(|dir| dir.join(CUE_FILE_NAME));
+        let mut store = store_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(
+                |contents| match serde_json::from_str::<CueStore>(&contents) {
+                    Ok(store) => Some(store),
+                    Err(e) => {
+                        tracing::warn!("Ignoring unreadable cue file: {}", e);
+                        None
+                    }
+                },
+            )
+            .unwrap_or_default();
+        store.store_path = store_path;
+        store
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.store_path else {
+            return Err("No config directory available for cue points.".to_string());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create '{}': {}", dir.display(), e))?;
+        }
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize cue points: {}", e))?;
+        fs::write(path, contents)
+            .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))
+    }
+
+    pub fn markers(&self, file_path: &str) -> Option<&TrackMarkers> {
+        self.files.get(file_path)
+    }
+
+    pub fn markers_mut(&mut self, file_path: &str) -> &mut TrackMarkers {
+        self.files.entry(file_path.to_string()).or_default()
+    }
+}
+
+// `$XDG_CONFIG_HOME/audio_visualizer`, falling back to `~/.config/audio_visualizer`
+pub fn config_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(base.join("audio_visualizer"))
+}
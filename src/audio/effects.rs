@@ -0,0 +1,507 @@
+use crate::audio::biquad::{Biquad, BiquadCoefficients, BUTTERWORTH_Q};
+use parking_lot::Mutex;
+use rodio::Source;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// How often (in samples) the effects stage checks for new settings
+const SETTINGS_POLL_INTERVAL: usize = 512;
+
+// Where the analysis thread listens
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnalysisTap {
+    // Straight from the decoder (after rate change), effects are only heard
+    PreFx,
+    // Exactly what comes out of the speakers
+    PostFx,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilterSettings {
+    pub enabled: bool,
+    pub cutoff_hz: f32,
+    pub q: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EqBandSettings {
+    pub enabled: bool,
+    pub frequency_hz: f32,
+    pub gain_db: f32,
+    pub q: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressorSettings {
+    pub enabled: bool,
+    pub threshold_db: f32,
+    pub ratio: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+    pub makeup_db: f32,
+}
+
+// Everything the UI can tweak about the effect chain
+#[derive(Debug, Clone, PartialEq)]
+pub struct FxSettings {
+    // Master bypass for the whole chain
+    pub enabled: bool,
+    pub high_pass: FilterSettings,
+    pub low_pass: FilterSettings,
+    pub eq_bands: Vec<EqBandSettings>,
+    pub compressor: CompressorSettings,
+    pub gain_db: f32,
+}
+
+impl Default for FxSettings {
+    fn default() -> Self {
+        FxSettings {
+            enabled: false,
+            high_pass: FilterSettings {
+                enabled: false,
+                cutoff_hz: 80.0,
+                q: BUTTERWORTH_Q,
+            },
+            low_pass: FilterSettings {
+                enabled: false,
+                cutoff_hz: 250.0,
+                q: BUTTERWORTH_Q,
+            },
+            eq_bands: vec![
+                EqBandSettings {
+                    enabled: false,
+                    frequency_hz: 100.0,
+                    gain_db: 0.0,
+                    q: 1.0,
+                },
+                EqBandSettings {
+                    enabled: false,
+                    frequency_hz: 1000.0,
+                    gain_db: 0.0,
+                    q: 1.0,
+                },
+                EqBandSettings {
+                    enabled: false,
+                    frequency_hz: 6000.0,
+                    gain_db: 0.0,
+                    q: 1.0,
+                },
+            ],
+            compressor: CompressorSettings {
+                enabled: false,
+                threshold_db: -18.0,
+                ratio: 4.0,
+                attack_ms: 10.0,
+                release_ms: 120.0,
+                makeup_db: 0.0,
+            },
+            gain_db: 0.0,
+        }
+    }
+}
+
+// Shared between `AudioManager` and the effects stage in the sink.
+// The version counter lets the audio thread skip the lock when nothing changed.
+#[derive(Clone)]
+pub struct FxHandle {
+    settings: Arc<Mutex<FxSettings>>,
+    version: Arc<AtomicU64>,
+    analyze_post_fx: Arc<AtomicBool>,
+}
+
+impl FxHandle {
+    pub fn new() -> Self {
+        FxHandle {
+            settings: Arc::new(Mutex::new(FxSettings::default())),
+            version: Arc::new(AtomicU64::new(0)),
+            analyze_post_fx: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    pub fn settings(&self) -> FxSettings {
+        self.settings.lock().clone()
+    }
+
+    pub fn set_settings(&self, settings: FxSettings) {
+        *self.settings.lock() = settings;
+        self.version.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn set_analysis_tap(&self, tap: AnalysisTap) {
+        self.analyze_post_fx
+            .store(tap == AnalysisTap::PostFx, Ordering::Relaxed);
+    }
+
+    pub fn analysis_tap(&self) -> AnalysisTap {
+        if self.analyze_post_fx.load(Ordering::Relaxed) {
+            AnalysisTap::PostFx
+        } else {
+            AnalysisTap::PreFx
+        }
+    }
+
+    // Flag the post-FX broadcaster listens to (the pre-FX one uses the inverse)
+    pub fn post_fx_tap_flag(&self) -> Arc<AtomicBool> {
+        self.analyze_post_fx.clone()
+    }
+}
+
+// A single processor in the chain. Works on interleaved stereo frames.
+pub trait AudioEffect: Send {
+    // Picks up new parameters. Internal state (filter memory, envelopes) is kept so
+    // dragging a slider doesn't click.
+    fn update(&mut self, settings: &FxSettings, sample_rate: f32);
+
+    fn process(&mut self, frame: &mut [f32; 2]);
+}
+
+// Two biquads with the same coefficients, one per channel
+#[derive(Clone, Copy)]
+struct StereoBiquad {
+    channels: [Biquad; 2],
+}
+
+impl StereoBiquad {
+    fn new() -> Self {
+        StereoBiquad {
+            channels: [Biquad::new(BiquadCoefficients::IDENTITY); 2],
+        }
+    }
+
+    fn set_coefficients(&mut self, coefficients: BiquadCoefficients) {
+        self.channels
+            .iter_mut()
+            .for_each(|c| c.set_coefficients(coefficients));
+    }
+
+    #[inline]
+    fn process(&mut self, frame: &mut [f32; 2]) {
+        frame[0] = self.channels[0].process(frame[0]);
+        frame[1] = self.channels[1].process(frame[1]);
+    }
+
+    fn reset(&mut self) {
+        self.channels.iter_mut().for_each(Biquad::reset);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    HighPass,
+    LowPass,
+}
+
+pub struct PassFilter {
+    kind: FilterKind,
+    enabled: bool,
+    filter: StereoBiquad,
+}
+
+impl PassFilter {
+    pub fn new(kind: FilterKind) -> Self {
+        PassFilter {
+            kind,
+            enabled: false,
+            filter: StereoBiquad::new(),
+        }
+    }
+}
+
+impl AudioEffect for PassFilter {
+    fn update(&mut self, settings: &FxSettings, sample_rate: f32) {
+        let filter_settings = match self.kind {
+            FilterKind::HighPass => settings.high_pass,
+            FilterKind::LowPass => settings.low_pass,
+        };
+        if filter_settings.enabled && !self.enabled {
+            self.filter.reset();
+        }
+        self.enabled = filter_settings.enabled;
+        self.filter.set_coefficients(match self.kind {
+            FilterKind::HighPass => BiquadCoefficients::high_pass(
+                sample_rate,
+                filter_settings.cutoff_hz,
+                filter_settings.q,
+            ),
+            FilterKind::LowPass => BiquadCoefficients::low_pass(
+                sample_rate,
+                filter_settings.cutoff_hz,
+                filter_settings.q,
+            ),
+        });
+    }
+
+    fn process(&mut self, frame: &mut [f32; 2]) {
+        if self.enabled {
+            self.filter.process(frame);
+        }
+    }
+}
+
+// Peaking bands in series
+pub struct ParametricEq {
+    bands: Vec<(bool, StereoBiquad)>,
+}
+
+impl ParametricEq {
+    pub fn new() -> Self {
+        ParametricEq { bands: Vec::new() }
+    }
+}
+
+impl AudioEffect for ParametricEq {
+    fn update(&mut self, settings: &FxSettings, sample_rate: f32) {
+        self.bands
+            .resize(settings.eq_bands.len(), (false, StereoBiquad::new()));
+        for ((enabled, filter), band) in self.bands.iter_mut().zip(&settings.eq_bands) {
+            *enabled = band.enabled && band.gain_db.abs() > f32::EPSILON;
+            filter.set_coefficients(BiquadCoefficients::peaking(
+                sample_rate,
+                band.frequency_hz,
+                band.q,
+                band.gain_db,
+            ));
+        }
+    }
+
+    fn process(&mut self, frame: &mut [f32; 2]) {
+        for (enabled, filter) in self.bands.iter_mut() {
+            if *enabled {
+                filter.process(frame);
+            }
+        }
+    }
+}
+
+// Feed-forward peak compressor, stereo linked so the image doesn't wander
+pub struct Compressor {
+    settings: CompressorSettings,
+    attack_coeff: f32,
+    release_coeff: f32,
+    envelope_db: f32,
+}
+
+impl Compressor {
+    pub fn new() -> Self {
+        Compressor {
+            settings: FxSettings::default().compressor,
+            attack_coeff: 0.0,
+            release_coeff: 0.0,
+            envelope_db: -120.0,
+        }
+    }
+}
+
+impl AudioEffect for Compressor {
+    fn update(&mut self, settings: &FxSettings, sample_rate: f32) {
+        self.settings = settings.compressor;
+        self.attack_coeff = time_constant(self.settings.attack_ms, sample_rate);
+        self.release_coeff = time_constant(self.settings.release_ms, sample_rate);
+    }
+
+    fn process(&mut self, frame: &mut [f32; 2]) {
+        if !self.settings.enabled {
+            return;
+        }
+        let level_db = linear_to_db(frame[0].abs().max(frame[1].abs()));
+        let coeff = if level_db > self.envelope_db {
+            self.attack_coeff
+        } else {
+            self.release_coeff
+        };
+        self.envelope_db = level_db + coeff * (self.envelope_db - level_db);
+
+        let over_db = (self.envelope_db - self.settings.threshold_db).max(0.0);
+        let reduction_db = over_db * (1.0 - 1.0 / self.settings.ratio.max(1.0));
+        let gain = db_to_linear(self.settings.makeup_db - reduction_db);
+        frame[0] *= gain;
+        frame[1] *= gain;
+    }
+}
+
+pub struct Gain {
+    gain: f32,
+}
+
+impl Gain {
+    pub fn new() -> Self {
+        Gain { gain: 1.0 }
+    }
+}
+
+impl AudioEffect for Gain {
+    fn update(&mut self, settings: &FxSettings, _sample_rate: f32) {
+        self.gain = db_to_linear(settings.gain_db);
+    }
+
+    fn process(&mut self, frame: &mut [f32; 2]) {
+        frame[0] *= self.gain;
+        frame[1] *= self.gain;
+    }
+}
+
+// What the player runs: high-pass -> low-pass -> EQ -> compressor -> gain
+pub fn default_chain() -> Vec<Box<dyn AudioEffect>> {
+    vec![
+        Box::new(PassFilter::new(FilterKind::HighPass)),
+        Box::new(PassFilter::new(FilterKind::LowPass)),
+        Box::new(ParametricEq::new()),
+        Box::new(Compressor::new()),
+        Box::new(Gain::new()),
+    ]
+}
+
+// Runs an effect chain over a stereo source, in the chain's order
+pub struct EffectsStage<S>
+where
+    S: Source<Item = f32> + Send + 'static,
+{
+    source: S,
+    handle: FxHandle,
+    effects: Vec<Box<dyn AudioEffect>>,
+    enabled: bool,
+    seen_version: u64,
+    samples_until_poll: usize,
+    // Processed frame being handed out sample by sample
+    frame: [f32; 2],
+    frame_index: usize,
+}
+
+impl<S> EffectsStage<S>
+where
+    S: Source<Item = f32> + Send + 'static,
+{
+    pub fn new(source: S, handle: FxHandle) -> Self {
+        Self::with_chain(source, handle, default_chain())
+    }
+
+    pub fn with_chain(source: S, handle: FxHandle, effects: Vec<Box<dyn AudioEffect>>) -> Self {
+        let mut stage = EffectsStage {
+            source,
+            handle,
+            effects,
+            enabled: false,
+            seen_version: 0,
+            samples_until_poll: 0,
+            frame: [0.0; 2],
+            frame_index: 2,
+        };
+        stage.apply_settings();
+        stage
+    }
+
+    fn apply_settings(&mut self) {
+        self.seen_version = self.handle.version.load(Ordering::Acquire);
+        let settings = self.handle.settings();
+        let sample_rate = self.source.sample_rate() as f32;
+        self.enabled = settings.enabled;
+        for effect in self.effects.iter_mut() {
+            effect.update(&settings, sample_rate);
+        }
+    }
+}
+
+impl<S> Iterator for EffectsStage<S>
+where
+    S: Source<Item = f32> + Send + 'static,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.frame_index < 2 {
+            let sample = self.frame[self.frame_index];
+            self.frame_index += 1;
+            return Some(sample);
+        }
+
+        // Settings only change between frames
+        if self.samples_until_poll == 0 {
+            if self.handle.version.load(Ordering::Acquire) != self.seen_version {
+                self.apply_settings();
+            }
+            self.samples_until_poll = SETTINGS_POLL_INTERVAL;
+        }
+        self.samples_until_poll = self.samples_until_poll.saturating_sub(2);
+
+        let left = self.source.next()?;
+        let right = self.source.next().unwrap_or(left);
+        self.frame = [left, right];
+        if self.enabled {
+            for effect in self.effects.iter_mut() {
+                effect.process(&mut self.frame);
+            }
+        }
+        self.frame_index = 1;
+        Some(self.frame[0])
+    }
+}
+
+impl<S> Source for EffectsStage<S>
+where
+    S: Source<Item = f32> + Send + 'static,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+}
+
+// One-pole smoothing coefficient for a time constant in milliseconds
+pub(crate) fn time_constant(time_ms: f32, sample_rate: f32) -> f32 {
+    (-1.0 / (time_ms.max(0.01) * 0.001 * sample_rate)).exp()
+}
+
+pub fn linear_to_db(value: f32) -> f32 {
+    20.0 * value.max(1e-6).log10()
+}
+
+pub fn db_to_linear(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    // Swaps the channels, easy to spot in the output
+    struct SwapChannels;
+
+    impl AudioEffect for SwapChannels {
+        fn update(&mut self, _settings: &FxSettings, _sample_rate: f32) {}
+
+        fn process(&mut self, frame: &mut [f32; 2]) {
+            frame.swap(0, 1);
+        }
+    }
+
+    #[test]
+    fn custom_chain_runs_only_while_enabled() {
+        let source = || SamplesBuffer::new(2, 44_100, vec![0.25, 0.75, 0.25, 0.75]);
+        let handle = FxHandle::new();
+        let chain = || -> Vec<Box<dyn AudioEffect>> { vec![Box::new(SwapChannels)] };
+
+        let bypassed: Vec<f32> =
+            EffectsStage::with_chain(source(), handle.clone(), chain()).collect();
+        assert_eq!(bypassed, [0.25, 0.75, 0.25, 0.75]);
+
+        handle.set_settings(FxSettings {
+            enabled: true,
+            ..FxSettings::default()
+        });
+        let processed: Vec<f32> = EffectsStage::with_chain(source(), handle, chain()).collect();
+        assert_eq!(processed, [0.75, 0.25, 0.75, 0.25]);
+    }
+}
@@ -1,6 +1,11 @@
 use crate::audio::{
+    cues::{CuePoint, CueStore},
+    effects::{AnalysisTap, EffectsStage, FxHandle, FxSettings},
     processor::{AudioAnalysisData, AudioProcessor},
-    sample_broadcaster::SampleBroadcaster,
+    sample_broadcaster::{SampleBroadcaster, SampleChunk},
+    sequencer::{SequencerHandle, Track, TrackSequencer, OUTPUT_SAMPLE_RATE},
+    time_stretch::{PlaybackRateHandle, StretchMode, TimeStretcher},
+    transport::TransportHandle,
 };
 use rodio::{Decoder, OutputStream, Sink, Source};
 use std::fs::File;
@@ -12,7 +17,7 @@ use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
 
-const DEFAULT_FFT_SIZE: usize = 1024;
+pub const DEFAULT_FFT_SIZE: usize = 1024;
 const SAMPLES_PER_CHUNK: usize = DEFAULT_FFT_SIZE;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -32,6 +37,17 @@ pub struct AudioManager {
     sink: Option<Sink>,
     processing_thread_handle: Option<thread::JoinHandle<()>>,
     stop_signal_sender: Option<mpsc::Sender<()>>,
+    // Control handle for the track sequencer playing inside the current sink
+    sequencer: Option<SequencerHandle>,
+    crossfade: Duration,
+    // Shared with every session's TimeStretcher, so the rate survives track loads
+    playback_rate: PlaybackRateHandle,
+    // Position, A-B loop and seeking for the current track, also shared across sessions
+    transport: TransportHandle,
+    // Remembered loop regions and cue points for every file we've seen
+    cue_store: CueStore,
+    // Effect chain settings and analysis tap selection, shared across sessions
+    fx: FxHandle,
     current_file_path: Option<String>,
     state: PlaybackState,
     current_volume: f32,
@@ -44,11 +60,17 @@ impl AudioManager {
             .map_err(|e| format!("Failed to open output stream: {}", e))?;
 
         Ok(AudioManager {
-            stream: stream,
+            stream,
             stream_handle,
             sink: None,
             processing_thread_handle: None,
             stop_signal_sender: None,
+            sequencer: None,
+            crossfade: Duration::ZERO,
+            playback_rate: PlaybackRateHandle::new(),
+            transport: TransportHandle::new(),
+            cue_store: CueStore::load(),
+            fx: FxHandle::new(),
             current_file_path: None,
             state: PlaybackState::Idle,
             current_volume: volume.unwrap_or(0.0).clamp(0.0, 1.0),
@@ -66,8 +88,185 @@ impl AudioManager {
         }
     }
 
+    // Sets the crossfade length used when switching or advancing tracks.
+    // Zero means a straight gapless cut.
+    pub fn set_crossfade_duration(&mut self, crossfade: Duration) {
+        self.crossfade = crossfade;
+        tracing::debug!("Setting crossfade to: {:?}", crossfade);
+
+        if let Some(sequencer) = &self.sequencer {
+            sequencer.set_crossfade(crossfade);
+        }
+    }
+
+    // Sets the playback rate (0.25x - 2x). How pitch behaves depends on the stretch mode.
+    pub fn set_playback_rate(&mut self, rate: f32) {
+        self.playback_rate.set_rate(rate);
+        tracing::debug!("Setting playback rate to: {}", self.playback_rate.rate());
+    }
+
+    pub fn set_stretch_mode(&mut self, mode: StretchMode) {
+        self.playback_rate.set_mode(mode);
+        tracing::debug!("Setting stretch mode to: {:?}", mode);
+    }
+
+    pub fn get_fx_settings(&self) -> FxSettings {
+        self.fx.settings()
+    }
+
+    pub fn set_fx_settings(&mut self, settings: FxSettings) {
+        self.fx.set_settings(settings);
+    }
+
+    // Whether the visualizer sees the signal before or after the effect chain
+    pub fn set_analysis_tap(&mut self, tap: AnalysisTap) {
+        self.fx.set_analysis_tap(tap);
+        tracing::debug!("Setting analysis tap to: {:?}", tap);
+    }
+
+    pub fn get_analysis_tap(&self) -> AnalysisTap {
+        self.fx.analysis_tap()
+    }
+
+    // Position in the current track (in track time, not affected by the playback rate)
+    pub fn get_playback_position(&self) -> Duration {
+        frames_to_duration(self.heard_position_frames())
+    }
+
+    // What is coming out of the speakers right now. The decoder runs ahead of that by the
+    // sequencer's crossfade lookahead and the time-stretcher's buffers.
+    fn heard_position_frames(&self) -> u64 {
+        self.transport
+            .heard_position_frames(self.playback_rate.latency_frames() as u64)
+    }
+
+    // Jumps within the current track. Sample accurate, served from memory when it lands
+    // between the earliest cue / loop point A and a minute after it.
+    pub fn seek(&mut self, position: Duration) {
+        if self.sequencer.is_some() {
+            self.transport.request_seek(duration_to_frames(position));
+        }
+    }
+
+    // Marks loop point A at the current position
+    pub fn set_loop_start(&mut self) {
+        self.transport.set_loop_start(self.heard_position_frames());
+        self.remember_loop_region();
+    }
+
+    // Marks loop point B at the current position and starts looping if A is set
+    pub fn set_loop_end(&mut self) {
+        self.transport.set_loop_end(self.heard_position_frames());
+        if self.transport.loop_region().is_some() {
+            self.transport.set_loop_enabled(true);
+        }
+        self.remember_loop_region();
+    }
+
+    pub fn set_loop_enabled(&mut self, enabled: bool) {
+        self.transport
+            .set_loop_enabled(enabled && self.transport.loop_region().is_some());
+    }
+
+    pub fn is_loop_enabled(&self) -> bool {
+        self.transport.loop_enabled()
+    }
+
+    pub fn clear_loop(&mut self) {
+        self.transport.clear_loop_region();
+        self.remember_loop_region();
+    }
+
+    // Loop points A and B as set so far, either may be missing
+    pub fn get_loop_points(&self) -> (Option<Duration>, Option<Duration>) {
+        let (start, end) = self.transport.loop_points();
+        (start.map(frames_to_duration), end.map(frames_to_duration))
+    }
+
+    pub fn get_cue_points(&self) -> &[CuePoint] {
+        self.current_file_path
+            .as_ref()
+            .and_then(|path| self.cue_store.markers(path))
+            .map_or(&[], |markers| markers.cues.as_slice())
+    }
+
+    // Adds a named cue point at the current position, kept sorted by position
+    pub fn add_cue_point(&mut self, name: &str) -> Result<(), String> {
+        let Some(path) = self.current_file_path.clone() else {
+            return Err("No file loaded to add a cue point to.".to_string());
+        };
+        let position = self.get_playback_position().as_secs_f64();
+        let name = if name.trim().is_empty() {
+            format!("Cue {}", self.get_cue_points().len() + 1)
+        } else {
+            name.trim().to_string()
+        };
+
+        let cues = &mut self.cue_store.markers_mut(&path).cues;
+        cues.push(CuePoint { name, position });
+        cues.sort_by(|a, b| a.position.total_cmp(&b.position));
+        self.transport
+            .set_earliest_cue(earliest_cue_frame(self.get_cue_points()));
+        self.cue_store.save()
+    }
+
+    pub fn remove_cue_point(&mut self, index: usize) -> Result<(), String> {
+        let Some(path) = self.current_file_path.clone() else {
+            return Ok(());
+        };
+        let cues = &mut self.cue_store.markers_mut(&path).cues;
+        if index < cues.len() {
+            cues.remove(index);
+        }
+        self.transport
+            .set_earliest_cue(earliest_cue_frame(self.get_cue_points()));
+        self.cue_store.save()
+    }
+
+    pub fn jump_to_cue_point(&mut self, index: usize) {
+        if let Some(cue) = self.get_cue_points().get(index) {
+            let position = Duration::from_secs_f64(cue.position.max(0.0));
+            tracing::info!("Jumping to cue '{}' at {:?}", cue.name, position);
+            self.seek(position);
+        }
+    }
+
+    // Writes the transport's loop region back to the cue store for the current file
+    fn remember_loop_region(&mut self) {
+        let Some(path) = self.current_file_path.clone() else {
+            return;
+        };
+        let loop_region = self.transport.loop_region().map(|(start, end)| {
+            (
+                frames_to_duration(start).as_secs_f64(),
+                frames_to_duration(end).as_secs_f64(),
+            )
+        });
+        self.cue_store.markers_mut(&path).loop_region = loop_region;
+        if let Err(e) = self.cue_store.save() {
+            tracing::warn!("Failed to save loop region: {}", e);
+        }
+    }
+
+    // Opens a file along with whatever loop region and cues we remember for it
+    fn open_track(&self, file_path: &str) -> Result<Track, String> {
+        let markers = self.cue_store.markers(file_path);
+        let loop_region = markers
+            .and_then(|markers| markers.loop_region)
+            .map(|(start, end)| {
+                (
+                    duration_to_frames(Duration::from_secs_f64(start.max(0.0))),
+                    duration_to_frames(Duration::from_secs_f64(end.max(0.0))),
+                )
+            });
+        let earliest_cue = markers.and_then(|markers| earliest_cue_frame(&markers.cues));
+        open_track(file_path, loop_region, earliest_cue)
+    }
+
     // Loads and plays the specified MP3 file, begins audio processing.
     // Uses the `analysis_sender` channel for analysis results
+    // If something is already playing, the new file takes over without stopping the sink,
+    // so there is no gap and the analysis stream keeps running.
     pub fn load_and_play_file(
         &mut self,
         file_path: &str,
@@ -81,23 +280,83 @@ impl AudioManager {
             return Err("File path cannot be empty.".to_string());
         }
 
+        // Decode first, a bad path shouldn't interrupt whatever is currently playing
+        let track = self.open_track(file_path)?;
+
+        if let Some(sequencer) = self.active_sequencer() {
+            sequencer.play_now(track);
+            if let Some(sink) = &self.sink {
+                sink.play();
+            }
+            self.current_file_path = Some(file_path.to_string());
+            self.state = PlaybackState::Playing;
+            tracing::info!("Switching to file: {}", file_path);
+            return Ok(());
+        }
+
+        self.start_session(track, analysis_sender)?;
+        self.current_file_path = Some(file_path.to_string());
+        tracing::info!("Playing file: {}", file_path);
+
+        Ok(())
+    }
+
+    // Queues a file to play right after the current one (gapless, or crossfaded).
+    // Starts playback immediately if nothing is playing.
+    pub fn queue_file(
+        &mut self,
+        file_path: &str,
+        analysis_sender: mpsc::SyncSender<AudioAnalysisData>,
+    ) -> Result<(), String> {
+        if file_path.trim().is_empty() {
+            return Err("File path cannot be empty.".to_string());
+        }
+
+        match self.active_sequencer() {
+            Some(sequencer) => {
+                sequencer.enqueue(self.open_track(file_path)?);
+                tracing::info!("Queued file: {}", file_path);
+                Ok(())
+            }
+            None => self.load_and_play_file(file_path, analysis_sender),
+        }
+    }
+
+    pub fn clear_queue(&mut self) {
+        if let Some(sequencer) = &self.sequencer {
+            sequencer.clear_queue();
+        }
+    }
+
+    pub fn get_queued_files(&self) -> Vec<String> {
+        self.sequencer
+            .as_ref()
+            .map_or_else(Vec::new, |s| s.queued_paths())
+    }
+
+    // Sequencer of a sink that still has something to play
+    fn active_sequencer(&self) -> Option<SequencerHandle> {
+        match (&self.sink, &self.sequencer) {
+            (Some(sink), Some(sequencer)) if !sink.empty() => Some(sequencer.clone()),
+            _ => None,
+        }
+    }
+
+    // Builds a fresh sink + processing thread around a new sequencer
+    fn start_session(
+        &mut self,
+        first_track: Track,
+        analysis_sender: mpsc::SyncSender<AudioAnalysisData>,
+    ) -> Result<(), String> {
         // Cleanup previous state
         self.stop_playback_and_processing();
 
-        // Load and decode file
-        let path = Path::new(file_path);
-        let file = File::open(path)
-            .map_err(|e| format!("Failed to open file '{}': {}", path.display(), e))?;
-        let decoder_raw = Decoder::new(BufReader::new(file))
-            .map_err(|e| format!("Failed to decode file '{}': {}", path.display(), e))?;
-
-        // Convert samples to f32 - expected by the SampleBroadcaster
-        let decoder_f32 = decoder_raw.convert_samples::<f32>();
+        let (sequencer, sequencer_handle) =
+            TrackSequencer::new(first_track, self.crossfade, self.transport.clone());
 
-        // Store source properties *after* conversion if needed, ensure it's from the f32 source
-        let source_sample_rate = decoder_f32.sample_rate();
-        let source_channels = decoder_f32.channels();
-        self.current_file_path = Some(file_path.to_string());
+        // Store source properties, these are fixed by the sequencer for the whole session
+        let source_sample_rate = sequencer.sample_rate();
+        let source_channels = sequencer.channels();
         tracing::info!(
             "Source properties: Rate={}, Channels={}",
             source_sample_rate,
@@ -106,7 +365,7 @@ impl AudioManager {
 
         // Setup processing thread
         // Use bounded channel for sample data things
-        let (sample_chunk_sender, sample_chunk_receiver) = mpsc::sync_channel::<Vec<f32>>(5);
+        let (sample_chunk_sender, sample_chunk_receiver) = mpsc::sync_channel::<SampleChunk>(5);
         // Use unbounded channel for simple signals
         let (stop_sender, stop_receiver) = mpsc::channel::<()>();
         self.stop_signal_sender = Some(stop_sender);
@@ -116,7 +375,7 @@ impl AudioManager {
             .spawn(move || {
                 tracing::info!("Audio processing thread started.");
 
-                let mut processor = AudioProcessor::new(DEFAULT_FFT_SIZE);
+                let mut processor = AudioProcessor::new(DEFAULT_FFT_SIZE, source_sample_rate);
                 loop {
                     // Check for stop signal first
                     match stop_receiver.try_recv() {
@@ -130,28 +389,19 @@ impl AudioManager {
 
                     // Wait for the next chunk of samples with a timeout
                     match sample_chunk_receiver.recv_timeout(Duration::from_millis(200)) {
-                        Ok(samples) => {
-                            // Stereo to Mono Conversion
-                            // TODO: Currently this just takes the first channel. Look into
-                            //   averaging channels or find some crate to handle stereo
-                            let mono_samples: Option<AudioAnalysisData> = if source_channels == 1 {
-                                processor.process_samples(&samples)
-                            } else if source_channels > 1 && !samples.is_empty() {
-                                // Process first channel directly from input slice:
-                                // This is a naive approach - taking the first channel's data, assuming
-                                // interleaved samples [L, R, L, R, ...].
-                                // This allocation creates a temporary Vec owned by the thread for this iteration.
-                                // Using the collected Vec directly causes lifetime issues...
-                                // The borrowed `samples` slice must be processed directly...
-                                // TODO: Consider processing in place, or passing a mutable buffer maybe
-                                let first_channel_samples: Vec<f32> = samples.iter().step_by(source_channels as usize).cloned().collect();
-                                processor.process_samples(&first_channel_samples)
+                        Ok(chunk) => {
+                            let samples = chunk.samples;
+                            let first_frame = chunk.start_sample / source_channels.max(1) as u64;
+                            // The processor splits the channels itself, it keeps the first
+                            // channel for the spectrum and L/R pairs for the stereo views
+                            let analysis: Option<AudioAnalysisData> = if source_channels > 0 && !samples.is_empty() {
+                                processor.process_samples(first_frame, &samples, source_channels as usize)
                             } else {
                                 // No samples or 0 channels
                                 None
                             };
 
-                            if let Some(data) = mono_samples {
+                            if let Some(data) = analysis {
                                 if let Err(e) = analysis_sender.try_send(data) {
                                     if matches!(e, mpsc::TrySendError::Disconnected(_)) {
                                         // Exit if receiver is gone
@@ -181,8 +431,16 @@ impl AudioManager {
         self.processing_thread_handle = Some(processing_handle);
 
         // Setup playback sink
-        let broadcaster =
-            SampleBroadcaster::new(decoder_f32, sample_chunk_sender, SAMPLES_PER_CHUNK);
+        // Rate change happens before the broadcasters, analysis follows what is actually heard.
+        // Two broadcasters share the chunk channel, one on each side of the effect chain,
+        // and the analysis tap setting decides which of them sends.
+        let stretcher = TimeStretcher::new(sequencer, self.playback_rate.clone());
+        let pre_fx_tap =
+            SampleBroadcaster::new(stretcher, sample_chunk_sender.clone(), SAMPLES_PER_CHUNK)
+                .with_tap_gate(self.fx.post_fx_tap_flag(), false);
+        let effects = EffectsStage::new(pre_fx_tap, self.fx.clone());
+        let broadcaster = SampleBroadcaster::new(effects, sample_chunk_sender, SAMPLES_PER_CHUNK)
+            .with_tap_gate(self.fx.post_fx_tap_flag(), true);
         let sink = Sink::try_new(&self.stream_handle)
             .map_err(|e| format!("Failed to create sink: {}", e))?;
 
@@ -193,9 +451,9 @@ impl AudioManager {
         sink.append(broadcaster);
         sink.play();
         self.sink = Some(sink);
+        self.sequencer = Some(sequencer_handle);
 
         self.state = PlaybackState::Playing;
-        tracing::info!("Playing file: {}", file_path);
 
         Ok(())
     }
@@ -211,6 +469,7 @@ impl AudioManager {
             drop(sink); // Explicitly drop sink here
             tracing::debug!("Audio sink stopped and dropped.");
         }
+        self.sequencer = None;
         // The SampleBroadcaster and its `sample_chunk_sender` are dropped when the sink drops.
         // This causes the `sample_chunk_receiver` in the processing thread to eventually
         // receive `RecvTimeoutError::Disconnected`, allowing it to exit gracefully.
@@ -282,6 +541,14 @@ impl AudioManager {
     // Checks if the underlying sink has finished playing
     // Used in `update` of AudioVisualizerApp
     pub fn check_and_update_finished_state(&mut self) {
+        // The sequencer may have moved on to a queued track by itself
+        if let Some(now_playing) = self.sequencer.as_ref().and_then(|s| s.now_playing()) {
+            if self.current_file_path.as_ref() != Some(&now_playing) {
+                tracing::info!("Now playing: {}", now_playing);
+                self.current_file_path = Some(now_playing);
+            }
+        }
+
         let mut sink_finished = false;
         if let Some(sink) = &self.sink {
             if sink.empty() && self.state == PlaybackState::Playing {
@@ -327,6 +594,49 @@ impl AudioManager {
     }
 }
 
+fn frames_to_duration(frames: u64) -> Duration {
+    Duration::from_secs_f64(frames as f64 / OUTPUT_SAMPLE_RATE as f64)
+}
+
+fn duration_to_frames(duration: Duration) -> u64 {
+    (duration.as_secs_f64() * OUTPUT_SAMPLE_RATE as f64).round() as u64
+}
+
+// Cues are kept sorted, so that's the first one
+fn earliest_cue_frame(cues: &[CuePoint]) -> Option<u64> {
+    cues.first()
+        .map(|cue| duration_to_frames(Duration::from_secs_f64(cue.position.max(0.0))))
+}
+
+// Opens and decodes a file into a track the sequencer can play
+fn open_track(
+    file_path: &str,
+    loop_region: Option<(u64, u64)>,
+    earliest_cue: Option<u64>,
+) -> Result<Track, String> {
+    let path = Path::new(file_path);
+    let file =
+        File::open(path).map_err(|e| format!("Failed to open file '{}': {}", path.display(), e))?;
+    let decoder_raw = Decoder::new(BufReader::new(file))
+        .map_err(|e| format!("Failed to decode file '{}': {}", path.display(), e))?;
+
+    // Convert samples to f32 - expected by the SampleBroadcaster
+    let decoder_f32 = decoder_raw.convert_samples::<f32>();
+    tracing::info!(
+        "Decoded '{}': Rate={}, Channels={}",
+        path.display(),
+        decoder_f32.sample_rate(),
+        decoder_f32.channels()
+    );
+
+    Ok(Track::new(
+        file_path.to_string(),
+        Box::new(decoder_f32),
+        loop_region,
+        earliest_cue,
+    ))
+}
+
 // Ensure graceful shutdown when AudioManager is dropped
 // TODO: This might be redundant with the stop_playback_and_processing method.
 impl Drop for AudioManager {
@@ -1,6 +1,14 @@
+pub mod biquad;
+pub mod crossover;
+pub mod cues;
+pub mod effects;
 pub mod manager;
 pub mod processor;
 pub mod sample_broadcaster;
+pub mod sequencer;
+pub mod time_stretch;
+pub mod timeline;
+pub mod transport;
 
 pub use manager::{AudioManager, PlaybackState};
 pub use processor::AudioAnalysisData;
@@ -1,3 +1,4 @@
+use crate::audio::crossover::{BandLevels, CrossoverBank, DEFAULT_CROSSOVER_FREQUENCIES};
 use rustfft::{num_complex::Complex, FftPlanner};
 
 // TODO: Add fields for frequency binning, peak frequency, etc. later
@@ -10,7 +11,17 @@ pub struct AudioAnalysisData {
     pub peak_amplitude: f32,
     // N/2 + 1 points
     pub frequency_magnitudes: Vec<f32>,
+    // The window's raw (unwindowed) samples, for time domain views
+    pub waveform: Vec<f32>,
+    // Left / right pairs of the same window (mono sources get L = R)
+    pub stereo: Vec<[f32; 2]>,
     pub fft_size: usize,
+    // Needed to turn bin indices into frequencies
+    pub sample_rate: u32,
+    // Seconds into the played (post time-stretch) stream where this window starts
+    pub timestamp: f64,
+    // Crossover bank levels over the same window, lowest band first (see `DEFAULT_BAND_NAMES`)
+    pub bands: Vec<BandLevels>,
 }
 
 pub struct AudioProcessor {
@@ -23,10 +34,16 @@ pub struct AudioProcessor {
     fft_input_buffer: Vec<Complex<f32>>,
     fft_output_buffer: Vec<Complex<f32>>,
     sample_buffer: Vec<f32>,
+    // Same frames as sample_buffer, but with both channels
+    stereo_buffer: Vec<[f32; 2]>,
+    sample_rate: u32,
+    // Stream position (in frames) of sample_buffer[0]
+    buffer_start_frame: u64,
+    crossover: CrossoverBank,
 }
 
 impl AudioProcessor {
-    pub fn new(fft_size: usize) -> Self {
+    pub fn new(fft_size: usize, sample_rate: u32) -> Self {
         if !fft_size.is_power_of_two() {
             tracing::warn!(
                 "FFT size {} is not a power of two. This may impact performance.",
@@ -46,14 +63,39 @@ impl AudioProcessor {
             fft_input_buffer: vec![Complex::new(0.0, 0.0); fft_size],
             fft_output_buffer: vec![Complex::new(0.0, 0.0); fft_size],
             sample_buffer: Vec::with_capacity(fft_size * 2),
+            stereo_buffer: Vec::with_capacity(fft_size * 2),
+            sample_rate,
+            buffer_start_frame: 0,
+            crossover: CrossoverBank::new(sample_rate, &DEFAULT_CROSSOVER_FREQUENCIES),
         }
     }
 
-    // Processes incoming raw audio samples (mono assumed for now).
+    // Processes incoming raw interleaved audio samples.
+    // `first_frame` is the stream position of the first frame, used for timestamps.
     // Buffers samples until a full FFT window is available.
     // Returns analysis data if a full FFT window was processed.
-    pub fn process_samples(&mut self, new_samples: &[f32]) -> Option<AudioAnalysisData> {
-        self.sample_buffer.extend_from_slice(new_samples);
+    pub fn process_samples(
+        &mut self,
+        first_frame: u64,
+        new_samples: &[f32],
+        channels: usize,
+    ) -> Option<AudioAnalysisData> {
+        let buffered_until = self.buffer_start_frame + self.sample_buffer.len() as u64;
+        if first_frame != buffered_until {
+            // A chunk got dropped upstream, don't stitch non-adjacent audio into one window
+            self.sample_buffer.clear();
+            self.stereo_buffer.clear();
+            self.buffer_start_frame = first_frame;
+            self.crossover.reset();
+        }
+        // TODO: The spectrum still only looks at the first channel. Look into averaging
+        //   channels at some point, only the stereo views see both right now.
+        for frame in new_samples.chunks_exact(channels.max(1)) {
+            let left = frame[0];
+            let right = frame.get(1).copied().unwrap_or(left);
+            self.sample_buffer.push(left);
+            self.stereo_buffer.push([left, right]);
+        }
 
         if self.sample_buffer.len() >= self.fft_size {
             // We have enough samples for at least one FFT window
@@ -87,15 +129,32 @@ impl AudioProcessor {
                 .map(|c| c.norm() / self.fft_size as f32)
                 .collect();
 
+            let timestamp = self.buffer_start_frame as f64 / self.sample_rate as f64;
+
+            // Windows don't overlap, so every sample goes through the bank exactly once
+            let bands = self
+                .crossover
+                .process_block(&self.sample_buffer[..self.fft_size]);
+
+            let waveform = self.sample_buffer[..self.fft_size].to_vec();
+            let stereo = self.stereo_buffer[..self.fft_size].to_vec();
+
             // Remove processed samples from the buffer
             // drain is efficient enough for removing from the beginning
             self.sample_buffer.drain(0..self.fft_size);
+            self.stereo_buffer.drain(0..self.fft_size);
+            self.buffer_start_frame += self.fft_size as u64;
 
             Some(AudioAnalysisData {
                 rms_amplitude,
                 peak_amplitude,
                 frequency_magnitudes,
+                waveform,
+                stereo,
                 fft_size: self.fft_size,
+                sample_rate: self.sample_rate,
+                timestamp,
+                bands,
             })
         } else {
             // Not enough samples yet
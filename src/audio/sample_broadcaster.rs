@@ -1,10 +1,19 @@
 use rodio::Source;
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
 use std::time::Duration;
 
 // TODO: `std::sync::mpsc` is not the most performant option, but for now it works.
 // Can look at using `crossbeam-channel` (or `flume`?) in the future.
 
+// A run of interleaved samples, tagged with where it sits in the played stream.
+// The position lets the processing thread notice dropped chunks and keep timestamps honest.
+pub struct SampleChunk {
+    // Index of the first sample in the broadcast stream (interleaved samples, not frames)
+    pub start_sample: u64,
+    pub samples: Vec<f32>,
+}
+
 // Wrap audio source and send clones of sample chunks through a channel.
 #[allow(dead_code)]
 pub struct SampleBroadcaster<S>
@@ -13,8 +22,13 @@ where
 {
     source: S,
     // Bounded channel is essential to prevent unbounded memory use if receiver lags
-    sample_chunk_sender: mpsc::SyncSender<Vec<f32>>,
+    sample_chunk_sender: mpsc::SyncSender<SampleChunk>,
     buffer: Vec<f32>,
+    // Stream position of buffer[0]
+    buffer_start_sample: u64,
+    // Optional on/off switch, chunks only go out while the flag equals the second value.
+    // Lets a pre-FX and a post-FX broadcaster share one channel with only one of them sending.
+    tap_gate: Option<(Arc<AtomicBool>, bool)>,
     // TODO: Remove this if we're not going to do something with it elsewhere
     // Store sample rate for context if needed elsewhere
     sample_rate: u32,
@@ -25,8 +39,8 @@ where
     S: Source<Item = f32> + Send + 'static,
 {
     pub fn new(
-        source: S,                                       // Audio source (must yield f32 samples)
-        sample_chunk_sender: mpsc::SyncSender<Vec<f32>>, // Bounded sender for sending chunks of samples
+        source: S,                                          // Audio source (must yield f32 samples)
+        sample_chunk_sender: mpsc::SyncSender<SampleChunk>, // Bounded sender for sending chunks of samples
         buffer_capacity: usize, // The size of chunks to send (e.g., FFT size)
     ) -> Self {
         let sample_rate = source.sample_rate();
@@ -35,9 +49,34 @@ where
             source,
             sample_chunk_sender,
             buffer: Vec::with_capacity(buffer_capacity),
+            buffer_start_sample: 0,
+            tap_gate: None,
             sample_rate,
         }
     }
+
+    // Only send while `flag` equals `send_when`. Positions keep counting either way.
+    pub fn with_tap_gate(mut self, flag: Arc<AtomicBool>, send_when: bool) -> Self {
+        self.tap_gate = Some((flag, send_when));
+        self
+    }
+
+    fn tap_open(&self) -> bool {
+        self.tap_gate
+            .as_ref()
+            .is_none_or(|(flag, send_when)| flag.load(Ordering::Relaxed) == *send_when)
+    }
+
+    // Packs the current buffer into a chunk and moves the stream position past it
+    fn take_chunk(&mut self) -> SampleChunk {
+        let chunk = SampleChunk {
+            start_sample: self.buffer_start_sample,
+            samples: self.buffer.clone(),
+        };
+        self.buffer_start_sample += self.buffer.len() as u64;
+        self.buffer.clear();
+        chunk
+    }
 }
 
 // Required for use with rodio::Sink
@@ -78,10 +117,14 @@ where
                 self.buffer.push(sample);
 
                 // If the buffer is full, send a clone to the processing thread
-                if self.buffer.len() == self.buffer.capacity() {
+                if self.buffer.len() == self.buffer.capacity() && !self.tap_open() {
+                    // Someone else is the active analysis tap, just keep the position moving
+                    self.take_chunk();
+                } else if self.buffer.len() == self.buffer.capacity() {
                     // Use try_send for non-blocking behavior.
                     // Drop chunks when / if the processing thread bogs down
-                    match self.sample_chunk_sender.try_send(self.buffer.clone()) {
+                    let chunk = self.take_chunk();
+                    match self.sample_chunk_sender.try_send(chunk) {
                         Ok(_) => {}
                         Err(mpsc::TrySendError::Full(_)) => {
                             tracing::trace!(
@@ -94,8 +137,6 @@ where
                             tracing::error!("Sample chunk channel disconnected.");
                         }
                     }
-                    // Reset buffer for the next chunk
-                    self.buffer.clear();
                 }
 
                 // Return the original sample for `rodio` playback
@@ -104,15 +145,12 @@ where
             None => {
                 // We've reached the end of the audio file.
                 // Send remaining samples in the buffer if any.
-                if !self.buffer.is_empty() {
+                if !self.buffer.is_empty() && self.tap_open() {
                     // Blocking send here is to ensure the last partial chunk is processed.
-                    match self.sample_chunk_sender.send(self.buffer.clone()) {
-                        Ok(_) => {}
-                        Err(e) => tracing::error!("Failed to send final sample chunk: {}", e),
+                    let chunk = self.take_chunk();
+                    if self.sample_chunk_sender.send(chunk).is_err() {
+                        tracing::error!("Failed to send final sample chunk: channel disconnected");
                     }
-
-                    // Reset buffer, we're done
-                    self.buffer.clear();
                 }
 
                 // Send end signal to `rodio`
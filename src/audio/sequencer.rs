@@ -0,0 +1,445 @@
+use crate::audio::transport::{CachedReader, TransportHandle};
+use parking_lot::Mutex;
+use rodio::source::UniformSourceIterator;
+use rodio::Source;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Every track gets converted to this format so the sink, the broadcaster and the
+// analysis thread never see a format change when tracks switch.
+pub const OUTPUT_SAMPLE_RATE: u32 = 44_100;
+pub const OUTPUT_CHANNELS: u16 = 2;
+
+// How often (in samples) the sequencer looks at the shared state for pending changes.
+// Must stay a multiple of OUTPUT_CHANNELS so track cuts land on frame boundaries.
+const CONTROL_INTERVAL: usize = 512;
+
+pub type TrackSource = Box<dyn Source<Item = f32> + Send>;
+
+// A decoded file, already converted to the sequencer output format
+pub struct Track {
+    path: String,
+    source: CachedReader<UniformSourceIterator<TrackSource, f32>>,
+    // Remembered A-B loop and first cue (in frames), handed to the transport once the track
+    // becomes current
+    loop_region: Option<(u64, u64)>,
+    earliest_cue: Option<u64>,
+}
+
+impl Track {
+    pub fn new(
+        path: String,
+        source: TrackSource,
+        loop_region: Option<(u64, u64)>,
+        earliest_cue: Option<u64>,
+    ) -> Self {
+        let uniform = UniformSourceIterator::new(source, OUTPUT_CHANNELS, OUTPUT_SAMPLE_RATE);
+        Track {
+            path,
+            source: CachedReader::new(uniform),
+            loop_region,
+            earliest_cue,
+        }
+    }
+}
+
+#[derive(Default)]
+struct SequencerState {
+    // Track that should replace the current one as soon as possible
+    cut_to: Option<Track>,
+    // Tracks that play after the current one runs out
+    queue: VecDeque<Track>,
+    crossfade_samples: usize,
+    now_playing: Option<String>,
+}
+
+// Control side of a `TrackSequencer`, owned by `AudioManager`.
+// The sequencer itself lives inside the rodio sink, so everything goes through shared state.
+#[derive(Clone)]
+pub struct SequencerHandle {
+    state: Arc<Mutex<SequencerState>>,
+}
+
+impl SequencerHandle {
+    // Switches to `track` right away, crossfading if a crossfade length is set
+    pub fn play_now(&self, track: Track) {
+        let mut state = self.state.lock();
+        state.now_playing = Some(track.path.clone());
+        state.cut_to = Some(track);
+    }
+
+    pub fn enqueue(&self, track: Track) {
+        self.state.lock().queue.push_back(track);
+    }
+
+    pub fn clear_queue(&self) {
+        self.state.lock().queue.clear();
+    }
+
+    pub fn queued_paths(&self) -> Vec<String> {
+        let state = self.state.lock();
+        state.queue.iter().map(|t| t.path.clone()).collect()
+    }
+
+    pub fn set_crossfade(&self, crossfade: Duration) {
+        self.state.lock().crossfade_samples = crossfade_to_samples(crossfade);
+    }
+
+    // Path of the track the sequencer is currently reading from
+    pub fn now_playing(&self) -> Option<String> {
+        self.state.lock().now_playing.clone()
+    }
+}
+
+// A track on its way out, mixed under the incoming one with a falling gain
+struct FadeOut {
+    samples: Box<dyn Iterator<Item = f32> + Send>,
+    remaining: usize,
+    length: usize,
+}
+
+impl FadeOut {
+    // Next sample along with its gain, None once the fade or the samples are over
+    fn next(&mut self) -> Option<(f32, f32)> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let Some(sample) = self.samples.next() else {
+            self.remaining = 0;
+            return None;
+        };
+        let progress = 1.0 - self.remaining as f32 / self.length as f32;
+        self.remaining -= 1;
+        Some((sample, equal_power_out(progress)))
+    }
+}
+
+// A crossfade that got cut off by another track change, mixed down the way it was playing.
+// The new fade then takes over from exactly what was audible instead of dropping the
+// older track mid-fade.
+struct InterruptedFade {
+    fade: FadeOut,
+    incoming: Box<dyn Iterator<Item = f32> + Send>,
+}
+
+impl Iterator for InterruptedFade {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let incoming = self.incoming.next();
+        match self.fade.next() {
+            Some((out_sample, out_gain)) => Some(mix(incoming, out_sample, out_gain)),
+            None => incoming,
+        }
+    }
+}
+
+// Endless-ish source that plays tracks back to back without a gap, optionally crossfading.
+// The stream only ends once the current track is done and nothing is queued.
+pub struct TrackSequencer {
+    state: Arc<Mutex<SequencerState>>,
+    transport: TransportHandle,
+    current: Option<Track>,
+    fade_out: Option<FadeOut>,
+    // Read-ahead of the current track, used to find its tail before it actually ends
+    lookahead: VecDeque<f32>,
+    samples_until_control: usize,
+    // Cached copies of the shared state, refreshed every CONTROL_INTERVAL samples
+    crossfade_samples: usize,
+    has_queued: bool,
+}
+
+impl TrackSequencer {
+    // `transport` follows whichever track is current, it gets reset on every track change
+    pub fn new(
+        first_track: Track,
+        crossfade: Duration,
+        transport: TransportHandle,
+    ) -> (Self, SequencerHandle) {
+        transport.reset_for_new_track(first_track.loop_region, first_track.earliest_cue);
+        let state = Arc::new(Mutex::new(SequencerState {
+            crossfade_samples: crossfade_to_samples(crossfade),
+            now_playing: Some(first_track.path.clone()),
+            ..Default::default()
+        }));
+        let sequencer = TrackSequencer {
+            state: state.clone(),
+            transport,
+            current: Some(first_track),
+            fade_out: None,
+            lookahead: VecDeque::new(),
+            samples_until_control: 0,
+            crossfade_samples: 0,
+            has_queued: false,
+        };
+
+        (sequencer, SequencerHandle { state })
+    }
+
+    fn poll_control(&mut self) {
+        let mut state = self.state.lock();
+        self.crossfade_samples = state.crossfade_samples;
+        self.has_queued = !state.queue.is_empty();
+
+        if let Some(track) = state.cut_to.take() {
+            drop(state);
+            self.cut_to(track);
+        } else {
+            drop(state);
+        }
+
+        if let Some(frame) = self.transport.take_seek_request() {
+            if let Some(track) = self.current.as_mut() {
+                track.source.seek_frame(frame);
+                // Anything read ahead belongs to the old position
+                self.lookahead.clear();
+            }
+        }
+    }
+
+    // Replaces the current track, either instantly or by fading the old one out
+    fn cut_to(&mut self, track: Track) {
+        self.transport
+            .reset_for_new_track(track.loop_region, track.earliest_cue);
+        let previous = self.current.replace(track);
+        let tail: Vec<f32> = self.lookahead.drain(..).collect();
+
+        match previous {
+            Some(previous) if self.crossfade_samples > 0 => {
+                // Whatever was read ahead still has to come out first
+                let samples = tail.into_iter().chain(previous.source);
+                self.start_fade_out(Box::new(samples), self.crossfade_samples);
+            }
+            _ => {}
+        }
+    }
+
+    fn start_fade_out(&mut self, samples: Box<dyn Iterator<Item = f32> + Send>, length: usize) {
+        // Keep fades frame aligned so both channels get the same gain curve
+        let length = length - length % OUTPUT_CHANNELS as usize;
+        if length == 0 {
+            return;
+        }
+        // A fade that's still running goes out along with the track it was fading into
+        let samples = match self.fade_out.take() {
+            Some(fade) => Box::new(InterruptedFade {
+                fade,
+                incoming: samples,
+            }),
+            None => samples,
+        };
+        self.fade_out = Some(FadeOut {
+            samples,
+            remaining: length,
+            length,
+        });
+    }
+
+    // Pulls the next queued track in, if any. Returns false when the queue is empty.
+    fn advance_queue(&mut self) -> bool {
+        let mut state = self.state.lock();
+        match state.queue.pop_front() {
+            Some(track) => {
+                tracing::info!("Sequencer advancing to queued track: {}", track.path);
+                state.now_playing = Some(track.path.clone());
+                self.has_queued = !state.queue.is_empty();
+                self.transport
+                    .reset_for_new_track(track.loop_region, track.earliest_cue);
+                self.current = Some(track);
+                true
+            }
+            None => {
+                self.has_queued = false;
+                false
+            }
+        }
+    }
+
+    // Next sample of the incoming / current track, handling gapless advance and
+    // the read-ahead needed to crossfade into a queued track.
+    fn next_incoming(&mut self) -> Option<f32> {
+        loop {
+            let Some(track) = self.current.as_mut() else {
+                if self.advance_queue() {
+                    continue;
+                }
+                return None;
+            };
+
+            if self.crossfade_samples > 0 && self.has_queued {
+                while self.lookahead.len() < self.crossfade_samples {
+                    match track.source.next_with_transport(&self.transport) {
+                        Some(sample) => self.lookahead.push_back(sample),
+                        None => break,
+                    }
+                }
+
+                if !self
+                    .lookahead
+                    .len()
+                    .is_multiple_of(OUTPUT_CHANNELS as usize)
+                {
+                    // Finish the frame in progress so the tail starts on a frame boundary
+                    return self.lookahead.pop_front();
+                }
+                if self.lookahead.len() < self.crossfade_samples {
+                    // The track ends inside the crossfade window, so the buffered tail
+                    // fades out underneath the next queued track.
+                    let tail: Vec<f32> = self.lookahead.drain(..).collect();
+                    let tail_len = tail.len();
+                    self.current = None;
+                    if self.advance_queue() {
+                        self.start_fade_out(Box::new(tail.into_iter()), tail_len);
+                        continue;
+                    }
+                    // Queue got cleared in the meantime, just play the tail out
+                    self.lookahead.extend(tail);
+                    return self.lookahead.pop_front();
+                }
+            }
+
+            // Leftover read-ahead (crossfade turned off or queue cleared) comes first
+            if let Some(sample) = self.lookahead.pop_front() {
+                return Some(sample);
+            }
+
+            match track.source.next_with_transport(&self.transport) {
+                Some(sample) => return Some(sample),
+                None => {
+                    // Gapless: the next queued track (if any) starts on the very next sample
+                    self.current = None;
+                }
+            }
+        }
+    }
+
+    // Next sample of the outgoing track along with its gain, if a fade is running
+    fn next_outgoing(&mut self) -> Option<(f32, f32)> {
+        let fade = self.fade_out.as_mut()?;
+        let next = fade.next();
+        if fade.remaining == 0 {
+            self.fade_out = None;
+        }
+        next
+    }
+}
+
+impl Iterator for TrackSequencer {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.samples_until_control == 0 {
+            self.poll_control();
+            self.samples_until_control = CONTROL_INTERVAL;
+        }
+        self.samples_until_control -= 1;
+
+        let outgoing = self.next_outgoing();
+        let incoming = self.next_incoming();
+        self.transport
+            .set_read_ahead((self.lookahead.len() / OUTPUT_CHANNELS as usize) as u64);
+
+        match (incoming, outgoing) {
+            (None, None) => None,
+            (Some(sample), None) => Some(sample),
+            (incoming, Some((out_sample, out_gain))) => Some(mix(incoming, out_sample, out_gain)),
+        }
+    }
+}
+
+impl Source for TrackSequencer {
+    // Format never changes, so the whole stream is a single frame
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        OUTPUT_CHANNELS
+    }
+
+    fn sample_rate(&self) -> u32 {
+        OUTPUT_SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+fn crossfade_to_samples(crossfade: Duration) -> usize {
+    let frames = (crossfade.as_secs_f32() * OUTPUT_SAMPLE_RATE as f32) as usize;
+    frames * OUTPUT_CHANNELS as usize
+}
+
+// Equal-power crossfade, the incoming gain mirrors the outgoing one
+fn mix(incoming: Option<f32>, out_sample: f32, out_gain: f32) -> f32 {
+    let in_gain = (1.0 - out_gain * out_gain).max(0.0).sqrt();
+    incoming.unwrap_or(0.0) * in_gain + out_sample * out_gain
+}
+
+// cos curve, so out^2 + in^2 stays at 1 through the fade
+fn equal_power_out(progress: f32) -> f32 {
+    (progress.clamp(0.0, 1.0) * std::f32::consts::FRAC_PI_2).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    // A track holding `value` on both channels for `seconds`
+    fn track(name: &str, value: f32, seconds: f32) -> Track {
+        let samples = (seconds * OUTPUT_SAMPLE_RATE as f32) as usize * OUTPUT_CHANNELS as usize;
+        let source = SamplesBuffer::new(OUTPUT_CHANNELS, OUTPUT_SAMPLE_RATE, vec![value; samples]);
+        Track::new(name.into(), Box::new(source), None, None)
+    }
+
+    fn sequencer(first: Track, crossfade: Duration) -> (TrackSequencer, SequencerHandle) {
+        TrackSequencer::new(first, crossfade, TransportHandle::new())
+    }
+
+    #[test]
+    fn queued_tracks_play_back_to_back() {
+        let (sequencer, handle) = sequencer(track("a", 0.25, 0.1), Duration::ZERO);
+        handle.enqueue(track("b", 0.5, 0.1));
+        let samples: Vec<f32> = sequencer.collect();
+        let half = samples.len() / 2;
+        assert_eq!(half, (0.1 * OUTPUT_SAMPLE_RATE as f32) as usize * 2);
+        assert!(samples[..half].iter().all(|&s| s == 0.25));
+        assert!(samples[half..].iter().all(|&s| s == 0.5));
+    }
+
+    #[test]
+    fn play_now_crossfades_into_the_new_track() {
+        let (mut sequencer, handle) = sequencer(track("a", 1.0, 2.0), Duration::from_millis(100));
+        sequencer.by_ref().take(4096).for_each(drop);
+        handle.play_now(track("b", 0.5, 1.0));
+        let samples: Vec<f32> = sequencer.collect();
+        assert_eq!(samples[0], 1.0, "the fade starts at the old track's level");
+        assert!(samples.iter().all(|&s| s <= std::f32::consts::SQRT_2));
+        assert_eq!(*samples.last().unwrap(), 0.5);
+        assert_eq!(handle.now_playing().as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn cut_in_the_middle_of_a_crossfade_finishes_the_old_fade() {
+        // a fading out under a silent b, then another cut to a silent c halfway through.
+        // Dropping a's fade at the second cut would jump from about 0.7 straight to 0.
+        let crossfade = Duration::from_millis(100);
+        let (mut sequencer, handle) = sequencer(track("a", 1.0, 2.0), crossfade);
+        sequencer.by_ref().take(CONTROL_INTERVAL).for_each(drop);
+        handle.play_now(track("b", 0.0, 2.0));
+        let mut samples: Vec<f32> = sequencer.by_ref().take(4096).collect();
+        handle.play_now(track("c", 0.0, 2.0));
+        samples.extend(sequencer.by_ref().take(crossfade_to_samples(crossfade) * 2));
+
+        let largest_step = samples
+            .chunks_exact(OUTPUT_CHANNELS as usize)
+            .zip(samples.chunks_exact(OUTPUT_CHANNELS as usize).skip(1))
+            .map(|(a, b)| (a[0] - b[0]).abs())
+            .fold(0.0, f32::max);
+        assert!(largest_step < 0.01, "output jumped by {largest_step}");
+        assert_eq!(*samples.last().unwrap(), 0.0);
+    }
+}
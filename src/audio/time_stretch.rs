@@ -0,0 +1,440 @@
+use rodio::Source;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const MIN_PLAYBACK_RATE: f32 = 0.25;
+pub const MAX_PLAYBACK_RATE: f32 = 2.0;
+
+// WSOLA parameters, in frames at the sequencer output rate (~23ms windows, 50% overlap)
+const WSOLA_WINDOW: usize = 1024;
+const WSOLA_HOP: usize = WSOLA_WINDOW / 2;
+// How far (either way) a segment may move from its nominal position to line up with the last one
+const WSOLA_TOLERANCE: usize = 256;
+// Only every Nth frame goes into the similarity measure, plenty for lining up waveforms
+const WSOLA_CORRELATION_STEP: usize = 4;
+// Output frames produced per resampler step
+const RESAMPLE_BLOCK: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StretchMode {
+    // Plain resampling, pitch follows the playback rate (tape style)
+    Resample,
+    // WSOLA time stretching, pitch is preserved
+    TimeStretch,
+}
+
+impl StretchMode {
+    pub const ALL: [StretchMode; 2] = [StretchMode::Resample, StretchMode::TimeStretch];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            StretchMode::Resample => "Resample (pitch follows)",
+            StretchMode::TimeStretch => "Time Stretch (keep pitch)",
+        }
+    }
+}
+
+// Rate + mode shared between `AudioManager` and the stretcher running inside the sink.
+// Plain atomics, the audio thread reads them once per processing step.
+#[derive(Clone)]
+pub struct PlaybackRateHandle {
+    rate_bits: Arc<AtomicU32>,
+    mode: Arc<AtomicU8>,
+    // Input frames the stretcher holds between its source and what it hands out,
+    // published after every processing step
+    latency_frames: Arc<AtomicU32>,
+}
+
+impl PlaybackRateHandle {
+    pub fn new() -> Self {
+        PlaybackRateHandle {
+            rate_bits: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            mode: Arc::new(AtomicU8::new(StretchMode::Resample as u8)),
+            latency_frames: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    pub fn set_rate(&self, rate: f32) {
+        let rate = rate.clamp(MIN_PLAYBACK_RATE, MAX_PLAYBACK_RATE);
+        self.rate_bits.store(rate.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn rate(&self) -> f32 {
+        f32::from_bits(self.rate_bits.load(Ordering::Relaxed))
+    }
+
+    pub fn set_mode(&self, mode: StretchMode) {
+        self.mode.store(mode as u8, Ordering::Relaxed);
+    }
+
+    pub fn mode(&self) -> StretchMode {
+        match self.mode.load(Ordering::Relaxed) {
+            0 => StretchMode::Resample,
+            _ => StretchMode::TimeStretch,
+        }
+    }
+
+    pub fn latency_frames(&self) -> u32 {
+        self.latency_frames.load(Ordering::Relaxed)
+    }
+}
+
+// Changes the playback rate of a stereo source, either by resampling or by WSOLA.
+// Sits in front of the SampleBroadcaster, so the analysis sees exactly what is heard.
+pub struct TimeStretcher<S>
+where
+    S: Source<Item = f32> + Send + 'static,
+{
+    source: S,
+    source_done: bool,
+    control: PlaybackRateHandle,
+    active_mode: StretchMode,
+    // Input frames not consumed yet. All positions below are relative to input[0].
+    input: VecDeque<[f32; 2]>,
+    // Interleaved output ready to be handed out
+    output: VecDeque<f32>,
+    // Resampler read head
+    read_pos: f64,
+    // WSOLA state
+    window: Vec<f32>,
+    overlap_add: Vec<[f32; 2]>,
+    analysis_pos: f64,
+    // Where the previously placed segment would naturally carry on
+    natural_continuation: Option<usize>,
+}
+
+impl<S> TimeStretcher<S>
+where
+    S: Source<Item = f32> + Send + 'static,
+{
+    pub fn new(source: S, control: PlaybackRateHandle) -> Self {
+        if source.channels() != 2 {
+            tracing::warn!(
+                "TimeStretcher expects stereo input, got {} channels.",
+                source.channels()
+            );
+        }
+        let active_mode = control.mode();
+
+        TimeStretcher {
+            source,
+            source_done: false,
+            control,
+            active_mode,
+            input: VecDeque::with_capacity(WSOLA_WINDOW * 4),
+            output: VecDeque::with_capacity(WSOLA_HOP * 2),
+            read_pos: 0.0,
+            window: periodic_hann(WSOLA_WINDOW),
+            overlap_add: vec![[0.0; 2]; WSOLA_WINDOW],
+            analysis_pos: 0.0,
+            natural_continuation: None,
+        }
+    }
+
+    // Makes sure `input` holds at least `frames` frames, unless the source runs out
+    fn fill_input(&mut self, frames: usize) {
+        while self.input.len() < frames && !self.source_done {
+            match (self.source.next(), self.source.next()) {
+                (Some(left), Some(right)) => self.input.push_back([left, right]),
+                (Some(left), None) => {
+                    self.input.push_back([left, left]);
+                    self.source_done = true;
+                }
+                _ => self.source_done = true,
+            }
+        }
+    }
+
+    fn frame_at(&self, index: usize) -> [f32; 2] {
+        self.input.get(index).copied().unwrap_or([0.0; 2])
+    }
+
+    // Drops consumed frames from the front and shifts the relative positions along
+    fn discard_input(&mut self, frames: usize) {
+        let frames = frames.min(self.input.len());
+        self.input.drain(..frames);
+        self.read_pos -= frames as f64;
+        self.analysis_pos -= frames as f64;
+        if let Some(natural) = self.natural_continuation.as_mut() {
+            *natural -= frames;
+        }
+    }
+
+    // Switching algorithms mid-stream: carry on from where the output currently is
+    fn switch_mode(&mut self, mode: StretchMode) {
+        match mode {
+            StretchMode::Resample => {
+                // Cleared, otherwise discard_input would keep shifting it and run it below zero
+                self.read_pos = self
+                    .natural_continuation
+                    .take()
+                    .map_or(self.analysis_pos, |n| n as f64);
+            }
+            StretchMode::TimeStretch => {
+                self.analysis_pos = self.read_pos.floor();
+                self.natural_continuation = None;
+            }
+        }
+        self.overlap_add.iter_mut().for_each(|f| *f = [0.0; 2]);
+        self.active_mode = mode;
+    }
+
+    // Linear interpolation resampler, pitch moves with the rate
+    fn resample_step(&mut self, rate: f64) -> bool {
+        let needed = self.read_pos as usize + RESAMPLE_BLOCK * rate.ceil() as usize + 2;
+        self.fill_input(needed);
+
+        for _ in 0..RESAMPLE_BLOCK {
+            let index = self.read_pos.floor() as usize;
+            if self.source_done && index >= self.input.len() {
+                break;
+            }
+            let frac = (self.read_pos - index as f64) as f32;
+            let a = self.frame_at(index);
+            let b = self.frame_at(index + 1);
+            self.output.push_back(a[0] + (b[0] - a[0]) * frac);
+            self.output.push_back(a[1] + (b[1] - a[1]) * frac);
+            self.read_pos += rate;
+        }
+
+        self.discard_input(self.read_pos.floor() as usize);
+        !self.output.is_empty()
+    }
+
+    // One WSOLA hop: pick the segment near the nominal analysis position that best
+    // continues the previous one, overlap-add it and emit WSOLA_HOP frames.
+    fn wsola_step(&mut self, rate: f64) -> bool {
+        let nominal = self.analysis_pos.max(0.0) as usize;
+        let natural = self.natural_continuation;
+        self.fill_input(nominal.max(natural.unwrap_or(0)) + WSOLA_TOLERANCE + WSOLA_WINDOW);
+
+        if self.source_done && nominal >= self.input.len() {
+            // Everything has been played, flush what is left in the overlap buffer
+            if self.natural_continuation.take().is_some() {
+                for frame in self.overlap_add.iter().take(WSOLA_HOP) {
+                    self.output.extend(frame);
+                }
+            }
+            return !self.output.is_empty();
+        }
+
+        let best = match natural {
+            Some(natural) => self.best_aligned_segment(nominal, natural),
+            None => nominal,
+        };
+
+        for i in 0..WSOLA_WINDOW {
+            let frame = self.frame_at(best + i);
+            let w = self.window[i];
+            self.overlap_add[i][0] += frame[0] * w;
+            self.overlap_add[i][1] += frame[1] * w;
+        }
+        for frame in self.overlap_add.iter().take(WSOLA_HOP) {
+            self.output.extend(frame);
+        }
+        self.overlap_add.rotate_left(WSOLA_HOP);
+        for frame in self.overlap_add.iter_mut().skip(WSOLA_WINDOW - WSOLA_HOP) {
+            *frame = [0.0; 2];
+        }
+
+        self.natural_continuation = Some(best + WSOLA_HOP);
+        self.analysis_pos += WSOLA_HOP as f64 * rate;
+
+        // Anything before both the next search range and the next natural continuation is done with
+        let keep_from = (self.analysis_pos as usize)
+            .saturating_sub(WSOLA_TOLERANCE)
+            .min(best + WSOLA_HOP);
+        self.discard_input(keep_from);
+        true
+    }
+
+    // Input frames from the front of the output queue up to the newest one read in. The queue
+    // drains between steps, so this is up to a block too high for most of the time.
+    fn buffered_frames(&self, rate: f64) -> f64 {
+        let head = match self.active_mode {
+            StretchMode::Resample => self.read_pos,
+            StretchMode::TimeStretch => self.analysis_pos,
+        };
+        let queued = (self.output.len() / 2) as f64 * rate;
+        (self.input.len() as f64 - head + queued).max(0.0)
+    }
+
+    // Searches nominal +/- tolerance for the segment most similar to the natural continuation
+    fn best_aligned_segment(&self, nominal: usize, natural: usize) -> usize {
+        let start = nominal.saturating_sub(WSOLA_TOLERANCE);
+        let end = nominal + WSOLA_TOLERANCE;
+        let overlap = WSOLA_WINDOW - WSOLA_HOP;
+
+        let template: Vec<f32> = (0..overlap)
+            .step_by(WSOLA_CORRELATION_STEP)
+            .map(|i| mono(self.frame_at(natural + i)))
+            .collect();
+
+        let mut best = nominal;
+        let mut best_score = f32::MIN;
+        for candidate in start..=end {
+            let mut cross = 0.0f32;
+            let mut energy = 1e-9f32;
+            for (k, i) in (0..overlap).step_by(WSOLA_CORRELATION_STEP).enumerate() {
+                let sample = mono(self.frame_at(candidate + i));
+                cross += template[k] * sample;
+                energy += sample * sample;
+            }
+            let score = cross / energy.sqrt();
+            if score > best_score {
+                best_score = score;
+                best = candidate;
+            }
+        }
+        best
+    }
+}
+
+impl<S> Iterator for TimeStretcher<S>
+where
+    S: Source<Item = f32> + Send + 'static,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(sample) = self.output.pop_front() {
+            return Some(sample);
+        }
+
+        // Rate and mode are only picked up between steps, which keeps frames intact
+        let mode = self.control.mode();
+        if mode != self.active_mode {
+            self.switch_mode(mode);
+        }
+        let rate = self.control.rate() as f64;
+
+        let produced = match self.active_mode {
+            StretchMode::Resample => self.resample_step(rate),
+            StretchMode::TimeStretch => self.wsola_step(rate),
+        };
+        self.control
+            .latency_frames
+            .store(self.buffered_frames(rate) as u32, Ordering::Relaxed);
+        if !produced {
+            return None;
+        }
+        self.output.pop_front()
+    }
+}
+
+impl<S> Source for TimeStretcher<S>
+where
+    S: Source<Item = f32> + Send + 'static,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    // Depends on the rate, which can change at any time
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+fn mono(frame: [f32; 2]) -> f32 {
+    (frame[0] + frame[1]) * 0.5
+}
+
+// Periodic Hann, so 50% overlapped windows sum to exactly one
+fn periodic_hann(size: usize) -> Vec<f32> {
+    (0..size)
+        .map(|i| 0.5 * (1.0 - (std::f32::consts::TAU * i as f32 / size as f32).cos()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    const SAMPLE_RATE: u32 = 44_100;
+
+    fn stereo_sine(frames: usize, frequency: f32) -> SamplesBuffer<f32> {
+        let samples: Vec<f32> = (0..frames)
+            .flat_map(|i| {
+                let value =
+                    (std::f32::consts::TAU * frequency * i as f32 / SAMPLE_RATE as f32).sin() * 0.5;
+                [value, value]
+            })
+            .collect();
+        SamplesBuffer::new(2, SAMPLE_RATE, samples)
+    }
+
+    // Runs the stretcher to the end, calling `on_sample` with each output sample index first
+    fn run(
+        frames: usize,
+        control: &PlaybackRateHandle,
+        mut on_sample: impl FnMut(usize),
+    ) -> Vec<f32> {
+        let mut stretcher = TimeStretcher::new(stereo_sine(frames, 440.0), control.clone());
+        let mut output = Vec::new();
+        loop {
+            on_sample(output.len());
+            match stretcher.next() {
+                Some(sample) => output.push(sample),
+                None => break,
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn output_length_follows_rate_in_both_modes() {
+        let frames = SAMPLE_RATE as usize;
+        for mode in StretchMode::ALL {
+            for rate in [0.5, 1.0, 2.0] {
+                let control = PlaybackRateHandle::new();
+                control.set_mode(mode);
+                control.set_rate(rate);
+                let output = run(frames, &control, |_| {});
+                let expected = frames as f32 / rate;
+                let produced = (output.len() / 2) as f32;
+                assert!(
+                    (produced - expected).abs() < expected * 0.05 + WSOLA_WINDOW as f32,
+                    "{:?} at {}: {} frames, expected about {}",
+                    mode,
+                    rate,
+                    produced,
+                    expected
+                );
+                assert!(output.iter().all(|s| s.is_finite() && s.abs() <= 0.6));
+            }
+        }
+    }
+
+    #[test]
+    fn switching_modes_mid_stream_keeps_playing() {
+        let frames = SAMPLE_RATE as usize * 2;
+        let control = PlaybackRateHandle::new();
+        control.set_rate(0.8);
+        let output = run(frames, &control, |index| {
+            // Flip every ~50ms of output, including straight after a WSOLA hop
+            if index % 4410 == 0 {
+                let next = match control.mode() {
+                    StretchMode::Resample => StretchMode::TimeStretch,
+                    StretchMode::TimeStretch => StretchMode::Resample,
+                };
+                control.set_mode(next);
+            }
+        });
+        let produced = (output.len() / 2) as f32;
+        let expected = frames as f32 / 0.8;
+        assert!((produced - expected).abs() < expected * 0.05);
+        assert!(output.iter().all(|s| s.is_finite() && s.abs() <= 0.6));
+    }
+}
@@ -0,0 +1,111 @@
+use crate::audio::manager::DEFAULT_FFT_SIZE;
+use crate::audio::processor::{AudioAnalysisData, AudioProcessor};
+use crate::audio::sequencer::{OUTPUT_CHANNELS, OUTPUT_SAMPLE_RATE};
+use rodio::source::UniformSourceIterator;
+use rodio::{Decoder, Source};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+// A whole file decoded up front, in the same format the sequencer plays
+pub struct DecodedAudio {
+    // Interleaved, OUTPUT_CHANNELS per frame
+    pub samples: Vec<f32>,
+    pub channels: u16,
+    pub sample_rate: u32,
+}
+
+impl DecodedAudio {
+    pub fn open(file_path: &str) -> Result<Self, String> {
+        let path = Path::new(file_path);
+        let file = File::open(path)
+            .map_err(|e| format!("Failed to open file '{}': {}", path.display(), e))?;
+        let decoder = Decoder::new(BufReader::new(file))
+            .map_err(|e| format!("Failed to decode file '{}': {}", path.display(), e))?;
+        let samples: Vec<f32> = UniformSourceIterator::new(
+            decoder.convert_samples::<f32>(),
+            OUTPUT_CHANNELS,
+            OUTPUT_SAMPLE_RATE,
+        )
+        .collect();
+        tracing::info!(
+            "Decoded '{}' for offline use: {} frames",
+            path.display(),
+            samples.len() / OUTPUT_CHANNELS as usize
+        );
+        Ok(DecodedAudio {
+            samples,
+            channels: OUTPUT_CHANNELS,
+            sample_rate: OUTPUT_SAMPLE_RATE,
+        })
+    }
+
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    pub fn duration_seconds(&self) -> f64 {
+        self.frames() as f64 / self.sample_rate as f64
+    }
+
+    // Writes the first `duration` seconds as 16 bit PCM, what every encoder and editor takes
+    pub fn write_wav(&self, path: &Path, duration: f64) -> Result<(), String> {
+        let spec = hound::WavSpec {
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let write_error = |e: hound::Error| format!("Failed to write '{}': {}", path.display(), e);
+        let mut writer = hound::WavWriter::create(path, spec).map_err(write_error)?;
+        let frames = (duration.max(0.0) * self.sample_rate as f64).round() as usize;
+        let samples = (frames * self.channels as usize).min(self.samples.len());
+        for &sample in &self.samples[..samples] {
+            writer
+                .write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+                .map_err(write_error)?;
+        }
+        writer.finalize().map_err(write_error)
+    }
+}
+
+// The live analysis run over decoded audio, at whatever pace the caller steps it.
+// Effects aren't applied, the windows are what the dry track would give.
+pub struct AnalysisTimeline<'a> {
+    audio: &'a DecodedAudio,
+    processor: AudioProcessor,
+    // First frame that hasn't gone through the processor yet
+    next_frame: usize,
+    latest: Option<AudioAnalysisData>,
+}
+
+impl<'a> AnalysisTimeline<'a> {
+    pub fn new(audio: &'a DecodedAudio) -> Self {
+        AnalysisTimeline {
+            audio,
+            processor: AudioProcessor::new(DEFAULT_FFT_SIZE, audio.sample_rate),
+            next_frame: 0,
+            latest: None,
+        }
+    }
+
+    // Latest window that has been completely heard `seconds` into the track, which is
+    // what the live view would be showing at that point. Only moves forward.
+    pub fn advance_to(&mut self, seconds: f64) -> Option<&AudioAnalysisData> {
+        let channels = self.audio.channels.max(1) as usize;
+        let target =
+            ((seconds.max(0.0) * self.audio.sample_rate as f64) as usize).min(self.audio.frames());
+        while self.next_frame + DEFAULT_FFT_SIZE <= target {
+            let window = &self.audio.samples
+                [self.next_frame * channels..(self.next_frame + DEFAULT_FFT_SIZE) * channels];
+            if let Some(data) =
+                self.processor
+                    .process_samples(self.next_frame as u64, window, channels)
+            {
+                self.latest = Some(data);
+            }
+            self.next_frame += DEFAULT_FFT_SIZE;
+        }
+        self.latest.as_ref()
+    }
+}
@@ -0,0 +1,449 @@
+use rodio::Source;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Marker for "no value" in the atomics below
+const UNSET: u64 = u64::MAX;
+// Most audio a `CachedReader` keeps around, ~21MB of stereo f32 at 44.1kHz.
+// Longer loops still work, they just go through the decoder's seek when wrapping.
+const MAX_CACHED_SECONDS: usize = 60;
+
+// Position / loop / seek state of the track the sequencer is currently reading.
+// All positions are frames at the sequencer output rate, counted from the start of the track.
+#[derive(Clone)]
+pub struct TransportHandle {
+    inner: Arc<TransportState>,
+}
+
+struct TransportState {
+    position: AtomicU64,
+    loop_start: AtomicU64,
+    loop_end: AtomicU64,
+    loop_enabled: AtomicBool,
+    seek_request: AtomicU64,
+    // Earliest cue point of the track, where the reader starts caching unless A comes first
+    earliest_cue: AtomicU64,
+    // Frames the sequencer has read past what it has handed on (crossfade lookahead)
+    read_ahead: AtomicU64,
+}
+
+impl TransportHandle {
+    pub fn new() -> Self {
+        TransportHandle {
+            inner: Arc::new(TransportState {
+                position: AtomicU64::new(0),
+                loop_start: AtomicU64::new(UNSET),
+                loop_end: AtomicU64::new(UNSET),
+                loop_enabled: AtomicBool::new(false),
+                seek_request: AtomicU64::new(UNSET),
+                earliest_cue: AtomicU64::new(UNSET),
+                read_ahead: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    // Frame the decoder is at. Runs ahead of the speakers by everything buffered after it,
+    // see `heard_position_frames` for what is actually playing.
+    pub fn position_frames(&self) -> u64 {
+        self.inner.position.load(Ordering::Relaxed)
+    }
+
+    // Frame coming out of the pipeline: the decoder position minus the sequencer's read-ahead
+    // and `downstream_latency` (frames buffered by the stages after the sequencer).
+    // Just after a loop wrap the buffers still hold the end of the previous pass.
+    // The device buffer inside rodio isn't known and isn't taken off, it's a few ms.
+    pub fn heard_position_frames(&self, downstream_latency: u64) -> u64 {
+        let latency = self.inner.read_ahead.load(Ordering::Relaxed) + downstream_latency;
+        let position = self.position_frames();
+        match self.active_loop() {
+            Some((start, end))
+                if (start..end).contains(&position) && position - start < latency =>
+            {
+                end - (latency - (position - start)).min(end - start)
+            }
+            _ => position.saturating_sub(latency),
+        }
+    }
+
+    pub(super) fn set_read_ahead(&self, frames: u64) {
+        self.inner.read_ahead.store(frames, Ordering::Relaxed);
+    }
+
+    pub fn set_loop_start(&self, frame: u64) {
+        self.inner.loop_start.store(frame, Ordering::Relaxed);
+        self.order_loop_points();
+    }
+
+    pub fn set_loop_end(&self, frame: u64) {
+        self.inner.loop_end.store(frame, Ordering::Relaxed);
+        self.order_loop_points();
+    }
+
+    pub fn set_loop_region(&self, start_frame: u64, end_frame: u64) {
+        self.inner.loop_start.store(start_frame, Ordering::Relaxed);
+        self.inner.loop_end.store(end_frame, Ordering::Relaxed);
+        self.order_loop_points();
+    }
+
+    // B set before A is fine, the two just get swapped
+    fn order_loop_points(&self) {
+        if let Some((start, end)) = self.loop_region() {
+            if end < start {
+                self.inner.loop_start.store(end, Ordering::Relaxed);
+                self.inner.loop_end.store(start, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn clear_loop_region(&self) {
+        self.inner.loop_enabled.store(false, Ordering::Relaxed);
+        self.inner.loop_start.store(UNSET, Ordering::Relaxed);
+        self.inner.loop_end.store(UNSET, Ordering::Relaxed);
+    }
+
+    // Start / end as set so far, each may be missing
+    pub fn loop_points(&self) -> (Option<u64>, Option<u64>) {
+        let start = self.inner.loop_start.load(Ordering::Relaxed);
+        let end = self.inner.loop_end.load(Ordering::Relaxed);
+        (
+            (start != UNSET).then_some(start),
+            (end != UNSET).then_some(end),
+        )
+    }
+
+    pub fn loop_region(&self) -> Option<(u64, u64)> {
+        let start = self.inner.loop_start.load(Ordering::Relaxed);
+        let end = self.inner.loop_end.load(Ordering::Relaxed);
+        if start == UNSET || end == UNSET {
+            None
+        } else {
+            Some((start, end))
+        }
+    }
+
+    pub fn set_loop_enabled(&self, enabled: bool) {
+        self.inner.loop_enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn loop_enabled(&self) -> bool {
+        self.inner.loop_enabled.load(Ordering::Relaxed)
+    }
+
+    // Picked up by the sequencer on its next control poll
+    pub fn request_seek(&self, frame: u64) {
+        self.inner.seek_request.store(frame, Ordering::Relaxed);
+    }
+
+    pub(super) fn take_seek_request(&self) -> Option<u64> {
+        match self.inner.seek_request.swap(UNSET, Ordering::Relaxed) {
+            UNSET => None,
+            frame => Some(frame),
+        }
+    }
+
+    pub fn set_earliest_cue(&self, frame: Option<u64>) {
+        self.inner
+            .earliest_cue
+            .store(frame.unwrap_or(UNSET), Ordering::Relaxed);
+    }
+
+    // A different track took over, old positions and markers don't apply to it.
+    // The new track may bring its own (remembered) loop region, looping stays off either way.
+    pub(super) fn reset_for_new_track(
+        &self,
+        loop_region: Option<(u64, u64)>,
+        earliest_cue: Option<u64>,
+    ) {
+        self.inner.position.store(0, Ordering::Relaxed);
+        self.inner.read_ahead.store(0, Ordering::Relaxed);
+        self.inner.seek_request.store(UNSET, Ordering::Relaxed);
+        self.clear_loop_region();
+        if let Some((start, end)) = loop_region {
+            self.set_loop_region(start, end);
+        }
+        self.set_earliest_cue(earliest_cue);
+    }
+
+    fn store_position(&self, frame: u64) {
+        self.inner.position.store(frame, Ordering::Relaxed);
+    }
+
+    // Active loop end / start, only when looping is switched on and both points are set
+    fn active_loop(&self) -> Option<(u64, u64)> {
+        if !self.loop_enabled() {
+            return None;
+        }
+        self.loop_region().filter(|(start, end)| end > start)
+    }
+
+    // First frame anything may jump back to, loop point A or the earliest cue
+    fn cache_anchor(&self) -> Option<u64> {
+        let start = self.inner.loop_start.load(Ordering::Relaxed);
+        let cue = self.inner.earliest_cue.load(Ordering::Relaxed);
+        Some(start.min(cue)).filter(|&frame| frame != UNSET)
+    }
+}
+
+// Wraps a decoded track and keeps up to MAX_CACHED_SECONDS of it in memory, starting at the
+// transport's cache anchor (loop point A or the earliest cue). Loops and cue jumps landing in
+// that stretch are sample accurate and free, anything else goes through the decoder's seek.
+pub struct CachedReader<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    source_done: bool,
+    channels: usize,
+    // All indices below are absolute sample positions in the track
+    // Next sample the decoder will produce
+    decoded: usize,
+    // Contiguous run of decoded samples starting at `cache_start`
+    cache: Vec<f32>,
+    cache_start: usize,
+    max_cached: usize,
+    // Next sample to hand out
+    position: usize,
+}
+
+impl<S> CachedReader<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(source: S) -> Self {
+        let channels = source.channels().max(1) as usize;
+        let max_cached = MAX_CACHED_SECONDS * source.sample_rate() as usize * channels;
+        CachedReader {
+            source,
+            source_done: false,
+            channels,
+            decoded: 0,
+            cache: Vec::new(),
+            cache_start: 0,
+            max_cached,
+            position: 0,
+        }
+    }
+
+    fn position_frames(&self) -> u64 {
+        (self.position / self.channels) as u64
+    }
+
+    // Jumps to `frame`. Only moves the read head, a decoder seek (if the cache can't serve
+    // the new position) happens on the next read.
+    pub fn seek_frame(&mut self, frame: u64) {
+        self.position = frame as usize * self.channels;
+    }
+
+    // Moves the start of the cache to `anchor`, keeping what's already cached past it
+    fn set_anchor(&mut self, anchor: Option<u64>) {
+        let Some(anchor) = anchor else {
+            self.cache.clear();
+            return;
+        };
+        let start = anchor as usize * self.channels;
+        if start == self.cache_start {
+            return;
+        }
+        if start > self.cache_start && start <= self.cache_start + self.cache.len() {
+            self.cache.drain(..start - self.cache_start);
+        } else {
+            // Nothing usable cached. The first jump back to the anchor seeks the decoder there
+            // once, and since it then reads on from the (empty) cache's end, that pass refills it.
+            self.cache.clear();
+        }
+        self.cache_start = start;
+    }
+
+    fn cached(&self, index: usize) -> Option<f32> {
+        index
+            .checked_sub(self.cache_start)
+            .and_then(|offset| self.cache.get(offset))
+            .copied()
+    }
+
+    // Gets the decoder to `position` for a read the cache can't serve
+    fn seek_decoder(&mut self) {
+        let frame = self.position / self.channels;
+        let time = Duration::from_secs_f64(frame as f64 / self.source.sample_rate() as f64);
+        match self.source.try_seek(time) {
+            Ok(()) => {
+                self.decoded = frame * self.channels;
+                self.position = self.decoded;
+                self.source_done = false;
+            }
+            Err(e) => {
+                tracing::warn!("Seek to {:?} failed, playing on instead: {}", time, e);
+                self.position = self.decoded;
+            }
+        }
+    }
+
+    // Next sample with the transport applied: loops back from B to A on a frame boundary
+    // and publishes the current position.
+    pub fn next_with_transport(&mut self, transport: &TransportHandle) -> Option<f32> {
+        if self.position.is_multiple_of(self.channels) {
+            self.set_anchor(transport.cache_anchor());
+            if let Some((start, end)) = transport.active_loop() {
+                if self.position_frames() >= end {
+                    self.seek_frame(start);
+                }
+            }
+            transport.store_position(self.position_frames());
+        }
+        self.next()
+    }
+}
+
+impl<S> Iterator for CachedReader<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(sample) = self.cached(self.position) {
+            self.position += 1;
+            return Some(sample);
+        }
+        if self.position != self.decoded {
+            self.seek_decoder();
+        }
+        if self.source_done {
+            return None;
+        }
+        let Some(sample) = self.source.next() else {
+            self.source_done = true;
+            return None;
+        };
+        // Only extends the cache while reading straight on from its end
+        if self.decoded == self.cache_start + self.cache.len() && self.cache.len() < self.max_cached
+        {
+            self.cache.push(sample);
+        }
+        self.decoded += 1;
+        self.position += 1;
+        Some(sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    const SAMPLE_RATE: u32 = 1_000;
+
+    // Stereo track whose samples are their own index, so positions can be read off the output
+    fn ramp(seconds: usize) -> CachedReader<SamplesBuffer<f32>> {
+        let samples = (0..seconds * SAMPLE_RATE as usize * 2)
+            .map(|i| i as f32)
+            .collect::<Vec<_>>();
+        CachedReader::new(SamplesBuffer::new(2, SAMPLE_RATE, samples))
+    }
+
+    fn next_frame(
+        reader: &mut CachedReader<SamplesBuffer<f32>>,
+        transport: &TransportHandle,
+    ) -> u64 {
+        let left = reader.next_with_transport(transport).unwrap();
+        let right = reader.next_with_transport(transport).unwrap();
+        assert_eq!(right, left + 1.0);
+        left as u64 / 2
+    }
+
+    #[test]
+    fn loops_wrap_sample_accurately_from_the_cache() {
+        let transport = TransportHandle::new();
+        transport.set_loop_region(1_000, 2_000);
+        transport.set_loop_enabled(true);
+        let mut reader = ramp(10);
+
+        let frames: Vec<u64> = (0..4_000)
+            .map(|_| next_frame(&mut reader, &transport))
+            .collect();
+        assert_eq!(&frames[..1_000], (0..1_000).collect::<Vec<_>>().as_slice());
+        for pass in frames[1_000..].chunks(1_000) {
+            assert_eq!(pass, (1_000..2_000).collect::<Vec<_>>().as_slice());
+        }
+        // Never decoded past B, every wrap came out of memory
+        assert_eq!(reader.decoded, 2_000 * 2);
+        assert_eq!(reader.cache.len(), 1_000 * 2);
+    }
+
+    #[test]
+    fn loop_point_set_behind_the_decoder_gets_cached_on_the_first_pass() {
+        let transport = TransportHandle::new();
+        let mut reader = ramp(10);
+        for _ in 0..3_000 {
+            next_frame(&mut reader, &transport);
+        }
+        transport.set_loop_region(1_000, 4_000);
+        transport.set_loop_enabled(true);
+
+        let frames: Vec<u64> = (0..7_000)
+            .map(|_| next_frame(&mut reader, &transport))
+            .collect();
+        assert_eq!(
+            &frames[..1_000],
+            (3_000..4_000).collect::<Vec<_>>().as_slice()
+        );
+        for pass in frames[1_000..].chunks(3_000) {
+            assert_eq!(pass, (1_000..4_000).collect::<Vec<_>>().as_slice());
+        }
+        // One decoder seek back to A, the second wrap came out of memory
+        assert_eq!(reader.decoded, 4_000 * 2);
+        assert_eq!(reader.cache_start, 1_000 * 2);
+        assert_eq!(reader.cache.len(), 3_000 * 2);
+    }
+
+    #[test]
+    fn heard_position_trails_the_decoder_and_wraps_into_the_loop() {
+        let transport = TransportHandle::new();
+        transport.store_position(5_000);
+        transport.set_read_ahead(300);
+        assert_eq!(transport.heard_position_frames(200), 4_500);
+
+        // 100 frames into the next pass, the other 400 still come from the end of the last one
+        transport.set_loop_region(1_000, 2_000);
+        transport.set_loop_enabled(true);
+        transport.store_position(1_100);
+        assert_eq!(transport.heard_position_frames(200), 1_600);
+        transport.store_position(1_700);
+        assert_eq!(transport.heard_position_frames(200), 1_200);
+    }
+
+    #[test]
+    fn seeks_outside_the_cache_go_through_the_decoder() {
+        let transport = TransportHandle::new();
+        let mut reader = ramp(10);
+        next_frame(&mut reader, &transport);
+
+        reader.seek_frame(8_500);
+        assert_eq!(next_frame(&mut reader, &transport), 8_500);
+        reader.seek_frame(2_500);
+        assert_eq!(next_frame(&mut reader, &transport), 2_500);
+        assert_eq!(transport.position_frames(), 2_500);
+        // No cue or loop point, so nothing was kept
+        assert!(reader.cache.is_empty());
+    }
+
+    #[test]
+    fn cache_stops_growing_at_the_cap() {
+        let transport = TransportHandle::new();
+        transport.reset_for_new_track(None, Some(0));
+        let mut reader = ramp(MAX_CACHED_SECONDS + 10);
+        let frames = (MAX_CACHED_SECONDS + 5) * SAMPLE_RATE as usize;
+        for _ in 0..frames {
+            next_frame(&mut reader, &transport);
+        }
+        assert_eq!(reader.cache.len(), reader.max_cached);
+
+        reader.seek_frame(30_000);
+        assert_eq!(next_frame(&mut reader, &transport), 30_000);
+        assert_eq!(reader.decoded, frames * 2);
+        let past_cache = (MAX_CACHED_SECONDS as u64 + 2) * SAMPLE_RATE as u64;
+        reader.seek_frame(past_cache);
+        assert_eq!(next_frame(&mut reader, &transport), past_cache);
+    }
+}
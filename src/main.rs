@@ -1,19 +1,43 @@
 mod app;
 mod audio;
+mod offline_render;
 mod visualization;
 
 use app::AudioVisualizerApp;
+use eframe::egui_wgpu::WgpuConfiguration;
+use std::sync::Arc;
+use visualization::renderer::optional_device_features;
 
 fn main() -> Result<(), eframe::Error> {
     tracing_subscriber::fmt()
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
         .init();
 
+    // `render` makes videos without opening a window
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("render") {
+        if let Err(e) = offline_render::run(&args[1..]) {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     tracing::info!("Starting Audio Visualizer App");
 
+    // egui's usual device, plus whatever extras the point cloud can use on this adapter
+    let wgpu_options = WgpuConfiguration {
+        device_descriptor: Arc::new(|adapter| {
+            let mut descriptor = (WgpuConfiguration::default().device_descriptor)(adapter);
+            descriptor.required_features |= optional_device_features(adapter);
+            descriptor
+        }),
+        ..Default::default()
+    };
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default().with_inner_size([800.0, 600.0]),
         renderer: eframe::Renderer::Wgpu,
+        wgpu_options,
         ..Default::default()
     };
 
@@ -0,0 +1,645 @@
+use crate::audio::timeline::{AnalysisTimeline, DecodedAudio};
+use crate::audio::PlaybackState;
+use crate::visualization::capture::write_png;
+use crate::visualization::cpu_raster::CpuCanvas;
+use crate::visualization::post_process::{PostProcessor, ToneMapping};
+use crate::visualization::renderer::{optional_device_features, WgpuSphereRenderer};
+use crate::visualization::visualizer::Visualizer;
+use crate::visualization::{VisualizerInput, VisualizerRegistry};
+use anyhow::{anyhow, bail, Context, Result};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{mpsc, Arc};
+
+// sRGB so the bytes that come back can go straight into PNGs and encoders
+const FRAME_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+const DEFAULT_FPS: u32 = 60;
+const DEFAULT_SIZE: [u32; 2] = [1920, 1080];
+const SOUNDTRACK_FILE_NAME: &str = "soundtrack.wav";
+
+const USAGE: &str = "\
+Usage: audio_visualizer render <audio file> [options]
+
+Renders the track frame by frame at a fixed rate, independent of real time.
+
+Options:
+  --out <dir>          Write numbered PNG frames and soundtrack.wav into <dir> (default: frames)
+  --video <file>       Pipe raw frames into an encoder instead, muxed with the track's audio
+  --encoder <program>  Encoder used for --video, needs ffmpeg's arguments (default: ffmpeg)
+  --fps <n>            Frames per second (default: 60)
+  --size <w>x<h>       Frame size in pixels (default: 1920x1080)
+  --mode <name>        Visualization mode, as named in the app (default: the first one)
+  --duration <secs>    Only render the start of the track
+  --cpu                Draw in software instead of on the GPU, Point Cloud only and
+                       without post effects. Also used when no GPU adapter is found.";
+
+pub enum RenderOutput {
+    // Numbered PNGs plus the soundtrack as a WAV, in a directory
+    PngSequence(PathBuf),
+    // Raw RGBA frames piped into an ffmpeg compatible encoder, which also reads the track
+    Video { path: PathBuf, encoder: String },
+}
+
+pub struct RenderOptions {
+    pub input: String,
+    pub output: RenderOutput,
+    pub fps: u32,
+    pub size: [u32; 2],
+    pub mode: Option<String>,
+    pub max_duration: Option<f64>,
+    // Use the software point cloud renderer even if there's a GPU
+    pub software: bool,
+}
+
+impl RenderOptions {
+    // Arguments after `render`
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let mut input = None;
+        let mut out_dir = None;
+        let mut video = None;
+        let mut encoder = "ffmpeg".to_string();
+        let mut fps = DEFAULT_FPS;
+        let mut size = DEFAULT_SIZE;
+        let mut mode = None;
+        let mut max_duration = None;
+        let mut software = false;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| anyhow!("{} needs a value\n\n{}", arg, USAGE))
+            };
+            match arg.as_str() {
+                "--out" => out_dir = Some(PathBuf::from(value()?)),
+                "--video" => video = Some(PathBuf::from(value()?)),
+                "--encoder" => encoder = value()?,
+                "--fps" => {
+                    fps = value()?
+                        .parse()
+                        .ok()
+                        .filter(|&fps| fps > 0)
+                        .ok_or_else(|| anyhow!("--fps needs a positive whole number"))?
+                }
+                "--size" => size = parse_size(&value()?)?,
+                "--mode" => mode = Some(value()?),
+                "--duration" => {
+                    max_duration = Some(
+                        value()?
+                            .parse()
+                            .ok()
+                            .filter(|&seconds: &f64| seconds > 0.0)
+                            .ok_or_else(|| anyhow!("--duration needs a positive number"))?,
+                    )
+                }
+                "--cpu" => software = true,
+                "--help" | "-h" => bail!("{}", USAGE),
+                _ if arg.starts_with("--") => bail!("Unknown option {}\n\n{}", arg, USAGE),
+                _ if input.is_none() => input = Some(arg.clone()),
+                _ => bail!("Unexpected argument {}\n\n{}", arg, USAGE),
+            }
+        }
+
+        let output = match (video, out_dir) {
+            (Some(_), Some(_)) => bail!("--out and --video can't be used together"),
+            (Some(path), None) => RenderOutput::Video { path, encoder },
+            (None, dir) => RenderOutput::PngSequence(dir.unwrap_or_else(|| "frames".into())),
+        };
+        Ok(RenderOptions {
+            input: input.ok_or_else(|| anyhow!("No audio file given\n\n{}", USAGE))?,
+            output,
+            fps,
+            size,
+            mode,
+            max_duration,
+            software,
+        })
+    }
+}
+
+fn parse_size(value: &str) -> Result<[u32; 2]> {
+    let parsed = value
+        .split_once('x')
+        .and_then(|(w, h)| Some([w.parse().ok()?, h.parse().ok()?]))
+        .filter(|size: &[u32; 2]| size[0] > 0 && size[1] > 0);
+    parsed.ok_or_else(|| anyhow!("--size should look like 1920x1080, got {}", value))
+}
+
+// Where finished frames go
+enum FrameSink {
+    Png {
+        directory: PathBuf,
+        next_index: usize,
+    },
+    Encoder {
+        child: Child,
+        stdin: BufWriter<ChildStdin>,
+    },
+}
+
+impl FrameSink {
+    // `duration` is how much of the track gets rendered, in seconds
+    fn open(options: &RenderOptions, audio: &DecodedAudio, duration: f64) -> Result<Self> {
+        let [width, height] = options.size;
+        match &options.output {
+            RenderOutput::PngSequence(directory) => {
+                std::fs::create_dir_all(directory)
+                    .with_context(|| format!("Failed to create {}", directory.display()))?;
+                audio
+                    .write_wav(&directory.join(SOUNDTRACK_FILE_NAME), duration)
+                    .map_err(|e| anyhow!(e))?;
+                Ok(FrameSink::Png {
+                    directory: directory.clone(),
+                    next_index: 0,
+                })
+            }
+            RenderOutput::Video { path, encoder } => {
+                let mut child = Command::new(encoder)
+                    .args(["-y", "-loglevel", "error"])
+                    .args(["-f", "rawvideo", "-pix_fmt", "rgba"])
+                    .args(["-s", &format!("{}x{}", width, height)])
+                    .args(["-framerate", &options.fps.to_string()])
+                    .args(["-i", "-"])
+                    .args(["-i", &options.input])
+                    .args(["-map", "0:v:0", "-map", "1:a:0"])
+                    .args(["-c:v", "libx264", "-pix_fmt", "yuv420p", "-c:a", "aac"])
+                    .arg("-shortest")
+                    .arg(path)
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .with_context(|| format!("Failed to start encoder '{}'", encoder))?;
+                let stdin = child
+                    .stdin
+                    .take()
+                    .ok_or_else(|| anyhow!("Encoder has no stdin"))?;
+                Ok(FrameSink::Encoder {
+                    child,
+                    stdin: BufWriter::new(stdin),
+                })
+            }
+        }
+    }
+
+    // `pixels` is tightly packed RGBA
+    fn write_frame(&mut self, pixels: &[u8], size: [u32; 2]) -> Result<()> {
+        match self {
+            FrameSink::Png {
+                directory,
+                next_index,
+            } => {
+                let path = directory.join(format!("frame_{:06}.png", next_index));
+                write_png(&path, size, pixels)?;
+                *next_index += 1;
+            }
+            FrameSink::Encoder { stdin, .. } => {
+                stdin
+                    .write_all(pixels)
+                    .context("Encoder stopped taking frames")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        if let FrameSink::Encoder { mut child, stdin } = self {
+            // Closing stdin tells the encoder the video is done
+            stdin
+                .into_inner()
+                .map_err(|e| anyhow!("Failed to flush frames to the encoder: {}", e))?;
+            let status = child.wait()?;
+            if !status.success() {
+                bail!("Encoder exited with {}", status);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Headless device, nothing here needs a window
+fn create_device() -> Result<(Arc<wgpu::Adapter>, Arc<wgpu::Device>, Arc<wgpu::Queue>)> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::HighPerformance,
+        compatible_surface: None,
+        force_fallback_adapter: false,
+    }))
+    .ok_or_else(|| anyhow!("No GPU adapter available for rendering"))?;
+    tracing::info!("Rendering with {:?}", adapter.get_info());
+    let (device, queue) = pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            label: Some("Offline Render Device"),
+            required_features: optional_device_features(&adapter),
+            required_limits: wgpu::Limits::default(),
+        },
+        None,
+    ))?;
+    Ok((Arc::new(adapter), Arc::new(device), Arc::new(queue)))
+}
+
+// Draws frames either with the full visualizer stack on a GPU, or the point cloud in
+// software when there's no adapter (or --cpu asks for it)
+enum FrameRenderer {
+    Gpu(Box<GpuFrameRenderer>),
+    Cpu {
+        visualizer: Box<WgpuSphereRenderer>,
+        canvas: CpuCanvas,
+        // Same defaults the GPU path tone maps with
+        tone_mapping: ToneMapping,
+        exposure: f32,
+    },
+}
+
+impl FrameRenderer {
+    fn new(options: &RenderOptions) -> Result<Self> {
+        if options.software {
+            return Self::cpu(options);
+        }
+        match create_device() {
+            Ok((adapter, device, queue)) => Ok(FrameRenderer::Gpu(Box::new(
+                GpuFrameRenderer::new(&adapter, device, queue, options)?,
+            ))),
+            Err(e) if Self::cpu_can_render(options) => {
+                eprintln!("{:#}, using the software renderer", e);
+                Self::cpu(options)
+            }
+            Err(e) => Err(e.context("Only Point Cloud can be rendered without a GPU")),
+        }
+    }
+
+    fn cpu_can_render(options: &RenderOptions) -> bool {
+        let name = WgpuSphereRenderer::default().name();
+        options
+            .mode
+            .as_ref()
+            .is_none_or(|mode| mode.eq_ignore_ascii_case(name))
+    }
+
+    fn cpu(options: &RenderOptions) -> Result<Self> {
+        if !Self::cpu_can_render(options) {
+            bail!("Only Point Cloud can be rendered without a GPU");
+        }
+        let post = PostProcessor::new();
+        Ok(FrameRenderer::Cpu {
+            visualizer: Box::default(),
+            canvas: CpuCanvas::new(options.size),
+            tone_mapping: post.tone_mapping,
+            exposure: post.exposure,
+        })
+    }
+
+    // Steps the visualization by `dt` and leaves the finished frame in `pixels`
+    fn render_frame(&mut self, input: &VisualizerInput, dt: f32, pixels: &mut [u8]) -> Result<()> {
+        match self {
+            FrameRenderer::Gpu(gpu) => gpu.render_frame(input, dt, pixels),
+            FrameRenderer::Cpu {
+                visualizer,
+                canvas,
+                tone_mapping,
+                exposure,
+            } => {
+                visualizer.update(input, dt);
+                visualizer.render_cpu(canvas);
+                pixels.copy_from_slice(&canvas.to_rgba8(*tone_mapping, *exposure));
+                Ok(())
+            }
+        }
+    }
+}
+
+struct GpuFrameRenderer {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    registry: VisualizerRegistry,
+    target: wgpu::Texture,
+    target_view: wgpu::TextureView,
+    readback: wgpu::Buffer,
+    row_bytes: u32,
+    padded_row_bytes: u32,
+}
+
+impl GpuFrameRenderer {
+    fn new(
+        adapter: &Arc<wgpu::Adapter>,
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        options: &RenderOptions,
+    ) -> Result<Self> {
+        let [width, height] = options.size;
+        let mut registry = VisualizerRegistry::with_defaults();
+        registry.set_gpu(device.clone(), adapter, FRAME_FORMAT);
+        if let Some(mode) = &options.mode {
+            let names = registry.names();
+            let index = names
+                .iter()
+                .position(|name| name.eq_ignore_ascii_case(mode))
+                .ok_or_else(|| {
+                    anyhow!("Unknown mode '{}', pick one of: {}", mode, names.join(", "))
+                })?;
+            registry.set_active(index);
+        }
+
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offline Render Target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FRAME_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        // Texture to buffer copies need rows padded to 256 bytes
+        let row_bytes = width * 4;
+        let padded_row_bytes = row_bytes.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offline Render Readback Buffer"),
+            size: (padded_row_bytes * height) as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Ok(GpuFrameRenderer {
+            device,
+            queue,
+            registry,
+            target,
+            target_view,
+            readback,
+            row_bytes,
+            padded_row_bytes,
+        })
+    }
+
+    fn render_frame(&mut self, input: &VisualizerInput, dt: f32, pixels: &mut [u8]) -> Result<()> {
+        let size = self.target.size();
+        self.registry.update(input, dt);
+        let frame = self
+            .registry
+            .paint(size.width as f32 / size.height as f32)
+            .ok_or_else(|| anyhow!("The visualization has nothing to draw"))?;
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Offline Render Encoder"),
+            });
+        frame.prepare(
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            [size.width, size.height],
+        );
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Offline Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.target_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            frame.paint(&mut render_pass, &self.queue);
+        }
+        encoder.copy_texture_to_buffer(
+            self.target.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_row_bytes),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            size,
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        read_frame(
+            &self.device,
+            &self.readback,
+            self.row_bytes,
+            self.padded_row_bytes,
+            pixels,
+        )
+    }
+}
+
+// Renders the whole track (or its first `max_duration` seconds). Every frame advances
+// the analysis and the visualizer by exactly 1 / fps, so the same input always gives
+// the same video no matter how long a frame takes to draw.
+pub fn render(options: &RenderOptions) -> Result<()> {
+    let audio = DecodedAudio::open(&options.input).map_err(|e| anyhow!(e))?;
+    let duration = options
+        .max_duration
+        .map_or(audio.duration_seconds(), |max| {
+            max.min(audio.duration_seconds())
+        });
+    let frame_count = (duration * options.fps as f64).ceil() as usize;
+    let dt = 1.0 / options.fps as f32;
+    let [width, height] = options.size;
+
+    let mut renderer = FrameRenderer::new(options)?;
+    let mut sink = FrameSink::open(options, &audio, duration)?;
+    let mut timeline = AnalysisTimeline::new(&audio);
+    let mut pixels = vec![0u8; width as usize * height as usize * 4];
+    eprintln!(
+        "Rendering {} frames ({:.1} s at {} fps, {}x{})",
+        frame_count, duration, options.fps, width, height
+    );
+
+    for index in 0..frame_count {
+        let input = VisualizerInput {
+            playback_state: PlaybackState::Playing,
+            analysis: timeline.advance_to(index as f64 / options.fps as f64),
+        };
+        renderer.render_frame(&input, dt, &mut pixels)?;
+        sink.write_frame(&pixels, options.size)?;
+
+        if (index + 1) % options.fps as usize == 0 || index + 1 == frame_count {
+            eprintln!("  {}/{} frames", index + 1, frame_count);
+        }
+    }
+
+    sink.finish()?;
+    match &options.output {
+        RenderOutput::PngSequence(directory) => {
+            eprintln!(
+                "Frames and {} written to {}",
+                SOUNDTRACK_FILE_NAME,
+                directory.display()
+            )
+        }
+        RenderOutput::Video { path, .. } => eprintln!("Video written to {}", path.display()),
+    }
+    Ok(())
+}
+
+// Waits for the copy to land and strips the row padding into `pixels`
+fn read_frame(
+    device: &wgpu::Device,
+    readback: &wgpu::Buffer,
+    row_bytes: u32,
+    padded_row_bytes: u32,
+    pixels: &mut [u8],
+) -> Result<()> {
+    let slice = readback.slice(..);
+    let (sender, receiver) = mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    receiver
+        .recv()
+        .context("Readback was dropped")?
+        .context("Failed to read the frame back")?;
+    {
+        let mapped = slice.get_mapped_range();
+        for (row, padded) in pixels
+            .chunks_exact_mut(row_bytes as usize)
+            .zip(mapped.chunks_exact(padded_row_bytes as usize))
+        {
+            row.copy_from_slice(&padded[..row_bytes as usize]);
+        }
+    }
+    readback.unmap();
+    Ok(())
+}
+
+// Entry point for `audio_visualizer render ...`
+pub fn run(args: &[String]) -> Result<()> {
+    let options = RenderOptions::parse(args)?;
+    if let RenderOutput::Video { path, .. } = &options.output {
+        if path.is_dir() {
+            bail!(
+                "--video needs a file name, {} is a directory",
+                path.display()
+            );
+        }
+    }
+    render(&options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::visualization::preset::VisualPreset;
+
+    const GOLDEN_SIZE: [u32; 2] = [96, 64];
+    // Different libm builds can round the odd sin/cos differently, which moves a few
+    // sprite edges by a step or two
+    const CHANNEL_TOLERANCE: u8 = 3;
+    const MAX_DIFFERENT_PIXELS: usize = 20;
+
+    fn golden_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(format!("{name}.png"))
+    }
+
+    fn read_png(path: &std::path::Path) -> Option<([u32; 2], Vec<u8>)> {
+        let decoder = png::Decoder::new(std::fs::File::open(path).ok()?);
+        let mut reader = decoder.read_info().ok()?;
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).ok()?;
+        pixels.truncate(info.buffer_size());
+        Some(([info.width, info.height], pixels))
+    }
+
+    // Compares against tests/golden/<name>.png. Run with UPDATE_GOLDEN=1 to (re)write it
+    // after an intended change to the look.
+    fn assert_matches_golden(name: &str, pixels: &[u8]) {
+        let path = golden_path(name);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            write_png(&path, GOLDEN_SIZE, pixels).unwrap();
+            return;
+        }
+        let (size, golden) = read_png(&path).unwrap_or_else(|| {
+            panic!(
+                "Missing {}, run with UPDATE_GOLDEN=1 to create it",
+                path.display()
+            )
+        });
+        assert_eq!(size, GOLDEN_SIZE);
+        let different = pixels
+            .chunks_exact(4)
+            .zip(golden.chunks_exact(4))
+            .filter(|(a, b)| {
+                a.iter()
+                    .zip(*b)
+                    .any(|(a, b)| a.abs_diff(*b) > CHANNEL_TOLERANCE)
+            })
+            .count();
+        assert!(
+            different <= MAX_DIFFERENT_PIXELS,
+            "{different} pixels differ from {}",
+            path.display()
+        );
+    }
+
+    // What `render --cpu` draws with `preset` after `frames` frames of silence
+    fn render_silent_frames(preset: VisualPreset, frames: usize) -> Vec<u8> {
+        let options = RenderOptions {
+            input: String::new(),
+            output: RenderOutput::PngSequence(PathBuf::new()),
+            fps: 30,
+            size: GOLDEN_SIZE,
+            mode: None,
+            max_duration: None,
+            software: true,
+        };
+        let mut renderer = FrameRenderer::new(&options).unwrap();
+        if let FrameRenderer::Cpu { visualizer, .. } = &mut renderer {
+            visualizer.switch_preset(preset, 0.0);
+        }
+        let input = VisualizerInput {
+            playback_state: PlaybackState::Idle,
+            analysis: None,
+        };
+        let mut pixels = vec![0; (GOLDEN_SIZE[0] * GOLDEN_SIZE[1] * 4) as usize];
+        for _ in 0..frames {
+            renderer
+                .render_frame(&input, 1.0 / options.fps as f32, &mut pixels)
+                .unwrap();
+        }
+        pixels
+    }
+
+    // A few frames in, so the smoothing and auto-rotate have moved off their start values
+    fn assert_preset_matches_golden(preset: VisualPreset, name: &str) {
+        let pixels = render_silent_frames(preset, 10);
+
+        // Guard against blessing an empty frame
+        let lit = pixels
+            .chunks_exact(4)
+            .filter(|p| p[..3] != [0, 0, 0])
+            .count();
+        assert!(lit > pixels.len() / 4 / 20, "{name}: only {lit} pixels lit");
+        assert_matches_golden(name, &pixels);
+    }
+
+    #[test]
+    fn cpu_render_matches_golden() {
+        assert_preset_matches_golden(VisualPreset::default(), "point_cloud_default");
+    }
+
+    #[test]
+    fn built_in_presets_match_golden() {
+        assert_preset_matches_golden(VisualPreset::calm(), "point_cloud_calm");
+        assert_preset_matches_golden(VisualPreset::punchy(), "point_cloud_punchy");
+    }
+}
@@ -0,0 +1,65 @@
+use crate::audio::AudioAnalysisData;
+
+// How fast the features fall back after a hit, in seconds
+const FEATURE_RELEASE_TIME: f32 = 0.15;
+// Music rarely pushes RMS past ~0.33 or a band past ~0.25, scaled so they fill 0..1
+const RMS_GAIN: f32 = 3.0;
+const BAND_GAIN: f32 = 4.0;
+// Onset strength that counts as full level
+const MAX_ONSET: f32 = 3.0;
+
+// Audio levels in 0..1 for things that pulse with the music. Jump up straight away,
+// fall back over FEATURE_RELEASE_TIME.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AudioFeatures {
+    pub rms: f32,
+    pub peak: f32,
+    // Lowest crossover band
+    pub bass: f32,
+    pub onset: f32,
+}
+
+impl AudioFeatures {
+    // `data` should be None while nothing is playing, everything falls back to 0 then
+    pub fn update(&mut self, data: Option<&AudioAnalysisData>, dt: f32) {
+        let target = data.map_or(AudioFeatures::default(), |data| AudioFeatures {
+            rms: (data.rms_amplitude * RMS_GAIN).clamp(0.0, 1.0),
+            peak: data.peak_amplitude.clamp(0.0, 1.0),
+            bass: data
+                .bands
+                .first()
+                .map_or(0.0, |band| (band.rms * BAND_GAIN).clamp(0.0, 1.0)),
+            onset: strongest_onset(data).min(MAX_ONSET) / MAX_ONSET,
+        });
+        let release = (-dt / FEATURE_RELEASE_TIME).exp();
+        let follow = |current: f32, target: f32| target.max(current * release);
+        self.rms = follow(self.rms, target.rms);
+        self.peak = follow(self.peak, target.peak);
+        self.bass = follow(self.bass, target.bass);
+        self.onset = follow(self.onset, target.onset);
+    }
+}
+
+// Highest onset over all crossover bands, 1 = as loud as the band's recent average
+pub fn strongest_onset(data: &AudioAnalysisData) -> f32 {
+    data.bands.iter().map(|band| band.onset).fold(0.0, f32::max)
+}
+
+// Analysis windows stick around for several frames. This hands out each window's onset
+// once, so beat triggered things don't fire again every frame until the next window.
+#[derive(Debug, Default)]
+pub struct OnsetEdge {
+    last_window_timestamp: Option<f64>,
+}
+
+impl OnsetEdge {
+    // Strongest onset of `analysis` if it's a window that hasn't been seen yet
+    pub fn poll(&mut self, analysis: Option<&AudioAnalysisData>) -> Option<f32> {
+        let data = analysis?;
+        if self.last_window_timestamp == Some(data.timestamp) {
+            return None;
+        }
+        self.last_window_timestamp = Some(data.timestamp);
+        Some(strongest_onset(data))
+    }
+}
@@ -0,0 +1,413 @@
+use crate::audio::PlaybackState;
+use crate::visualization::spectrum::{FrequencyScale, SpectrumBands};
+use crate::visualization::visualizer::{Visualizer, VisualizerFrame, VisualizerInput};
+use anyhow::Result;
+use bytemuck::{Pod, Zeroable};
+use eframe::egui;
+use std::sync::Arc;
+use wgpu::util::DeviceExt;
+
+pub const MIN_BARS: usize = 8;
+pub const MAX_BARS: usize = 256;
+const DEFAULT_BARS: usize = 48;
+// Fraction of each bar slot left empty
+const BAR_GAP: f32 = 0.2;
+// Peak caps hang on for a moment, then fall with constant acceleration (full heights / s²)
+const PEAK_HOLD_SECONDS: f32 = 0.35;
+const PEAK_GRAVITY: f32 = 2.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarLayout {
+    // Bars grow up from the bottom edge
+    Bottom,
+    // Bars grow up and down from the centre line
+    Mirrored,
+    // Bars point outwards from a ring, bass at the top going clockwise
+    Circular,
+}
+
+impl BarLayout {
+    pub const ALL: [BarLayout; 3] = [BarLayout::Bottom, BarLayout::Mirrored, BarLayout::Circular];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BarLayout::Bottom => "Bottom",
+            BarLayout::Mirrored => "Mirrored",
+            BarLayout::Circular => "Circular",
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct BarParamsUniform {
+    low_color: [f32; 4],
+    high_color: [f32; 4],
+    cap_color: [f32; 4],
+    bar_count: u32,
+    layout_mode: u32,
+    aspect_ratio: f32,
+    gap: f32,
+}
+
+const BAR_SHADER_WGSL: &str = r#"
+struct BarParams {
+    low_color: vec4<f32>,
+    high_color: vec4<f32>,
+    cap_color: vec4<f32>,
+    bar_count: u32,
+    layout_mode: u32,
+    aspect_ratio: f32,
+    gap: f32,
+};
+@group(0) @binding(0)
+var<uniform> params: BarParams;
+// x = bar level, y = peak cap position, both 0..1
+@group(0) @binding(1)
+var<storage, read> bars: array<vec2<f32>>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+const TAU: f32 = 6.28318531;
+const CAP_HEIGHT: f32 = 0.015;
+const INNER_RADIUS: f32 = 0.3;
+const OUTER_RADIUS: f32 = 0.95;
+
+// Instances come in groups of `bar_count`: bars, caps, mirrored bars, mirrored caps.
+// Each instance is one quad (six vertices), u runs across the bar and v along it.
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0), vec2<f32>(1.0, 0.0), vec2<f32>(0.0, 1.0),
+        vec2<f32>(0.0, 1.0), vec2<f32>(1.0, 0.0), vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+    let bar = instance_index % params.bar_count;
+    let group = instance_index / params.bar_count;
+    let is_cap = (group & 1u) == 1u;
+    let side = select(1.0, -1.0, group >= 2u);
+    let level = bars[bar];
+
+    var v0 = 0.0;
+    var v1 = level.x;
+    if is_cap {
+        v0 = min(level.y, 1.0 - CAP_HEIGHT);
+        v1 = v0 + CAP_HEIGHT;
+    }
+    let v = mix(v0, v1, corner.y);
+    let u = (f32(bar) + mix(params.gap * 0.5, 1.0 - params.gap * 0.5, corner.x))
+        / f32(params.bar_count);
+
+    var position: vec2<f32>;
+    switch params.layout_mode {
+        case 1u: {
+            position = vec2<f32>(u * 2.0 - 1.0, v * side);
+        }
+        case 2u: {
+            let angle = u * TAU;
+            let radius = INNER_RADIUS + v * (OUTER_RADIUS - INNER_RADIUS);
+            // Keep the ring round whatever the rect's shape
+            let fit = vec2<f32>(
+                min(1.0, 1.0 / params.aspect_ratio),
+                min(1.0, params.aspect_ratio),
+            );
+            position = vec2<f32>(sin(angle), cos(angle)) * radius * fit;
+        }
+        default: {
+            position = vec2<f32>(u * 2.0 - 1.0, v * 2.0 - 1.0);
+        }
+    }
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(position, 0.0, 1.0);
+    out.color = select(mix(params.low_color, params.high_color, v), params.cap_color, is_cap);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
+"#;
+
+pub struct BarAnalyzerPrimitive {
+    params_uniform_buffer: wgpu::Buffer,
+    bar_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    render_pipeline: wgpu::RenderPipeline,
+}
+
+// Peak cap of one bar
+#[derive(Debug, Clone, Copy, Default)]
+struct PeakCap {
+    position: f32,
+    velocity: f32,
+    hold: f32,
+}
+
+impl PeakCap {
+    fn update(&mut self, level: f32, dt: f32) {
+        if level >= self.position {
+            *self = PeakCap {
+                position: level,
+                velocity: 0.0,
+                hold: PEAK_HOLD_SECONDS,
+            };
+        } else if self.hold > 0.0 {
+            self.hold -= dt;
+        } else {
+            self.velocity += PEAK_GRAVITY * dt;
+            self.position = (self.position - self.velocity * dt).max(level);
+        }
+    }
+}
+
+// Classic bar / column spectrum analyzer
+pub struct BarAnalyzer {
+    primitive: Option<Arc<BarAnalyzerPrimitive>>,
+    spectrum: SpectrumBands,
+    caps: Vec<PeakCap>,
+    pub bar_count: usize,
+    pub scale: FrequencyScale,
+    pub layout: BarLayout,
+    pub low_color: [f32; 3],
+    pub high_color: [f32; 3],
+    pub cap_color: [f32; 3],
+}
+
+impl BarAnalyzer {
+    pub fn new() -> Self {
+        BarAnalyzer {
+            primitive: None,
+            spectrum: SpectrumBands::new(DEFAULT_BARS),
+            caps: vec![PeakCap::default(); DEFAULT_BARS],
+            bar_count: DEFAULT_BARS,
+            scale: FrequencyScale::Log,
+            layout: BarLayout::Bottom,
+            low_color: [0.1, 0.4, 1.0],
+            high_color: [1.0, 0.2, 0.6],
+            cap_color: [1.0, 1.0, 1.0],
+        }
+    }
+
+    fn params(&self, aspect_ratio: f32) -> BarParamsUniform {
+        BarParamsUniform {
+            low_color: rgba(self.low_color),
+            high_color: rgba(self.high_color),
+            cap_color: rgba(self.cap_color),
+            bar_count: self.bar_count as u32,
+            layout_mode: self.layout as u32,
+            aspect_ratio,
+            gap: BAR_GAP,
+        }
+    }
+}
+
+impl Visualizer for BarAnalyzer {
+    fn name(&self) -> &'static str {
+        "Spectrum Bars"
+    }
+
+    fn prepare(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        target_format: wgpu::TextureFormat,
+    ) -> Result<()> {
+        if self.primitive.is_some() {
+            return Ok(());
+        }
+        tracing::info!("Preparing BarAnalyzer resources...");
+
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Bar Analyzer Shader"),
+            source: wgpu::ShaderSource::Wgsl(BAR_SHADER_WGSL.into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bar Analyzer Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let params_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bar Analyzer Params Uniform Buffer"),
+            contents: bytemuck::bytes_of(&self.params(1.0)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bar_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bar Analyzer Storage Buffer"),
+            contents: bytemuck::cast_slice(&[[0.0f32; 2]; MAX_BARS]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bar Analyzer Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: bar_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bar Analyzer Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Bar Analyzer Render Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader_module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_module,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        self.primitive = Some(Arc::new(BarAnalyzerPrimitive {
+            params_uniform_buffer,
+            bar_buffer,
+            bind_group,
+            render_pipeline,
+        }));
+        tracing::info!("BarAnalyzer resources prepared successfully.");
+        Ok(())
+    }
+
+    fn update(&mut self, input: &VisualizerInput, dt: f32) {
+        self.bar_count = self.bar_count.clamp(MIN_BARS, MAX_BARS);
+        self.spectrum.set_num_bands(self.bar_count);
+        self.spectrum.set_scale(self.scale);
+        self.caps.resize(self.bar_count, PeakCap::default());
+
+        self.spectrum.update(
+            input
+                .analysis
+                .filter(|_| input.playback_state == PlaybackState::Playing),
+        );
+        for (cap, &level) in self.caps.iter_mut().zip(self.spectrum.values()) {
+            cap.update(level, dt);
+        }
+    }
+
+    fn paint(&mut self, aspect_ratio: f32) -> Option<Box<dyn VisualizerFrame>> {
+        let primitive = self.primitive.clone()?;
+        let bars = self
+            .spectrum
+            .values()
+            .iter()
+            .zip(&self.caps)
+            .map(|(&level, cap)| [level, cap.position])
+            .collect();
+        let mirrored = self.layout == BarLayout::Mirrored;
+        Some(Box::new(BarAnalyzerFrame {
+            primitive,
+            params: self.params(aspect_ratio),
+            bars,
+            instance_count: (self.bar_count * if mirrored { 4 } else { 2 }) as u32,
+        }))
+    }
+
+    fn settings_ui(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_id_source("bar_layout")
+            .selected_text(self.layout.label())
+            .show_ui(ui, |ui| {
+                for layout in BarLayout::ALL {
+                    ui.selectable_value(&mut self.layout, layout, layout.label());
+                }
+            });
+        let mut log_scale = self.scale == FrequencyScale::Log;
+        if ui.checkbox(&mut log_scale, "Log frequency").changed() {
+            self.scale = if log_scale {
+                FrequencyScale::Log
+            } else {
+                FrequencyScale::Linear
+            };
+        }
+        ui.add(egui::Slider::new(&mut self.bar_count, MIN_BARS..=MAX_BARS).text("Bars"));
+        ui.label("Gradient:");
+        ui.color_edit_button_rgb(&mut self.low_color);
+        ui.color_edit_button_rgb(&mut self.high_color);
+        ui.label("Caps:");
+        ui.color_edit_button_rgb(&mut self.cap_color);
+    }
+}
+
+struct BarAnalyzerFrame {
+    primitive: Arc<BarAnalyzerPrimitive>,
+    params: BarParamsUniform,
+    bars: Vec<[f32; 2]>,
+    instance_count: u32,
+}
+
+impl VisualizerFrame for BarAnalyzerFrame {
+    fn paint<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, queue: &wgpu::Queue) {
+        let primitive = &self.primitive;
+        queue.write_buffer(
+            &primitive.params_uniform_buffer,
+            0,
+            bytemuck::bytes_of(&self.params),
+        );
+        let bar_count = self.bars.len().min(MAX_BARS);
+        queue.write_buffer(
+            &primitive.bar_buffer,
+            0,
+            bytemuck::cast_slice(&self.bars[..bar_count]),
+        );
+
+        render_pass.set_pipeline(&primitive.render_pipeline);
+        render_pass.set_bind_group(0, &primitive.bind_group, &[]);
+        render_pass.draw(0..6, 0..self.instance_count);
+    }
+}
+
+fn rgba(rgb: [f32; 3]) -> [f32; 4] {
+    [rgb[0], rgb[1], rgb[2], 1.0]
+}
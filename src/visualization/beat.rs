@@ -0,0 +1,53 @@
+use crate::audio::AudioAnalysisData;
+use crate::visualization::audio_features::OnsetEdge;
+
+// Beats further apart than this don't count towards the tempo, the music probably stopped
+const MAX_BEAT_INTERVAL: f32 = 2.0;
+// Onsets closer together than this are the same beat
+const MIN_BEAT_INTERVAL: f32 = 0.25;
+// Tempo the beat phase runs at before two beats were seen, 120 BPM
+const DEFAULT_BEAT_INTERVAL: f32 = 0.5;
+
+// Tracks onsets to get a beat phase to sync to
+pub struct BeatClock {
+    // Smoothed time between beats, None until two beats were seen
+    interval: Option<f32>,
+    since_beat: f32,
+    onsets: OnsetEdge,
+}
+
+impl BeatClock {
+    pub fn new() -> Self {
+        BeatClock {
+            interval: None,
+            since_beat: 0.0,
+            onsets: OnsetEdge::default(),
+        }
+    }
+
+    pub fn update(&mut self, analysis: Option<&AudioAnalysisData>, threshold: f32, dt: f32) {
+        self.since_beat += dt;
+        let Some(onset) = self.onsets.poll(analysis) else {
+            return;
+        };
+        if onset < threshold || self.since_beat < MIN_BEAT_INTERVAL {
+            return;
+        }
+        if self.since_beat <= MAX_BEAT_INTERVAL {
+            self.interval = Some(match self.interval {
+                Some(interval) => interval + (self.since_beat - interval) * 0.3,
+                None => self.since_beat,
+            });
+        }
+        self.since_beat = 0.0;
+    }
+
+    // Keeps running at the last tempo when beats are missed
+    pub fn phase(&self) -> f32 {
+        (self.since_beat / self.interval.unwrap_or(DEFAULT_BEAT_INTERVAL)).fract()
+    }
+
+    pub fn bpm(&self) -> f32 {
+        self.interval.map_or(0.0, |interval| 60.0 / interval)
+    }
+}
@@ -0,0 +1,243 @@
+use crate::audio::AudioAnalysisData;
+use crate::visualization::audio_features::OnsetEdge;
+use eframe::egui;
+use glam::{Mat4, Vec3};
+
+const MIN_DISTANCE: f32 = 0.5;
+const MAX_DISTANCE: f32 = 30.0;
+// Just short of straight up / down, look_at breaks down at the poles
+const MAX_PITCH: f32 = 1.5;
+// Radians per dragged point
+const ORBIT_SPEED: f32 = 0.01;
+// Radians per second with the arrow keys
+const KEY_ORBIT_SPEED: f32 = 1.5;
+
+// Where the camera is, as an orbit around `target`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraPose {
+    // Around the Y axis, 0 looks down -Z
+    pub yaw: f32,
+    // Up / down, positive looks from above
+    pub pitch: f32,
+    pub distance: f32,
+    pub target: Vec3,
+}
+
+impl CameraPose {
+    pub const DEFAULT: CameraPose = CameraPose {
+        yaw: 0.0,
+        pitch: 0.0,
+        distance: 4.0,
+        target: Vec3::ZERO,
+    };
+
+    pub fn eye(&self) -> Vec3 {
+        let direction = Vec3::new(
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+            self.pitch.cos() * self.yaw.cos(),
+        );
+        self.target + direction * self.distance
+    }
+
+    pub fn view_matrix(&self) -> Mat4 {
+        Mat4::look_at_rh(self.eye(), self.target, Vec3::Y)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CameraBookmark {
+    pub name: String,
+    pub pose: CameraPose,
+}
+
+// Mouse / keyboard driven orbit camera with bookmarks and optional cuts on beats
+pub struct OrbitCamera {
+    pub pose: CameraPose,
+    pub auto_rotate: bool,
+    pub bookmarks: Vec<CameraBookmark>,
+    // Jump to the next bookmark whenever an onset crosses `cut_threshold`
+    pub beat_cuts: bool,
+    pub cut_threshold: f32,
+    // Shortest time between two cuts, in seconds
+    pub min_cut_interval: f32,
+    next_bookmark: usize,
+    since_last_cut: f32,
+    onsets: OnsetEdge,
+}
+
+impl OrbitCamera {
+    pub fn new() -> Self {
+        OrbitCamera {
+            pose: CameraPose::DEFAULT,
+            auto_rotate: true,
+            bookmarks: vec![
+                CameraBookmark {
+                    name: "Front".to_string(),
+                    pose: CameraPose::DEFAULT,
+                },
+                CameraBookmark {
+                    name: "Above".to_string(),
+                    pose: CameraPose {
+                        pitch: 1.2,
+                        distance: 5.0,
+                        ..CameraPose::DEFAULT
+                    },
+                },
+                CameraBookmark {
+                    name: "Close".to_string(),
+                    pose: CameraPose {
+                        yaw: 0.8,
+                        pitch: 0.3,
+                        distance: 2.5,
+                        ..CameraPose::DEFAULT
+                    },
+                },
+            ],
+            beat_cuts: false,
+            cut_threshold: 1.5,
+            min_cut_interval: 0.5,
+            next_bookmark: 0,
+            since_last_cut: 0.0,
+            onsets: OnsetEdge::default(),
+        }
+    }
+
+    // Left drag orbits, right / middle drag pans, scroll zooms, double click resets.
+    // Arrow keys orbit and +/- zoom while the pointer is over the view.
+    pub fn handle_input(&mut self, response: &egui::Response) {
+        if response.dragged_by(egui::PointerButton::Primary) {
+            let delta = response.drag_delta();
+            self.orbit(-delta.x * ORBIT_SPEED, delta.y * ORBIT_SPEED);
+        }
+        if response.dragged_by(egui::PointerButton::Secondary)
+            || response.dragged_by(egui::PointerButton::Middle)
+        {
+            self.pan(response.drag_delta(), response.rect.height());
+        }
+        if response.double_clicked() {
+            self.pose = CameraPose::DEFAULT;
+        }
+        if !response.hovered() {
+            return;
+        }
+
+        let (scroll, dt, keys) = response.ctx.input(|i| {
+            let pressed = |key| i.key_down(key);
+            (
+                i.smooth_scroll_delta.y,
+                i.stable_dt,
+                [
+                    pressed(egui::Key::ArrowLeft),
+                    pressed(egui::Key::ArrowRight),
+                    pressed(egui::Key::ArrowUp),
+                    pressed(egui::Key::ArrowDown),
+                    pressed(egui::Key::Plus) || pressed(egui::Key::Equals),
+                    pressed(egui::Key::Minus),
+                ],
+            )
+        });
+        let [left, right, up, down, zoom_in, zoom_out] = keys.map(|down| down as i32 as f32);
+        self.orbit(
+            (left - right) * KEY_ORBIT_SPEED * dt,
+            (up - down) * KEY_ORBIT_SPEED * dt,
+        );
+        // Scroll is in points, keys zoom by about 2x per second
+        self.zoom((-scroll * 0.002).exp() * ((zoom_out - zoom_in) * dt * 0.7).exp());
+    }
+
+    fn orbit(&mut self, yaw: f32, pitch: f32) {
+        self.pose.yaw += yaw;
+        self.pose.pitch = (self.pose.pitch + pitch).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    fn zoom(&mut self, factor: f32) {
+        self.pose.distance = (self.pose.distance * factor).clamp(MIN_DISTANCE, MAX_DISTANCE);
+    }
+
+    // Moves the target in the view plane, so the point under the cursor roughly follows it
+    fn pan(&mut self, delta: egui::Vec2, view_height: f32) {
+        let view = self.pose.view_matrix();
+        let right = view.row(0).truncate();
+        let up = view.row(1).truncate();
+        // Height of the view at the target's distance, for a 45 degree field of view
+        let world_per_point =
+            2.0 * self.pose.distance * (std::f32::consts::FRAC_PI_8).tan() / view_height.max(1.0);
+        self.pose.target += (-right * delta.x + up * delta.y) * world_per_point;
+    }
+
+    pub fn go_to(&mut self, bookmark: usize) {
+        if let Some(bookmark) = self.bookmarks.get(bookmark) {
+            self.pose = bookmark.pose;
+        }
+    }
+
+    // Beat cuts, `analysis` should only be passed while playing
+    pub fn update(&mut self, analysis: Option<&AudioAnalysisData>, dt: f32) {
+        self.since_last_cut += dt;
+        let Some(onset) = self.onsets.poll(analysis) else {
+            return;
+        };
+        if self.beat_cuts
+            && !self.bookmarks.is_empty()
+            && onset >= self.cut_threshold
+            && self.since_last_cut >= self.min_cut_interval
+        {
+            self.next_bookmark = (self.next_bookmark + 1) % self.bookmarks.len();
+            self.go_to(self.next_bookmark);
+            self.since_last_cut = 0.0;
+        }
+    }
+
+    pub fn settings_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.auto_rotate, "Auto-rotate");
+            if ui.button("Reset view").clicked() {
+                self.pose = CameraPose::DEFAULT;
+            }
+        });
+        ui.label("Drag to orbit, right-drag to pan, scroll to zoom.");
+
+        ui.label("Bookmarks:");
+        let mut go_to = None;
+        let mut remove = None;
+        for (index, bookmark) in self.bookmarks.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut bookmark.name).desired_width(80.0));
+                if ui.small_button("Go").clicked() {
+                    go_to = Some(index);
+                }
+                if ui.small_button("Update").clicked() {
+                    bookmark.pose = self.pose;
+                }
+                if ui.small_button("✖").clicked() {
+                    remove = Some(index);
+                }
+            });
+        }
+        if let Some(index) = go_to {
+            self.go_to(index);
+            self.next_bookmark = index;
+        }
+        if let Some(index) = remove {
+            self.bookmarks.remove(index);
+            self.next_bookmark = 0;
+        }
+        if ui.button("Save current view").clicked() {
+            self.bookmarks.push(CameraBookmark {
+                name: format!("View {}", self.bookmarks.len() + 1),
+                pose: self.pose,
+            });
+        }
+
+        ui.checkbox(&mut self.beat_cuts, "Cut between bookmarks on beats");
+        if self.beat_cuts {
+            ui.add(egui::Slider::new(&mut self.cut_threshold, 0.2..=5.0).text("Onset threshold"));
+            ui.add(
+                egui::Slider::new(&mut self.min_cut_interval, 0.1..=4.0)
+                    .suffix(" s")
+                    .text("Min interval"),
+            );
+        }
+    }
+}
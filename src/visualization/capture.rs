@@ -0,0 +1,621 @@
+use crate::visualization::visualizer::VisualizerFrame;
+use anyhow::{Context, Result};
+use eframe::egui;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Screenshots and clips end up here, relative to the working directory
+const CAPTURE_DIRECTORY: &str = "captures";
+const MAX_CLIP_SECONDS: f32 = 30.0;
+const DEFAULT_CUSTOM_SIZE: [u32; 2] = [1920, 1080];
+// Frames still being copied back before new clip frames get skipped, screenshots always go
+const MAX_PENDING_CAPTURES: usize = 4;
+// Full size RGBA adds up fast (1080p is ~8 MB a frame), older clip frames get dropped
+// past this no matter what the length slider says
+const MAX_CLIP_BYTES: usize = 1024 * 1024 * 1024;
+// Frames a Custom size screenshot renders at its size before it's taken, so trails and
+// other effects with history have caught up with the size change
+const SCREENSHOT_WARMUP_FRAMES: u32 = 10;
+// GIFs get scaled down to this width, full size frames take ages to quantize and come out huge
+const GIF_MAX_WIDTH: u32 = 640;
+
+// Resolution captures are taken at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureSize {
+    // Whatever the visualization rect covers on screen
+    Display,
+    // A fixed size. The view gets letterboxed to its aspect ratio, so what's on screen is
+    // what ends up in the file, and renders at it while something is being captured.
+    Custom,
+}
+
+impl CaptureSize {
+    pub const ALL: [CaptureSize; 2] = [CaptureSize::Display, CaptureSize::Custom];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            CaptureSize::Display => "Display",
+            CaptureSize::Custom => "Custom",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipFormat {
+    Gif,
+    // Numbered PNGs in their own directory, full size and lossless
+    PngSequence,
+}
+
+impl ClipFormat {
+    pub const ALL: [ClipFormat; 2] = [ClipFormat::Gif, ClipFormat::PngSequence];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ClipFormat::Gif => "GIF",
+            ClipFormat::PngSequence => "PNG Sequence",
+        }
+    }
+}
+
+// Tightly packed RGBA, rows top down
+pub struct CapturedFrame {
+    pub size: [u32; 2],
+    pub pixels: Vec<u8>,
+}
+
+// Texture a visualizer frame gets drawn into a second time, plus the buffer it's copied
+// back through. Handed to the paint callback, which records the draw and the copy.
+pub struct CaptureTarget {
+    view: wgpu::TextureView,
+    texture: wgpu::Texture,
+    buffer: wgpu::Buffer,
+    size: [u32; 2],
+    padded_row_bytes: u32,
+    // Set once the callback has recorded the copy, the buffer can be mapped after that
+    recorded: AtomicBool,
+}
+
+impl CaptureTarget {
+    fn new(device: &wgpu::Device, size: [u32; 2], format: wgpu::TextureFormat) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Capture Texture"),
+            size: wgpu::Extent3d {
+                width: size[0],
+                height: size[1],
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        // Texture to buffer copies need rows padded to 256 bytes
+        let padded_row_bytes = (size[0] * 4).div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Capture Readback Buffer"),
+            size: (padded_row_bytes * size[1]) as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        CaptureTarget {
+            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            texture,
+            buffer,
+            size,
+            padded_row_bytes,
+            recorded: AtomicBool::new(false),
+        }
+    }
+
+    // Draws `frame` (already prepared) over black and copies the result into the buffer
+    pub fn record(
+        &self,
+        frame: &dyn VisualizerFrame,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Capture Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            frame.paint(&mut render_pass, queue);
+        }
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &self.buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_row_bytes),
+                    rows_per_image: Some(self.size[1]),
+                },
+            },
+            self.texture.size(),
+        );
+        self.recorded.store(true, Ordering::Release);
+    }
+
+    // Strips the row padding, swaps BGRA screens to RGBA and makes it all opaque
+    fn read(&self) -> CapturedFrame {
+        let row_bytes = self.size[0] as usize * 4;
+        let swap_red_blue = matches!(
+            self.texture.format(),
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        let mut pixels = Vec::with_capacity(row_bytes * self.size[1] as usize);
+        {
+            let mapped = self.buffer.slice(..).get_mapped_range();
+            for padded in mapped.chunks_exact(self.padded_row_bytes as usize) {
+                for pixel in padded[..row_bytes].chunks_exact(4) {
+                    let (r, b) = if swap_red_blue {
+                        (pixel[2], pixel[0])
+                    } else {
+                        (pixel[0], pixel[2])
+                    };
+                    pixels.extend_from_slice(&[r, pixel[1], b, 255]);
+                }
+            }
+        }
+        self.buffer.unmap();
+        CapturedFrame {
+            size: self.size,
+            pixels,
+        }
+    }
+}
+
+struct PendingCapture {
+    target: Arc<CaptureTarget>,
+    screenshot: bool,
+    clip: bool,
+    // Set once mapping has been asked for, the callback result arrives through it
+    mapped: Option<mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>>,
+    // Frames gone by without the callback recording anything (rect off screen and such)
+    frames_waited: u32,
+}
+
+// Screenshots and a rolling buffer of recent frames, read back from the live view
+pub struct FrameCapture {
+    pub size: CaptureSize,
+    pub custom_size: [u32; 2],
+    // Keeps the last `clip_seconds` around so they can be saved after the fact
+    pub clip_enabled: bool,
+    pub clip_seconds: f32,
+    pub clip_fps: u32,
+    pub clip_format: ClipFormat,
+    // Screen format, what the visualizer frames are drawn for
+    target_format: Option<wgpu::TextureFormat>,
+    screenshot_requested: bool,
+    // Frames left before a requested screenshot gets taken
+    screenshot_warmup: u32,
+    since_clip_frame: f32,
+    pending: Vec<PendingCapture>,
+    // Targets whose readback is done, reused while the size stays the same
+    spare_targets: Vec<Arc<CaptureTarget>>,
+    clip: VecDeque<Arc<CapturedFrame>>,
+    clip_bytes: usize,
+    // Results from the background writers
+    status_sender: mpsc::Sender<String>,
+    status_receiver: mpsc::Receiver<String>,
+    status: Option<String>,
+}
+
+impl FrameCapture {
+    pub fn new(target_format: Option<wgpu::TextureFormat>) -> Self {
+        let (status_sender, status_receiver) = mpsc::channel();
+        FrameCapture {
+            size: CaptureSize::Display,
+            custom_size: DEFAULT_CUSTOM_SIZE,
+            clip_enabled: false,
+            clip_seconds: 5.0,
+            clip_fps: 15,
+            clip_format: ClipFormat::Gif,
+            target_format,
+            screenshot_requested: false,
+            screenshot_warmup: 0,
+            since_clip_frame: 0.0,
+            pending: Vec::new(),
+            spare_targets: Vec::new(),
+            clip: VecDeque::new(),
+            clip_bytes: 0,
+            status_sender,
+            status_receiver,
+            status: None,
+        }
+    }
+
+    pub fn request_screenshot(&mut self) {
+        if self.target_format.is_none() {
+            self.status = Some("Screenshots need the wgpu renderer".into());
+            return;
+        }
+        self.screenshot_requested = true;
+        // With the clip buffer on the view is at the custom size already
+        self.screenshot_warmup = if self.size == CaptureSize::Custom && !self.clip_enabled {
+            SCREENSHOT_WARMUP_FRAMES
+        } else {
+            0
+        };
+    }
+
+    // Writes what's in the clip buffer out in the background
+    pub fn save_clip(&mut self) {
+        if self.clip.is_empty() {
+            self.status = Some("Nothing recorded yet, turn on the clip buffer first".into());
+            return;
+        }
+        let frames: Vec<_> = self.clip.iter().cloned().collect();
+        let (format, fps) = (self.clip_format, self.clip_fps);
+        let extension = match format {
+            ClipFormat::Gif => ".gif",
+            ClipFormat::PngSequence => "",
+        };
+        let path = capture_path("clip", extension);
+        self.status = Some(format!(
+            "Saving {} frames to {}",
+            frames.len(),
+            path.display()
+        ));
+        let sender = self.status_sender.clone();
+        std::thread::spawn(move || {
+            let result = match format {
+                ClipFormat::Gif => write_gif(&path, &frames, fps),
+                ClipFormat::PngSequence => write_png_sequence(&path, &frames),
+            };
+            let _ = sender.send(match result {
+                Ok(()) => format!("Saved {}", path.display()),
+                Err(e) => format!("Failed to save clip: {:#}", e),
+            });
+        });
+    }
+
+    // Where the view goes inside the space it was given, letterboxed for custom sizes
+    pub fn view_rect(&self, available: egui::Rect) -> egui::Rect {
+        if self.size == CaptureSize::Display {
+            return available;
+        }
+        let aspect = self.custom_size[0] as f32 / self.custom_size[1] as f32;
+        let size = if available.width() / available.height() > aspect {
+            egui::vec2(available.height() * aspect, available.height())
+        } else {
+            egui::vec2(available.width(), available.width() / aspect)
+        };
+        egui::Rect::from_center_size(available.center(), size)
+    }
+
+    // Size the frame should be rendered at, given the rect's size in pixels. Custom sizes only
+    // apply while something is being captured, the rest of the time the view renders at
+    // display size rather than keeping up to 8192x8192 worth of targets busy for nothing.
+    pub fn frame_size(&self, display_size: [u32; 2]) -> [u32; 2] {
+        match self.size {
+            CaptureSize::Custom if self.clip_enabled || self.screenshot_requested => {
+                self.custom_size.map(|side| side.max(1))
+            }
+            _ => display_size,
+        }
+    }
+
+    // Collects finished readbacks and advances the clip clock, once per app update
+    pub fn update(&mut self, device: &wgpu::Device, dt: f32) {
+        if self.clip_enabled {
+            self.since_clip_frame += dt;
+        } else {
+            self.since_clip_frame = 0.0;
+            self.clear_clip();
+        }
+        while let Ok(status) = self.status_receiver.try_recv() {
+            self.status = Some(status);
+        }
+        if self.pending.is_empty() {
+            return;
+        }
+
+        // The previous update's frame has been submitted by now, so its copies can be mapped
+        for pending in &mut self.pending {
+            if pending.mapped.is_none() && pending.target.recorded.load(Ordering::Acquire) {
+                let (sender, receiver) = mpsc::channel();
+                pending
+                    .target
+                    .buffer
+                    .slice(..)
+                    .map_async(wgpu::MapMode::Read, move |result| {
+                        let _ = sender.send(result);
+                    });
+                pending.mapped = Some(receiver);
+            }
+            pending.frames_waited += 1;
+        }
+        device.poll(wgpu::Maintain::Poll);
+
+        let mut finished = Vec::new();
+        let spare_targets = &mut self.spare_targets;
+        let mut undrawn_screenshot = false;
+        self.pending.retain(|pending| {
+            let reusable = match &pending.mapped {
+                Some(receiver) => match receiver.try_recv() {
+                    Ok(Ok(())) => {
+                        finished.push((pending.target.read(), pending.screenshot, pending.clip));
+                        true
+                    }
+                    Ok(Err(e)) => {
+                        tracing::warn!("Failed to read back a captured frame: {}", e);
+                        false
+                    }
+                    Err(mpsc::TryRecvError::Empty) => return true,
+                    Err(mpsc::TryRecvError::Disconnected) => false,
+                },
+                // Never drawn, nothing is coming
+                None if pending.frames_waited < 2 => return true,
+                None => {
+                    undrawn_screenshot |= pending.screenshot;
+                    true
+                }
+            };
+            // A paint callback still holding on to it could record into it again
+            let unshared = Arc::strong_count(&pending.target) == 1;
+            if reusable && unshared && spare_targets.len() < MAX_PENDING_CAPTURES {
+                pending.target.recorded.store(false, Ordering::Release);
+                spare_targets.push(pending.target.clone());
+            }
+            false
+        });
+        if undrawn_screenshot {
+            self.status = Some("Screenshot failed, the frame was never drawn".into());
+        }
+
+        for (frame, screenshot, clip) in finished {
+            let frame = Arc::new(frame);
+            if screenshot {
+                self.save_screenshot(frame.clone());
+            }
+            if clip && self.clip_enabled {
+                // A clip can't change size halfway, start over after a resize
+                if self.clip.back().is_some_and(|last| last.size != frame.size) {
+                    self.clear_clip();
+                }
+                self.clip_bytes += frame.pixels.len();
+                self.clip.push_back(frame);
+                while self.clip.len() > self.clip_frame_limit() || self.clip_bytes > MAX_CLIP_BYTES
+                {
+                    let Some(dropped) = self.clip.pop_front() else {
+                        break;
+                    };
+                    self.clip_bytes -= dropped.pixels.len();
+                }
+            }
+        }
+    }
+
+    // Target to draw this frame into as well, when a screenshot or clip frame is due
+    pub fn take_target(
+        &mut self,
+        device: &wgpu::Device,
+        size: [u32; 2],
+    ) -> Option<Arc<CaptureTarget>> {
+        let format = self.target_format?;
+        let interval = 1.0 / self.clip_fps.max(1) as f32;
+        let clip = self.clip_enabled
+            && self.since_clip_frame >= interval
+            && self.pending.len() < MAX_PENDING_CAPTURES;
+        let screenshot = self.screenshot_requested && self.screenshot_warmup == 0;
+        if screenshot {
+            self.screenshot_requested = false;
+        } else if self.screenshot_requested {
+            self.screenshot_warmup -= 1;
+        }
+        if !clip && !screenshot {
+            return None;
+        }
+        if clip {
+            // Keep the remainder so the rate holds, but don't try to catch up after a stall
+            self.since_clip_frame = (self.since_clip_frame - interval).min(interval);
+        }
+        // Spares of an old size won't fit again until the next resize back, not worth keeping
+        self.spare_targets.retain(|target| target.size == size);
+        let target = self
+            .spare_targets
+            .pop()
+            .unwrap_or_else(|| Arc::new(CaptureTarget::new(device, size, format)));
+        self.pending.push(PendingCapture {
+            target: target.clone(),
+            screenshot,
+            clip,
+            mapped: None,
+            frames_waited: 0,
+        });
+        Some(target)
+    }
+
+    pub fn settings_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui
+                .button("Screenshot")
+                .on_hover_text("Saves the current frame as a PNG (F9)")
+                .clicked()
+            {
+                self.request_screenshot();
+            }
+            if ui
+                .add_enabled(!self.clip.is_empty(), egui::Button::new("Save Clip"))
+                .on_hover_text("Saves the clip buffer (F10)")
+                .clicked()
+            {
+                self.save_clip();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Size:");
+            egui::ComboBox::from_id_source("capture_size")
+                .selected_text(self.size.label())
+                .show_ui(ui, |ui| {
+                    for size in CaptureSize::ALL {
+                        ui.selectable_value(&mut self.size, size, size.label());
+                    }
+                });
+            if self.size == CaptureSize::Custom {
+                ui.add(egui::DragValue::new(&mut self.custom_size[0]).clamp_range(16..=8192));
+                ui.label("x");
+                ui.add(egui::DragValue::new(&mut self.custom_size[1]).clamp_range(16..=8192));
+            }
+        });
+        ui.checkbox(&mut self.clip_enabled, "Clip buffer")
+            .on_hover_text("Keeps the last few seconds in memory so they can be saved");
+        ui.add_enabled_ui(self.clip_enabled, |ui| {
+            ui.add(
+                egui::Slider::new(&mut self.clip_seconds, 1.0..=MAX_CLIP_SECONDS)
+                    .text("Length")
+                    .suffix(" s"),
+            );
+            ui.add(egui::Slider::new(&mut self.clip_fps, 5..=30).text("FPS"));
+            ui.horizontal(|ui| {
+                ui.label("Format:");
+                egui::ComboBox::from_id_source("clip_format")
+                    .selected_text(self.clip_format.label())
+                    .show_ui(ui, |ui| {
+                        for format in ClipFormat::ALL {
+                            ui.selectable_value(&mut self.clip_format, format, format.label());
+                        }
+                    });
+            });
+            ui.label(format!(
+                "{:.1} s buffered, {} / {} MB",
+                self.clip.len() as f32 / self.clip_fps.max(1) as f32,
+                self.clip_bytes / (1024 * 1024),
+                MAX_CLIP_BYTES / (1024 * 1024)
+            ))
+            .on_hover_text(
+                "Large sizes hit the memory limit before the full length, lower the size or FPS to keep more",
+            );
+        });
+        if let Some(status) = &self.status {
+            ui.label(status);
+        }
+    }
+
+    fn clear_clip(&mut self) {
+        self.clip.clear();
+        self.clip_bytes = 0;
+    }
+
+    fn clip_frame_limit(&self) -> usize {
+        (self.clip_seconds.clamp(1.0, MAX_CLIP_SECONDS) * self.clip_fps as f32).ceil() as usize
+    }
+
+    fn save_screenshot(&mut self, frame: Arc<CapturedFrame>) {
+        let path = capture_path("screenshot", ".png");
+        let sender = self.status_sender.clone();
+        std::thread::spawn(move || {
+            let result = std::fs::create_dir_all(CAPTURE_DIRECTORY)
+                .context("Failed to create the captures directory")
+                .and_then(|_| write_png(&path, frame.size, &frame.pixels));
+            let _ = sender.send(match result {
+                Ok(()) => format!("Saved {}", path.display()),
+                Err(e) => format!("Failed to save screenshot: {:#}", e),
+            });
+        });
+    }
+}
+
+// `pixels` is tightly packed RGBA
+pub fn write_png(path: &Path, size: [u32; 2], pixels: &[u8]) -> Result<()> {
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), size[0], size[1]);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()?
+        .write_image_data(pixels)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn write_png_sequence(directory: &Path, frames: &[Arc<CapturedFrame>]) -> Result<()> {
+    std::fs::create_dir_all(directory)
+        .with_context(|| format!("Failed to create {}", directory.display()))?;
+    for (index, frame) in frames.iter().enumerate() {
+        let path = directory.join(format!("frame_{:06}.png", index));
+        write_png(&path, frame.size, &frame.pixels)?;
+    }
+    Ok(())
+}
+
+fn write_gif(path: &Path, frames: &[Arc<CapturedFrame>], fps: u32) -> Result<()> {
+    std::fs::create_dir_all(CAPTURE_DIRECTORY)
+        .context("Failed to create the captures directory")?;
+    let Some(first) = frames.first() else {
+        return Ok(());
+    };
+    let [width, height] = first.size;
+    let scale = (GIF_MAX_WIDTH as f32 / width as f32).min(1.0);
+    let gif_size = [
+        ((width as f32 * scale) as u32).max(1),
+        ((height as f32 * scale) as u32).max(1),
+    ];
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut encoder = gif::Encoder::new(
+        BufWriter::new(file),
+        gif_size[0] as u16,
+        gif_size[1] as u16,
+        &[],
+    )?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+    // GIF delays are in hundredths of a second
+    let delay = (100.0 / fps.max(1) as f32).round() as u16;
+    for frame in frames {
+        let mut pixels = downscale(frame, gif_size);
+        let mut gif_frame =
+            gif::Frame::from_rgba_speed(gif_size[0] as u16, gif_size[1] as u16, &mut pixels, 10);
+        gif_frame.delay = delay;
+        encoder
+            .write_frame(&gif_frame)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+// Nearest neighbour, the palette reduction afterwards hides the difference to anything nicer
+fn downscale(frame: &CapturedFrame, size: [u32; 2]) -> Vec<u8> {
+    if frame.size == size {
+        return frame.pixels.clone();
+    }
+    let [width, height] = frame.size;
+    let mut pixels = Vec::with_capacity(size[0] as usize * size[1] as usize * 4);
+    for y in 0..size[1] {
+        let source_y = (y as u64 * height as u64 / size[1] as u64) as usize;
+        for x in 0..size[0] {
+            let source_x = (x as u64 * width as u64 / size[0] as u64) as usize;
+            let offset = (source_y * width as usize + source_x) * 4;
+            pixels.extend_from_slice(&frame.pixels[offset..offset + 4]);
+        }
+    }
+    pixels
+}
+
+// captures/<prefix>-<unix milliseconds><extension>, unique enough for one person clicking
+fn capture_path(prefix: &str, extension: &str) -> PathBuf {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis());
+    Path::new(CAPTURE_DIRECTORY).join(format!("{}-{}{}", prefix, millis, extension))
+}
@@ -0,0 +1,114 @@
+use crate::visualization::post_process::ToneMapping;
+use crate::visualization::renderer::SpriteBlend;
+
+// Software render target for machines without a GPU. Colour is linear and unclamped
+// like the HDR texture the GPU path draws into, the background is black.
+pub struct CpuCanvas {
+    width: u32,
+    height: u32,
+    color: Vec<[f32; 3]>,
+    // Clip space depth, 1 is the far plane
+    depth: Vec<f32>,
+}
+
+// One camera facing sprite, already projected into pixels
+pub struct Splat {
+    // Centre of the sprite, y down from the top left corner
+    pub center: [f32; 2],
+    // Distance from the centre to the sprite's edges
+    pub half_size: [f32; 2],
+    // Clip space depth, only used when depth testing
+    pub depth: f32,
+    pub color: [f32; 3],
+    // Multiplied with the coverage for each pixel
+    pub alpha: f32,
+}
+
+impl CpuCanvas {
+    pub fn new(size: [u32; 2]) -> Self {
+        let [width, height] = size;
+        let pixel_count = width as usize * height as usize;
+        CpuCanvas {
+            width,
+            height,
+            color: vec![[0.0; 3]; pixel_count],
+            depth: vec![1.0; pixel_count],
+        }
+    }
+
+    pub fn size(&self) -> [u32; 2] {
+        [self.width, self.height]
+    }
+
+    pub fn clear(&mut self) {
+        self.color.fill([0.0; 3]);
+        self.depth.fill(1.0);
+    }
+
+    // Covers every pixel whose centre lands inside the sprite, like the GPU without MSAA.
+    // `coverage` gets the position across the sprite (-1..1, y up) and returns the
+    // sprite's falloff there, or None where the fragment shader would discard.
+    pub fn splat(
+        &mut self,
+        splat: &Splat,
+        blend: SpriteBlend,
+        depth_test: bool,
+        coverage: impl Fn([f32; 2]) -> Option<f32>,
+    ) {
+        let [cx, cy] = splat.center;
+        let [hx, hy] = splat.half_size;
+        if hx <= 0.0 || hy <= 0.0 {
+            return;
+        }
+        // Pixel centres sit at +0.5
+        let first_x = (cx - hx - 0.5).ceil().max(0.0) as u32;
+        let first_y = (cy - hy - 0.5).ceil().max(0.0) as u32;
+        let end_x = ((cx + hx - 0.5).floor() + 1.0).clamp(0.0, self.width as f32) as u32;
+        let end_y = ((cy + hy - 0.5).floor() + 1.0).clamp(0.0, self.height as f32) as u32;
+
+        for y in first_y..end_y {
+            let local_y = (cy - (y as f32 + 0.5)) / hy;
+            for x in first_x..end_x {
+                let local_x = (x as f32 + 0.5 - cx) / hx;
+                let Some(falloff) = coverage([local_x, local_y]) else {
+                    continue;
+                };
+                let alpha = splat.alpha * falloff;
+                let index = y as usize * self.width as usize + x as usize;
+                if depth_test {
+                    if splat.depth >= self.depth[index] {
+                        continue;
+                    }
+                    self.depth[index] = splat.depth;
+                }
+                let pixel = &mut self.color[index];
+                for (channel, &source) in pixel.iter_mut().zip(&splat.color) {
+                    *channel = match blend {
+                        SpriteBlend::Additive => source * alpha + *channel,
+                        SpriteBlend::Alpha => source * alpha + *channel * (1.0 - alpha),
+                    };
+                }
+            }
+        }
+    }
+
+    // Tone maps and encodes to sRGB, rows top down. Same bytes layout the GPU readback gives.
+    pub fn to_rgba8(&self, tone_mapping: ToneMapping, exposure: f32) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(self.color.len() * 4);
+        for &color in &self.color {
+            let [r, g, b] = tone_mapping.apply(color, exposure).map(linear_to_srgb);
+            pixels.extend_from_slice(&[r, g, b, 255]);
+        }
+        pixels
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let encoded = if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
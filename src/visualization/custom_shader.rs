@@ -0,0 +1,432 @@
+use crate::audio::{AudioAnalysisData, PlaybackState};
+use crate::visualization::beat::BeatClock;
+use crate::visualization::shader_source::{capture_validation, shader_path, WatchedShader};
+use crate::visualization::spectrum::SpectrumBands;
+use crate::visualization::visualizer::{Visualizer, VisualizerFrame, VisualizerInput};
+use anyhow::{anyhow, Result};
+use bytemuck::{Pod, Zeroable};
+use eframe::egui;
+use std::sync::Arc;
+use wgpu::util::DeviceExt;
+
+// Where looks are loaded from, relative to the working directory
+const LOOKS_DIRECTORY: &str = "shaders/looks";
+const DEFAULT_LOOK: &str = "plasma.wgsl";
+// Drawn until a look from disk compiles, so the mode works without the directory too
+const BUILTIN_LOOK_WGSL: &str = include_str!("../../shaders/looks/plasma.wgsl");
+const INPUT_BANDS: usize = 8;
+const INPUT_SPECTRUM_BANDS: usize = 64;
+
+// What looks can read, documented for artists. Shown in the settings as is.
+const INPUTS_WGSL: &str = r#"// Available to every look as `inputs`
+struct Inputs {
+    // Seconds since the mode started
+    time: f32,
+    // Seconds since the previous frame
+    delta_time: f32,
+    // Levels of the latest analysis window, 0 while not playing
+    rms: f32,
+    peak: f32,
+    // 0 on a beat, rising to 1 when the next one is due
+    beat_phase: f32,
+    // Tempo the beat phase runs at, 0 until two beats were seen
+    bpm: f32,
+    // Render target size in pixels
+    resolution: vec2<f32>,
+    // Valid entries in `bands`
+    band_count: u32,
+    // Crossover bands, lowest first: x = rms, y = peak, z = onset
+    bands: array<vec4<f32>, 8>,
+    // 64 log spaced spectrum levels in 0..1, 4 per entry. Use `spectrum_at`.
+    spectrum: array<vec4<f32>, 16>,
+};
+@group(0) @binding(0)
+var<uniform> inputs: Inputs;
+
+// Spectrum level at `x` (0 = 30 Hz, 1 = 16 kHz), interpolated between bands
+fn spectrum_at(x: f32) -> f32
+
+// A look implements this, `uv` is 0..1 with y up and `frag_coord` is in
+// pixels with the origin at the bottom left (like Shadertoy's fragCoord):
+//
+// fn look(uv: vec2<f32>, frag_coord: vec2<f32>) -> vec4<f32>
+//
+// `look_vertex` and `look_fragment` are taken by the entry points.
+"#;
+
+// Appended after the look's source, so line numbers in errors match the file
+const PRELUDE_WGSL: &str = r#"
+struct Inputs {
+    time: f32,
+    delta_time: f32,
+    rms: f32,
+    peak: f32,
+    beat_phase: f32,
+    bpm: f32,
+    resolution: vec2<f32>,
+    band_count: u32,
+    bands: array<vec4<f32>, 8>,
+    spectrum: array<vec4<f32>, 16>,
+};
+@group(0) @binding(0)
+var<uniform> inputs: Inputs;
+
+fn spectrum_band(i: u32) -> f32 {
+    let index = min(i, 63u);
+    return inputs.spectrum[index / 4u][index % 4u];
+}
+
+fn spectrum_at(x: f32) -> f32 {
+    let position = clamp(x, 0.0, 1.0) * 63.0;
+    let i = u32(floor(position));
+    return mix(spectrum_band(i), spectrum_band(i + 1u), fract(position));
+}
+
+struct LookVertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// One triangle covering the whole target
+@vertex
+fn look_vertex(@builtin(vertex_index) vertex_index: u32) -> LookVertexOutput {
+    let position = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u)) * 2.0 - 1.0;
+    var out: LookVertexOutput;
+    out.clip_position = vec4<f32>(position, 0.0, 1.0);
+    out.uv = position * 0.5 + 0.5;
+    return out;
+}
+
+@fragment
+fn look_fragment(in: LookVertexOutput) -> @location(0) vec4<f32> {
+    let frag_coord = vec2<f32>(in.clip_position.x, inputs.resolution.y - in.clip_position.y);
+    return look(in.uv, frag_coord);
+}
+"#;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct ShaderInputsUniform {
+    time: f32,
+    delta_time: f32,
+    rms: f32,
+    peak: f32,
+    beat_phase: f32,
+    bpm: f32,
+    resolution: [f32; 2],
+    band_count: u32,
+    // WGSL aligns the arrays to 16 bytes
+    _padding: [u32; 3],
+    bands: [[f32; 4]; INPUT_BANDS],
+    spectrum: [[f32; 4]; INPUT_SPECTRUM_BANDS / 4],
+}
+
+pub struct CustomShaderPrimitive {
+    inputs_uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline_layout: wgpu::PipelineLayout,
+    target_format: wgpu::TextureFormat,
+}
+
+impl CustomShaderPrimitive {
+    // Compiles a look, validation errors come back as text instead of panicking
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        look_source: &str,
+    ) -> Result<wgpu::RenderPipeline, String> {
+        let source = format!("{}\n{}", look_source, PRELUDE_WGSL);
+        capture_validation(device, || {
+            let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Custom Shader Look"),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Custom Shader Render Pipeline"),
+                layout: Some(&self.pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader_module,
+                    entry_point: "look_vertex",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader_module,
+                    entry_point: "look_fragment",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: self.target_format,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        })
+    }
+}
+
+// Full screen fragment shaders ("looks") loaded from disk and reloaded when saved
+pub struct CustomShader {
+    primitive: Option<Arc<CustomShaderPrimitive>>,
+    device: Option<Arc<wgpu::Device>>,
+    // Last look that compiled, kept while the file on disk is broken
+    pipeline: Option<Arc<wgpu::RenderPipeline>>,
+    watched: WatchedShader,
+    // File names in LOOKS_DIRECTORY, refreshed by the settings
+    available_looks: Vec<String>,
+    // Why the file on disk isn't what's showing, if it isn't
+    error: Option<String>,
+    spectrum: SpectrumBands,
+    beat: BeatClock,
+    time: f32,
+    inputs: ShaderInputsUniform,
+    // Onset level that counts as a beat for `beat_phase`
+    pub beat_threshold: f32,
+}
+
+impl CustomShader {
+    pub fn new() -> Self {
+        CustomShader {
+            primitive: None,
+            device: None,
+            pipeline: None,
+            watched: WatchedShader::new(shader_path(LOOKS_DIRECTORY).join(DEFAULT_LOOK)),
+            available_looks: list_looks(),
+            error: None,
+            spectrum: SpectrumBands::new(INPUT_SPECTRUM_BANDS),
+            beat: BeatClock::new(),
+            time: 0.0,
+            inputs: ShaderInputsUniform::zeroed(),
+            beat_threshold: 1.5,
+        }
+    }
+
+    fn current_look(&self) -> String {
+        self.watched
+            .path()
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+    }
+
+    fn load_look(&mut self, name: &str) {
+        self.watched = WatchedShader::new(shader_path(LOOKS_DIRECTORY).join(name));
+        self.error = None;
+    }
+
+    fn update_inputs(&mut self, analysis: Option<&AudioAnalysisData>, dt: f32) {
+        self.spectrum.update(analysis);
+        self.beat.update(analysis, self.beat_threshold, dt);
+
+        let mut bands = [[0.0; 4]; INPUT_BANDS];
+        let band_levels = analysis.map_or(&[][..], |data| &data.bands[..]);
+        for (slot, band) in bands.iter_mut().zip(band_levels) {
+            *slot = [band.rms, band.peak, band.onset, 0.0];
+        }
+        let mut spectrum = [[0.0; 4]; INPUT_SPECTRUM_BANDS / 4];
+        for (i, &level) in self.spectrum.values().iter().enumerate() {
+            spectrum[i / 4][i % 4] = level;
+        }
+
+        self.inputs = ShaderInputsUniform {
+            time: self.time,
+            delta_time: dt,
+            rms: analysis.map_or(0.0, |data| data.rms_amplitude),
+            peak: analysis.map_or(0.0, |data| data.peak_amplitude),
+            beat_phase: self.beat.phase(),
+            bpm: self.beat.bpm(),
+            // Filled in by the frame, only it knows the target size
+            resolution: [0.0; 2],
+            band_count: band_levels.len().min(INPUT_BANDS) as u32,
+            _padding: [0; 3],
+            bands,
+            spectrum,
+        };
+    }
+}
+
+impl Visualizer for CustomShader {
+    fn name(&self) -> &'static str {
+        "Custom Shader"
+    }
+
+    fn prepare(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        target_format: wgpu::TextureFormat,
+    ) -> Result<()> {
+        if self.primitive.is_some() {
+            return Ok(());
+        }
+        tracing::info!("Preparing Custom Shader resources...");
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Custom Shader Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let inputs_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Custom Shader Inputs Uniform Buffer"),
+            contents: bytemuck::bytes_of(&self.inputs),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Custom Shader Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: inputs_uniform_buffer.as_entire_binding(),
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Custom Shader Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let primitive = CustomShaderPrimitive {
+            inputs_uniform_buffer,
+            bind_group,
+            pipeline_layout,
+            target_format,
+        };
+        let pipeline = primitive
+            .create_pipeline(device, BUILTIN_LOOK_WGSL)
+            .map_err(|e| anyhow!("Built-in look failed to compile: {}", e))?;
+
+        self.pipeline = Some(Arc::new(pipeline));
+        self.primitive = Some(Arc::new(primitive));
+        self.device = Some(device.clone());
+        tracing::info!("Custom Shader resources prepared successfully.");
+        Ok(())
+    }
+
+    fn update(&mut self, input: &VisualizerInput, dt: f32) {
+        self.time += dt;
+        self.update_inputs(
+            input
+                .analysis
+                .filter(|_| input.playback_state == PlaybackState::Playing),
+            dt,
+        );
+
+        let (Some(primitive), Some(device)) = (&self.primitive, &self.device) else {
+            return;
+        };
+        match self.watched.poll(dt) {
+            Some(Ok(source)) => match primitive.create_pipeline(device, &source) {
+                Ok(pipeline) => {
+                    tracing::info!("Loaded look {}", self.watched.path().display());
+                    self.pipeline = Some(Arc::new(pipeline));
+                    self.error = None;
+                }
+                Err(e) => {
+                    tracing::warn!("Look {} failed to compile", self.watched.path().display());
+                    self.error = Some(e);
+                }
+            },
+            Some(Err(e)) => self.error = Some(e),
+            None => {}
+        }
+    }
+
+    fn paint(&mut self, _aspect_ratio: f32) -> Option<Box<dyn VisualizerFrame>> {
+        Some(Box::new(CustomShaderFrame {
+            primitive: self.primitive.clone()?,
+            pipeline: self.pipeline.clone()?,
+            inputs: self.inputs,
+        }))
+    }
+
+    fn settings_ui(&mut self, ui: &mut egui::Ui) {
+        let mut selected = self.current_look();
+        ui.horizontal(|ui| {
+            ui.label("Look:");
+            egui::ComboBox::from_id_source("custom_shader_look")
+                .selected_text(selected.as_str())
+                .show_ui(ui, |ui| {
+                    for look in &self.available_looks {
+                        ui.selectable_value(&mut selected, look.clone(), look.as_str());
+                    }
+                });
+            if ui.small_button("Rescan").clicked() {
+                self.available_looks = list_looks();
+            }
+            if ui.small_button("Reload").clicked() {
+                self.watched.reload();
+            }
+        });
+        if selected != self.current_look() {
+            self.load_look(&selected);
+        }
+        ui.label(format!(
+            "Edit {} and save, it reloads on its own.",
+            self.watched.path().display()
+        ));
+        ui.add(egui::Slider::new(&mut self.beat_threshold, 0.2..=5.0).text("Beat threshold"));
+        egui::CollapsingHeader::new("Shader inputs")
+            .id_source("custom_shader_inputs")
+            .show(ui, |ui| {
+                ui.code(INPUTS_WGSL);
+            });
+    }
+
+    fn overlay_message(&self) -> Option<String> {
+        self.error.clone()
+    }
+}
+
+struct CustomShaderFrame {
+    primitive: Arc<CustomShaderPrimitive>,
+    pipeline: Arc<wgpu::RenderPipeline>,
+    // Resolution gets filled in once the target size is known
+    inputs: ShaderInputsUniform,
+}
+
+impl VisualizerFrame for CustomShaderFrame {
+    fn prepare(
+        &self,
+        _device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        _encoder: &mut wgpu::CommandEncoder,
+        size_in_pixels: [u32; 2],
+    ) {
+        let mut inputs = self.inputs;
+        inputs.resolution = size_in_pixels.map(|size| size as f32);
+        queue.write_buffer(
+            &self.primitive.inputs_uniform_buffer,
+            0,
+            bytemuck::bytes_of(&inputs),
+        );
+    }
+
+    fn paint<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, _queue: &wgpu::Queue) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.primitive.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+// WGSL files in LOOKS_DIRECTORY, sorted by name
+fn list_looks() -> Vec<String> {
+    let mut looks: Vec<String> = std::fs::read_dir(shader_path(LOOKS_DIRECTORY))
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .filter(|name| name.ends_with(".wgsl"))
+                .collect()
+        })
+        .unwrap_or_default();
+    looks.sort();
+    looks
+}
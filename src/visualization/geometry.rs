@@ -0,0 +1,334 @@
+use bytemuck::{Pod, Zeroable};
+use glam::Vec3;
+use std::collections::HashMap;
+use std::f32::consts::{PI, TAU};
+
+const GOLDEN_RATIO_CONJUGATE: f32 = 0.618_034; // (sqrt(5) - 1) / 2
+
+// One point of a generated shape, laid out exactly like the vertex buffer.
+// `normal` is the direction audio displacement pushes the point in, `uv` is where the point
+// sits on the shape's surface (both 0..1) and is what the spectrum mappings read. By
+// convention `v` runs "top to bottom" (v = 0 is where bass goes) and `u` runs around.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct GeometryPoint {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+impl GeometryPoint {
+    fn new(position: Vec3, normal: Vec3, u: f32, v: f32) -> Self {
+        GeometryPoint {
+            position: position.to_array(),
+            normal: normal.to_array(),
+            uv: [u, v],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    Sphere,
+    Torus,
+    TorusKnot,
+    Icosphere,
+    CubeLattice,
+    Helix,
+    Lorenz,
+    Terrain,
+}
+
+impl Shape {
+    pub const ALL: [Shape; 8] = [
+        Shape::Sphere,
+        Shape::Torus,
+        Shape::TorusKnot,
+        Shape::Icosphere,
+        Shape::CubeLattice,
+        Shape::Helix,
+        Shape::Lorenz,
+        Shape::Terrain,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Shape::Sphere => "Sphere",
+            Shape::Torus => "Torus",
+            Shape::TorusKnot => "Torus Knot",
+            Shape::Icosphere => "Icosphere",
+            Shape::CubeLattice => "Cube Lattice",
+            Shape::Helix => "Helix",
+            Shape::Lorenz => "Lorenz Attractor",
+            Shape::Terrain => "Terrain Grid",
+        }
+    }
+
+    // Generates roughly `num_points` points that fit inside `radius`. Shapes built on a
+    // lattice (icosphere, cube, grid) round down to the nearest full lattice.
+    pub fn generate(&self, radius: f32, num_points: usize) -> Vec<GeometryPoint> {
+        match self {
+            Shape::Sphere => generate_sphere_points_fibonacci(radius, num_points)
+                .into_iter()
+                .map(|p| sphere_point(Vec3::from(p).normalize_or_zero(), radius))
+                .collect(),
+            Shape::Torus => {
+                let major = radius * 0.7;
+                sweep_tube(num_points, radius * 0.3, |t| {
+                    Vec3::new((t * TAU).cos(), 0.0, (t * TAU).sin()) * major
+                })
+            }
+            Shape::TorusKnot => generate_torus_knot(radius, num_points),
+            Shape::Icosphere => generate_icosphere(radius, num_points),
+            Shape::CubeLattice => generate_cube_lattice(radius, num_points),
+            Shape::Helix => generate_helix(radius, num_points),
+            Shape::Lorenz => generate_lorenz(radius, num_points),
+            Shape::Terrain => generate_terrain(radius, num_points),
+        }
+    }
+}
+
+pub fn generate_sphere_points_fibonacci(radius: f32, num_points: usize) -> Vec<[f32; 3]> {
+    let mut points = Vec::with_capacity(num_points);
+    for i in 0..num_points {
+        let y = 1.0 - (i as f32 / (num_points - 1) as f32) * 2.0; // `y` has a range of 1 to -1
+        let r = (1.0 - y * y).sqrt(); // radius at y
+        let theta = (i as f32 * GOLDEN_RATIO_CONJUGATE) * std::f32::consts::TAU; // tau is 2*PI
+
+        let x = (theta.cos() * r) * radius;
+        let z = (theta.sin() * r) * radius;
+        points.push([x, y * radius, z]);
+    }
+
+    // Return vec of 3D points representing locations on a sphere's surface
+    points
+}
+
+// Point on a sphere from its unit normal, with latitude / longitude as uv
+fn sphere_point(normal: Vec3, radius: f32) -> GeometryPoint {
+    let u = (normal.z.atan2(normal.x) / TAU).rem_euclid(1.0);
+    let v = normal.y.clamp(-1.0, 1.0).acos() / PI;
+    GeometryPoint::new(normal * radius, normal, u, v)
+}
+
+// Spreads points over a tube of `tube_radius` around `curve` (t in 0..1, closed or not).
+// `v` runs along the curve, `u` around the tube.
+fn sweep_tube(
+    num_points: usize,
+    tube_radius: f32,
+    curve: impl Fn(f32) -> Vec3,
+) -> Vec<GeometryPoint> {
+    // Step for the finite differences, small against any of the curves we sweep
+    const EPSILON: f32 = 1e-3;
+
+    (0..num_points)
+        .map(|i| {
+            let t = i as f32 / num_points as f32;
+            // Golden ratio spacing around the tube, so consecutive points don't line up
+            let u = (i as f32 * GOLDEN_RATIO_CONJUGATE).fract();
+
+            let center = curve(t);
+            let before = curve(t - EPSILON);
+            let after = curve(t + EPSILON);
+            let tangent = (after - before).normalize_or_zero();
+            let acceleration = after + before - 2.0 * center;
+            // Straight segments have no curvature to build a frame from, any perpendicular will do
+            let mut side = acceleration - tangent * acceleration.dot(tangent);
+            if side.length_squared() < 1e-12 {
+                side = tangent.any_orthonormal_vector();
+            }
+            let side = side.normalize();
+            let binormal = tangent.cross(side);
+
+            let angle = u * TAU;
+            let normal = side * angle.cos() + binormal * angle.sin();
+            GeometryPoint::new(center + normal * tube_radius, normal, u, t)
+        })
+        .collect()
+}
+
+// (2, 3) torus knot, the trefoil
+fn generate_torus_knot(radius: f32, num_points: usize) -> Vec<GeometryPoint> {
+    const P: f32 = 2.0;
+    const Q: f32 = 3.0;
+    // The knot reaches out to 3 units, scale that onto the radius with room for the tube
+    let scale = radius * 0.8 / 3.0;
+    sweep_tube(num_points, radius * 0.12, |t| {
+        let angle = t * TAU;
+        let r = (Q * angle).cos() + 2.0;
+        Vec3::new(
+            r * (P * angle).cos(),
+            -(Q * angle).sin(),
+            r * (P * angle).sin(),
+        ) * scale
+    })
+}
+
+fn generate_helix(radius: f32, num_points: usize) -> Vec<GeometryPoint> {
+    const TURNS: f32 = 5.0;
+    let coil_radius = radius * 0.6;
+    // Top to bottom, so v = 0 (bass) sits at the top like on the sphere
+    sweep_tube(num_points, radius * 0.1, |t| {
+        let angle = t * TURNS * TAU;
+        Vec3::new(
+            angle.cos() * coil_radius,
+            radius * (1.0 - 2.0 * t),
+            angle.sin() * coil_radius,
+        )
+    })
+}
+
+// Subdivided icosahedron. Every level quadruples the faces, vertex count is 10 * 4^level + 2.
+fn generate_icosphere(radius: f32, num_points: usize) -> Vec<GeometryPoint> {
+    let mut level = 0;
+    while 10 * 4usize.pow(level + 1) + 2 <= num_points {
+        level += 1;
+    }
+
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut vertices: Vec<Vec3> = [
+        [-1.0, t, 0.0],
+        [1.0, t, 0.0],
+        [-1.0, -t, 0.0],
+        [1.0, -t, 0.0],
+        [0.0, -1.0, t],
+        [0.0, 1.0, t],
+        [0.0, -1.0, -t],
+        [0.0, 1.0, -t],
+        [t, 0.0, -1.0],
+        [t, 0.0, 1.0],
+        [-t, 0.0, -1.0],
+        [-t, 0.0, 1.0],
+    ]
+    .into_iter()
+    .map(|v| Vec3::from(v).normalize())
+    .collect();
+    let mut faces: Vec<[u32; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..level {
+        // Shared edges must share their midpoint, or we'd get duplicate points
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32, vertices: &mut Vec<Vec3>| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                vertices.push(((vertices[a as usize] + vertices[b as usize]) * 0.5).normalize());
+                (vertices.len() - 1) as u32
+            })
+        };
+        faces = faces
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let ab = midpoint(a, b, &mut vertices);
+                let bc = midpoint(b, c, &mut vertices);
+                let ca = midpoint(c, a, &mut vertices);
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    vertices
+        .into_iter()
+        .map(|normal| sphere_point(normal, radius))
+        .collect()
+}
+
+// n x n x n grid filling the cube inside the sphere of `radius`, pushed outwards from the centre
+fn generate_cube_lattice(radius: f32, num_points: usize) -> Vec<GeometryPoint> {
+    let n = ((num_points as f32).cbrt().floor() as usize).max(2);
+    let half_extent = radius / 3.0f32.sqrt();
+    let coordinate = |i: usize| (i as f32 / (n - 1) as f32) * 2.0 - 1.0;
+
+    let mut points = Vec::with_capacity(n * n * n);
+    for iy in 0..n {
+        for iz in 0..n {
+            for ix in 0..n {
+                let unit = Vec3::new(coordinate(ix), coordinate(iy), coordinate(iz));
+                // The centre point (odd n) has no outwards, let it move up
+                let normal = unit.try_normalize().unwrap_or(Vec3::Y);
+                let u = ix as f32 / (n - 1) as f32;
+                let v = iy as f32 / (n - 1) as f32;
+                points.push(GeometryPoint::new(unit * half_extent, normal, u, 1.0 - v));
+            }
+        }
+    }
+    points
+}
+
+// Lorenz system traced with plain Euler steps, each step is one point
+fn generate_lorenz(radius: f32, num_points: usize) -> Vec<GeometryPoint> {
+    const SIGMA: f32 = 10.0;
+    const RHO: f32 = 28.0;
+    const BETA: f32 = 8.0 / 3.0;
+    const DT: f32 = 0.005;
+    // Steps thrown away so the trace starts on the attractor, not on the way to it
+    const WARMUP_STEPS: usize = 1000;
+
+    // The attractor spans about +-25 units around (0, 0, RHO - 1)
+    let scale = radius / 25.0;
+    let wing_offset = (BETA * (RHO - 1.0)).sqrt();
+
+    let mut state = Vec3::new(0.1, 0.0, 0.0);
+    let step = |state: &mut Vec3| {
+        let derivative = Vec3::new(
+            SIGMA * (state.y - state.x),
+            state.x * (RHO - state.z) - state.y,
+            state.x * state.y - BETA * state.z,
+        );
+        *state += derivative * DT;
+    };
+    for _ in 0..WARMUP_STEPS {
+        step(&mut state);
+    }
+
+    (0..num_points)
+        .map(|_| {
+            step(&mut state);
+            // Lorenz z is "up", and centre the butterfly on the origin
+            let position = Vec3::new(state.x, state.z - (RHO - 1.0), state.y) * scale;
+            // Points circle one of the two wing centres, so outwards is away from that one
+            let wing_sign = if state.x >= 0.0 { 1.0 } else { -1.0 };
+            let wing_center =
+                Vec3::new(wing_offset * wing_sign, 0.0, wing_offset * wing_sign) * scale;
+            let offset = position - wing_center;
+            let normal = offset.try_normalize().unwrap_or(Vec3::Y);
+            let u = (offset.z.atan2(offset.x) / TAU).rem_euclid(1.0);
+            let v = ((1.0 - position.y / radius) * 0.5).clamp(0.0, 1.0);
+            GeometryPoint::new(position, normal, u, v)
+        })
+        .collect()
+}
+
+// Flat square grid on the XZ plane, displacement lifts it into a terrain
+fn generate_terrain(radius: f32, num_points: usize) -> Vec<GeometryPoint> {
+    let n = ((num_points as f32).sqrt().floor() as usize).max(2);
+    let mut points = Vec::with_capacity(n * n);
+    for iz in 0..n {
+        for ix in 0..n {
+            let u = ix as f32 / (n - 1) as f32;
+            let v = iz as f32 / (n - 1) as f32;
+            let position = Vec3::new(u * 2.0 - 1.0, 0.0, v * 2.0 - 1.0) * radius;
+            points.push(GeometryPoint::new(position, Vec3::Y, u, v));
+        }
+    }
+    points
+}
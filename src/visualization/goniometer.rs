@@ -0,0 +1,401 @@
+use crate::visualization::visualizer::{Visualizer, VisualizerFrame, VisualizerInput};
+use anyhow::Result;
+use bytemuck::{Pod, Zeroable};
+use eframe::egui;
+use std::sync::Arc;
+use wgpu::util::DeviceExt;
+
+// Stereo pairs drawn per analysis window, longer windows are cut short
+const POINTS_PER_WINDOW: usize = 1024;
+// Older windows kept around for the phosphor trail
+const MAX_HISTORY: usize = 8;
+// Per window smoothing of the correlation meter
+const CORRELATION_SMOOTHING: f32 = 0.3;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct GoniometerParamsUniform {
+    color: [f32; 4],
+    points_per_window: u32,
+    history_capacity: u32,
+    newest: u32,
+    window_count: u32,
+    point_size: f32,
+    aspect_ratio: f32,
+    decay: f32,
+    gain: f32,
+}
+
+const GONIOMETER_SHADER_WGSL: &str = r#"
+struct GoniometerParams {
+    color: vec4<f32>,
+    points_per_window: u32,
+    history_capacity: u32,
+    newest: u32,
+    window_count: u32,
+    point_size: f32,
+    aspect_ratio: f32,
+    decay: f32,
+    gain: f32,
+};
+@group(0) @binding(0)
+var<uniform> params: GoniometerParams;
+// Ring of windows, `points_per_window` left / right pairs each
+@group(0) @binding(1)
+var<storage, read> points: array<vec2<f32>>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) local: vec2<f32>,
+    @location(1) alpha: f32,
+};
+
+const FRAC_1_SQRT_2: f32 = 0.70710678;
+
+// One small quad per stereo pair. Instances run oldest window first.
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(-1.0, 1.0),
+        vec2<f32>(-1.0, 1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+    let age = params.window_count - 1u - instance_index / params.points_per_window;
+    let slot = (params.newest + params.history_capacity - age) % params.history_capacity;
+    let lr = points[slot * params.points_per_window + instance_index % params.points_per_window];
+
+    // Rotated by 45 degrees: mid (L + R) goes up, side (R - L) goes sideways,
+    // so mono is a vertical line and a hard left signal leans to the upper left
+    let mid_side = vec2<f32>(lr.y - lr.x, lr.x + lr.y) * FRAC_1_SQRT_2 * params.gain;
+    let position = clamp(mid_side, vec2<f32>(-1.0), vec2<f32>(1.0)) + corner * params.point_size;
+    // Square plot whatever the rect's shape
+    let fit = vec2<f32>(min(1.0, 1.0 / params.aspect_ratio), min(1.0, params.aspect_ratio));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(position * fit * 0.95, 0.0, 1.0);
+    out.local = corner;
+    // pow(0, 0) is undefined in WGSL
+    out.alpha = select(pow(params.decay, f32(age)), 1.0, age == 0u);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let glow = 1.0 - smoothstep(0.2, 1.0, length(in.local));
+    return vec4<f32>(params.color.rgb, params.color.a * glow * in.alpha);
+}
+"#;
+
+pub struct GoniometerPrimitive {
+    params_uniform_buffer: wgpu::Buffer,
+    point_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    render_pipeline: wgpu::RenderPipeline,
+}
+
+// Stereo vectorscope (Lissajous / goniometer) plus a phase correlation meter
+pub struct Goniometer {
+    primitive: Option<Arc<GoniometerPrimitive>>,
+    windows: Vec<[[f32; 2]; POINTS_PER_WINDOW]>,
+    newest: usize,
+    window_count: usize,
+    last_timestamp: Option<f64>,
+    // -1 (out of phase) ... 0 (unrelated) ... +1 (mono)
+    correlation: f32,
+    // Point radius as a fraction of the plot
+    pub point_size: f32,
+    // Brightness multiplier per window back in history
+    pub decay: f32,
+    pub gain: f32,
+    pub color: [f32; 3],
+}
+
+impl Goniometer {
+    pub fn new() -> Self {
+        Goniometer {
+            primitive: None,
+            windows: vec![[[0.0; 2]; POINTS_PER_WINDOW]; MAX_HISTORY],
+            newest: 0,
+            window_count: 1,
+            last_timestamp: None,
+            correlation: 0.0,
+            point_size: 0.006,
+            decay: 0.6,
+            gain: 1.0,
+            color: [0.4, 1.0, 0.6],
+        }
+    }
+
+    fn push_window(&mut self, stereo: &[[f32; 2]]) {
+        self.newest = (self.newest + 1) % MAX_HISTORY;
+        let window = &mut self.windows[self.newest];
+        let count = stereo.len().min(POINTS_PER_WINDOW);
+        window[..count].copy_from_slice(&stereo[..count]);
+        window[count..].fill([0.0; 2]);
+        self.window_count = (self.window_count + 1).min(MAX_HISTORY);
+
+        if let Some(correlation) = phase_correlation(stereo) {
+            self.correlation += (correlation - self.correlation) * CORRELATION_SMOOTHING;
+        }
+    }
+
+    fn params(&self, aspect_ratio: f32) -> GoniometerParamsUniform {
+        GoniometerParamsUniform {
+            color: [self.color[0], self.color[1], self.color[2], 1.0],
+            points_per_window: POINTS_PER_WINDOW as u32,
+            history_capacity: MAX_HISTORY as u32,
+            newest: self.newest as u32,
+            window_count: self.window_count as u32,
+            point_size: self.point_size,
+            aspect_ratio,
+            decay: self.decay,
+            gain: self.gain,
+        }
+    }
+}
+
+impl Visualizer for Goniometer {
+    fn name(&self) -> &'static str {
+        "Goniometer"
+    }
+
+    fn prepare(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        target_format: wgpu::TextureFormat,
+    ) -> Result<()> {
+        if self.primitive.is_some() {
+            return Ok(());
+        }
+        tracing::info!("Preparing Goniometer resources...");
+
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Goniometer Shader"),
+            source: wgpu::ShaderSource::Wgsl(GONIOMETER_SHADER_WGSL.into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Goniometer Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let params_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Goniometer Params Uniform Buffer"),
+            contents: bytemuck::bytes_of(&self.params(1.0)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let point_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Goniometer Point Storage Buffer"),
+            contents: bytemuck::cast_slice(&self.windows),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Goniometer Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: point_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Goniometer Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        // Additive, overlapping points build up like phosphor
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::OVER,
+        };
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Goniometer Render Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader_module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_module,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    blend: Some(additive),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        self.primitive = Some(Arc::new(GoniometerPrimitive {
+            params_uniform_buffer,
+            point_buffer,
+            bind_group,
+            render_pipeline,
+        }));
+        tracing::info!("Goniometer resources prepared successfully.");
+        Ok(())
+    }
+
+    fn update(&mut self, input: &VisualizerInput, _dt: f32) {
+        let Some(data) = input.analysis else {
+            return;
+        };
+        if self.last_timestamp != Some(data.timestamp) {
+            self.last_timestamp = Some(data.timestamp);
+            self.push_window(&data.stereo);
+        }
+    }
+
+    fn paint(&mut self, aspect_ratio: f32) -> Option<Box<dyn VisualizerFrame>> {
+        let primitive = self.primitive.clone()?;
+        Some(Box::new(GoniometerFrame {
+            primitive,
+            params: self.params(aspect_ratio),
+            windows: self.windows.clone(),
+        }))
+    }
+
+    fn settings_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("Correlation:");
+        correlation_meter(ui, self.correlation);
+        ui.label(format!("{:+.2}", self.correlation));
+        ui.separator();
+        ui.add(egui::Slider::new(&mut self.gain, 0.5..=4.0).text("Gain"));
+        ui.add(egui::Slider::new(&mut self.decay, 0.0..=0.95).text("Decay"));
+        ui.add(egui::Slider::new(&mut self.point_size, 0.002..=0.02).text("Point size"));
+        ui.color_edit_button_rgb(&mut self.color);
+    }
+}
+
+struct GoniometerFrame {
+    primitive: Arc<GoniometerPrimitive>,
+    params: GoniometerParamsUniform,
+    windows: Vec<[[f32; 2]; POINTS_PER_WINDOW]>,
+}
+
+impl VisualizerFrame for GoniometerFrame {
+    fn paint<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, queue: &wgpu::Queue) {
+        let primitive = &self.primitive;
+        queue.write_buffer(
+            &primitive.params_uniform_buffer,
+            0,
+            bytemuck::bytes_of(&self.params),
+        );
+        queue.write_buffer(
+            &primitive.point_buffer,
+            0,
+            bytemuck::cast_slice(&self.windows),
+        );
+
+        render_pass.set_pipeline(&primitive.render_pipeline);
+        render_pass.set_bind_group(0, &primitive.bind_group, &[]);
+        render_pass.draw(
+            0..6,
+            0..self.params.window_count * self.params.points_per_window,
+        );
+    }
+}
+
+// Pearson correlation between the channels, None for silence
+fn phase_correlation(stereo: &[[f32; 2]]) -> Option<f32> {
+    let (mut lr, mut ll, mut rr) = (0.0f32, 0.0f32, 0.0f32);
+    for &[left, right] in stereo {
+        lr += left * right;
+        ll += left * left;
+        rr += right * right;
+    }
+    let energy = (ll * rr).sqrt();
+    (energy > 1e-9).then(|| (lr / energy).clamp(-1.0, 1.0))
+}
+
+// -1 ... +1 bar, red on the out of phase side
+fn correlation_meter(ui: &mut egui::Ui, correlation: f32) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(120.0, 12.0), egui::Sense::hover());
+    let painter = ui.painter();
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+    let center_x = rect.center().x;
+    let value_x = center_x + correlation * rect.width() * 0.5;
+    let color = if correlation < 0.0 {
+        egui::Color32::LIGHT_RED
+    } else {
+        egui::Color32::LIGHT_GREEN
+    };
+    painter.rect_filled(
+        egui::Rect::from_x_y_ranges(
+            center_x.min(value_x)..=center_x.max(value_x),
+            rect.y_range(),
+        ),
+        0.0,
+        color,
+    );
+    painter.vline(
+        center_x,
+        rect.y_range(),
+        egui::Stroke::new(1.0, ui.visuals().weak_text_color()),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stereo(length: usize, channels: impl Fn(f32) -> [f32; 2]) -> Vec<[f32; 2]> {
+        (0..length)
+            .map(|i| channels(std::f32::consts::TAU * i as f32 / 64.0))
+            .collect()
+    }
+
+    #[test]
+    fn correlation_of_simple_signals() {
+        let mono = stereo(1024, |phase| [phase.sin(), phase.sin()]);
+        let inverted = stereo(1024, |phase| [phase.sin(), -phase.sin()]);
+        // 90 degrees apart, what a wide stereo image looks like on average
+        let quadrature = stereo(1024, |phase| [phase.sin(), phase.cos()]);
+        let one_side = stereo(1024, |phase| [phase.sin(), 0.0]);
+
+        assert!((phase_correlation(&mono).unwrap() - 1.0).abs() < 1e-4);
+        assert!((phase_correlation(&inverted).unwrap() + 1.0).abs() < 1e-4);
+        assert!(phase_correlation(&quadrature).unwrap().abs() < 1e-3);
+        assert_eq!(phase_correlation(&one_side), None);
+        assert_eq!(phase_correlation(&[[0.0, 0.0]; 256]), None);
+        assert_eq!(phase_correlation(&[]), None);
+    }
+}
@@ -1,2 +1,22 @@
+pub mod audio_features;
+pub mod bar_analyzer;
+pub mod beat;
+pub mod camera;
+pub mod capture;
+pub mod cpu_raster;
+pub mod custom_shader;
+pub mod geometry;
+pub mod goniometer;
+pub mod modulation;
+pub mod oscilloscope;
+pub mod palette;
+pub mod particles;
+pub mod post_process;
+pub mod preset;
 pub mod renderer;
-pub mod sphere_geometry;
+pub mod shader_source;
+pub mod spectrogram;
+pub mod spectrum;
+pub mod visualizer;
+
+pub use visualizer::{VisualizerInput, VisualizerPaintCallback, VisualizerRegistry};
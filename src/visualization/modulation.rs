@@ -0,0 +1,442 @@
+use crate::audio::crossover::DEFAULT_BAND_NAMES;
+use crate::audio::AudioAnalysisData;
+use crate::visualization::audio_features::strongest_onset;
+use crate::visualization::beat::BeatClock;
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+
+// Band onset needed to count as a beat for the beat phase source
+const BEAT_ONSET_THRESHOLD: f32 = 1.5;
+// Spectral centroid is reported on a log scale between these
+const CENTROID_MIN_HZ: f32 = 20.0;
+const CENTROID_MAX_HZ: f32 = 20_000.0;
+
+// Something in the audio (or a clock) a route reads from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ModSource {
+    Rms,
+    Peak,
+    // RMS of one crossover band, lowest first (see `DEFAULT_BAND_NAMES`)
+    Band(usize),
+    // Strongest band onset in the current window
+    Onset,
+    // 0 on the beat, ramping up to 1 just before the next one
+    BeatPhase,
+    // Spectral centroid, 0 = 20 Hz, 1 = 20 kHz on a log scale
+    Centroid,
+    // Sine between 0 and 1 at the matrix's LFO rate
+    Lfo,
+}
+
+impl ModSource {
+    // Every source the route editor offers, one entry per band
+    pub fn all() -> Vec<ModSource> {
+        let mut sources = vec![ModSource::Rms, ModSource::Peak];
+        sources.extend((0..DEFAULT_BAND_NAMES.len()).map(ModSource::Band));
+        sources.extend([
+            ModSource::Onset,
+            ModSource::BeatPhase,
+            ModSource::Centroid,
+            ModSource::Lfo,
+        ]);
+        sources
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            ModSource::Rms => "RMS".to_string(),
+            ModSource::Peak => "Peak".to_string(),
+            ModSource::Band(index) => format!(
+                "Band: {}",
+                DEFAULT_BAND_NAMES.get(*index).copied().unwrap_or("?")
+            ),
+            ModSource::Onset => "Onset".to_string(),
+            ModSource::BeatPhase => "Beat Phase".to_string(),
+            ModSource::Centroid => "Centroid".to_string(),
+            ModSource::Lfo => "LFO".to_string(),
+        }
+    }
+
+    // Input range that covers what the source usually does, picked when switching to it
+    pub fn default_range(&self) -> [f32; 2] {
+        match self {
+            ModSource::Rms | ModSource::Band(_) => [0.0, 0.33],
+            ModSource::Onset => [0.0, 3.0],
+            ModSource::Peak | ModSource::BeatPhase | ModSource::Centroid | ModSource::Lfo => {
+                [0.0, 1.0]
+            }
+        }
+    }
+}
+
+// What a route pushes around. Each target adds up the routes pointing at it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ModTarget {
+    // Added to the overall scale
+    Scale,
+    // Added to the colour saturation
+    Saturation,
+    // Added to the hue, 1 goes all the way around
+    Hue,
+    // 1 doubles the rotation speed, -1 stops it
+    RotationSpeed,
+    // Added to the spectrum displacement
+    Displacement,
+    // 1 doubles the point size
+    PointSize,
+    // Added to the bloom intensity, needs bloom switched on in post processing. Replaces
+    // the bloom's own audio drive there while a route points here.
+    Bloom,
+}
+
+impl ModTarget {
+    pub const ALL: [ModTarget; 7] = [
+        ModTarget::Scale,
+        ModTarget::Saturation,
+        ModTarget::Hue,
+        ModTarget::RotationSpeed,
+        ModTarget::Displacement,
+        ModTarget::PointSize,
+        ModTarget::Bloom,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ModTarget::Scale => "Scale",
+            ModTarget::Saturation => "Saturation",
+            ModTarget::Hue => "Hue",
+            ModTarget::RotationSpeed => "Rotation Speed",
+            ModTarget::Displacement => "Displacement",
+            ModTarget::PointSize => "Point Size",
+            ModTarget::Bloom => "Bloom",
+        }
+    }
+}
+
+// Shape applied to the source after it's been mapped to 0..1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ModCurve {
+    Linear,
+    // Quiet parts barely move, loud parts move a lot
+    EaseIn,
+    // Reacts early, then levels off
+    EaseOut,
+    SCurve,
+}
+
+impl ModCurve {
+    pub const ALL: [ModCurve; 4] = [
+        ModCurve::Linear,
+        ModCurve::EaseIn,
+        ModCurve::EaseOut,
+        ModCurve::SCurve,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ModCurve::Linear => "Linear",
+            ModCurve::EaseIn => "Ease In",
+            ModCurve::EaseOut => "Ease Out",
+            ModCurve::SCurve => "S-Curve",
+        }
+    }
+
+    fn apply(&self, x: f32) -> f32 {
+        match self {
+            ModCurve::Linear => x,
+            ModCurve::EaseIn => x * x,
+            ModCurve::EaseOut => 1.0 - (1.0 - x) * (1.0 - x),
+            ModCurve::SCurve => x * x * (3.0 - 2.0 * x),
+        }
+    }
+}
+
+// One source to one target
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModRoute {
+    pub enabled: bool,
+    pub source: ModSource,
+    pub target: ModTarget,
+    // Source values in this range map to 0..1 (clamped) before the curve
+    pub range: [f32; 2],
+    pub curve: ModCurve,
+    // Seconds to get most of the way to a new value, 0 follows the source directly
+    pub smoothing: f32,
+    // What a fully open route adds to the target, can be negative
+    pub amount: f32,
+}
+
+impl ModRoute {
+    pub fn new(source: ModSource, target: ModTarget, amount: f32) -> Self {
+        ModRoute {
+            enabled: true,
+            source,
+            target,
+            range: source.default_range(),
+            curve: ModCurve::Linear,
+            smoothing: 0.0,
+            amount,
+        }
+    }
+
+    fn shape(&self, value: f32) -> f32 {
+        let [low, high] = self.range;
+        let span = high - low;
+        let x = if span.abs() < f32::EPSILON {
+            (value >= high) as u8 as f32
+        } else {
+            ((value - low) / span).clamp(0.0, 1.0)
+        };
+        self.curve.apply(x)
+    }
+}
+
+// All routes of a preset, plus the settings of the sources that need some
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModulationMatrix {
+    pub routes: Vec<ModRoute>,
+    // LFO cycles per second
+    pub lfo_rate: f32,
+}
+
+impl Default for ModulationMatrix {
+    // RMS drives scale and saturation, how the point cloud always reacted
+    fn default() -> Self {
+        ModulationMatrix {
+            routes: vec![
+                ModRoute::new(ModSource::Rms, ModTarget::Scale, 2.5),
+                ModRoute::new(ModSource::Rms, ModTarget::Saturation, 0.9),
+            ],
+            lfo_rate: 0.25,
+        }
+    }
+}
+
+impl ModulationMatrix {
+    // Whether any enabled route points at `target`
+    pub fn drives(&self, target: ModTarget) -> bool {
+        self.routes
+            .iter()
+            .any(|route| route.enabled && route.target == target)
+    }
+
+    // Routes only blend when both sides have the same layout, otherwise they switch halfway
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        let same_layout = self.routes.len() == other.routes.len()
+            && self.routes.iter().zip(&other.routes).all(|(a, b)| {
+                (a.source, a.target, a.curve, a.enabled) == (b.source, b.target, b.curve, b.enabled)
+            });
+        let routes = if same_layout {
+            self.routes
+                .iter()
+                .zip(&other.routes)
+                .map(|(a, b)| ModRoute {
+                    range: [mix(a.range[0], b.range[0]), mix(a.range[1], b.range[1])],
+                    smoothing: mix(a.smoothing, b.smoothing),
+                    amount: mix(a.amount, b.amount),
+                    ..a.clone()
+                })
+                .collect()
+        } else if t < 0.5 {
+            self.routes.clone()
+        } else {
+            other.routes.clone()
+        };
+        ModulationMatrix {
+            routes,
+            lfo_rate: mix(self.lfo_rate, other.lfo_rate),
+        }
+    }
+
+    // Route editor. `modulator` is the one running these routes, its outputs feed the meters
+    // and a removed route's smoothing state goes along with it.
+    pub fn settings_ui(&mut self, ui: &mut egui::Ui, modulator: &mut Modulator) {
+        let values = modulator.route_values();
+        ui.add(
+            egui::Slider::new(&mut self.lfo_rate, 0.01..=4.0)
+                .logarithmic(true)
+                .suffix(" Hz")
+                .text("LFO rate"),
+        );
+        let mut remove = None;
+        for (index, route) in self.routes.iter_mut().enumerate() {
+            ui.push_id(index, |ui| {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut route.enabled, "");
+                    let mut source = route.source;
+                    egui::ComboBox::from_id_source("route_source")
+                        .selected_text(source.label())
+                        .width(110.0)
+                        .show_ui(ui, |ui| {
+                            for option in ModSource::all() {
+                                ui.selectable_value(&mut source, option, option.label());
+                            }
+                        });
+                    if source != route.source {
+                        route.source = source;
+                        route.range = source.default_range();
+                    }
+                    ui.label("→");
+                    egui::ComboBox::from_id_source("route_target")
+                        .selected_text(route.target.label())
+                        .width(110.0)
+                        .show_ui(ui, |ui| {
+                            for target in ModTarget::ALL {
+                                ui.selectable_value(&mut route.target, target, target.label());
+                            }
+                        });
+                    let value = values.get(index).copied().unwrap_or(0.0);
+                    ui.add(egui::ProgressBar::new(value).desired_width(50.0));
+                    if ui.small_button("Remove").clicked() {
+                        remove = Some(index);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Amount");
+                    ui.add(
+                        egui::DragValue::new(&mut route.amount)
+                            .speed(0.01)
+                            .clamp_range(-10.0..=10.0),
+                    );
+                    ui.label("Range");
+                    ui.add(egui::DragValue::new(&mut route.range[0]).speed(0.005));
+                    ui.add(egui::DragValue::new(&mut route.range[1]).speed(0.005));
+                    egui::ComboBox::from_id_source("route_curve")
+                        .selected_text(route.curve.label())
+                        .show_ui(ui, |ui| {
+                            for curve in ModCurve::ALL {
+                                ui.selectable_value(&mut route.curve, curve, curve.label());
+                            }
+                        });
+                    ui.label("Smoothing");
+                    ui.add(
+                        egui::DragValue::new(&mut route.smoothing)
+                            .speed(0.005)
+                            .clamp_range(0.0..=5.0)
+                            .suffix(" s"),
+                    );
+                });
+            });
+            ui.add_space(4.0);
+        }
+        if let Some(index) = remove {
+            self.routes.remove(index);
+            modulator.remove_route(index);
+        }
+        if ui.button("Add Route").clicked() {
+            self.routes
+                .push(ModRoute::new(ModSource::Rms, ModTarget::Scale, 1.0));
+        }
+    }
+}
+
+// Summed route outputs per target for one frame
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModulationOutput {
+    values: [f32; ModTarget::ALL.len()],
+}
+
+impl ModulationOutput {
+    pub fn get(&self, target: ModTarget) -> f32 {
+        self.values[target as usize]
+    }
+}
+
+// Runtime state behind a matrix: smoothed route values, the beat clock and the LFO
+pub struct Modulator {
+    beat: BeatClock,
+    lfo_phase: f32,
+    // Per route, after range, curve and smoothing but before the amount
+    smoothed: Vec<f32>,
+}
+
+impl Modulator {
+    pub fn new() -> Self {
+        Modulator {
+            beat: BeatClock::new(),
+            lfo_phase: 0.0,
+            smoothed: Vec::new(),
+        }
+    }
+
+    // Route outputs as of the latest `process`, 0..1
+    pub fn route_values(&self) -> &[f32] {
+        &self.smoothed
+    }
+
+    // Keeps the routes after `index` lined up with their smoothed values
+    fn remove_route(&mut self, index: usize) {
+        if index < self.smoothed.len() {
+            self.smoothed.remove(index);
+        }
+    }
+
+    // `analysis` should be None while nothing is playing, audio sources then read 0
+    pub fn process(
+        &mut self,
+        matrix: &ModulationMatrix,
+        analysis: Option<&AudioAnalysisData>,
+        dt: f32,
+    ) -> ModulationOutput {
+        self.beat.update(analysis, BEAT_ONSET_THRESHOLD, dt);
+        self.lfo_phase = (self.lfo_phase + dt * matrix.lfo_rate).fract();
+        self.smoothed.resize(matrix.routes.len(), 0.0);
+
+        let mut output = ModulationOutput::default();
+        for (index, route) in matrix.routes.iter().enumerate() {
+            let target = route.shape(self.source_value(route.source, analysis));
+            let smoothed = &mut self.smoothed[index];
+            *smoothed = if route.smoothing > 0.0 {
+                *smoothed + (target - *smoothed) * (1.0 - (-dt / route.smoothing).exp())
+            } else {
+                target
+            };
+            if route.enabled {
+                output.values[route.target as usize] += *smoothed * route.amount;
+            }
+        }
+        output
+    }
+
+    fn source_value(&self, source: ModSource, analysis: Option<&AudioAnalysisData>) -> f32 {
+        match source {
+            ModSource::BeatPhase => return self.beat.phase(),
+            ModSource::Lfo => {
+                return 0.5 - 0.5 * (self.lfo_phase * std::f32::consts::TAU).cos();
+            }
+            _ => {}
+        }
+        let Some(data) = analysis else {
+            return 0.0;
+        };
+        match source {
+            ModSource::Rms => data.rms_amplitude,
+            ModSource::Peak => data.peak_amplitude,
+            ModSource::Band(index) => data.bands.get(index).map_or(0.0, |band| band.rms),
+            ModSource::Onset => strongest_onset(data),
+            ModSource::Centroid => spectral_centroid(data),
+            ModSource::BeatPhase | ModSource::Lfo => 0.0,
+        }
+    }
+}
+
+// Magnitude weighted mean frequency, on a log scale between CENTROID_MIN_HZ and CENTROID_MAX_HZ
+fn spectral_centroid(data: &AudioAnalysisData) -> f32 {
+    let bin_hz = data.sample_rate as f32 / data.fft_size.max(1) as f32;
+    let (weighted, total) = data.frequency_magnitudes.iter().enumerate().fold(
+        (0.0, 0.0),
+        |(weighted, total), (bin, &magnitude)| {
+            (
+                weighted + bin as f32 * bin_hz * magnitude,
+                total + magnitude,
+            )
+        },
+    );
+    if total <= f32::EPSILON {
+        return 0.0;
+    }
+    let centroid = (weighted / total).max(CENTROID_MIN_HZ);
+    ((centroid / CENTROID_MIN_HZ).ln() / (CENTROID_MAX_HZ / CENTROID_MIN_HZ).ln()).clamp(0.0, 1.0)
+}
@@ -0,0 +1,419 @@
+use crate::visualization::visualizer::{Visualizer, VisualizerFrame, VisualizerInput};
+use anyhow::Result;
+use bytemuck::{Pod, Zeroable};
+use eframe::egui;
+use std::sync::Arc;
+use wgpu::util::DeviceExt;
+
+// Samples shown per trace, about 11ms at 44.1kHz. Analysis windows are longer,
+// the spare samples give the trigger room to move.
+const TRACE_LENGTH: usize = 512;
+// Older traces kept around for persistence
+const MAX_HISTORY: usize = 16;
+// Rising edge has to come from below -HYSTERESIS, so noise around zero doesn't trigger
+const TRIGGER_HYSTERESIS: f32 = 0.01;
+// Only every Nth sample goes into the autocorrelation score
+const CORRELATION_STEP: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    // Whatever the window starts with, the trace will wander
+    FreeRun,
+    // First rising zero crossing
+    ZeroCrossing,
+    // Offset that best lines up with the previous trace, steady for complex waveforms too
+    Autocorrelation,
+}
+
+impl TriggerMode {
+    pub const ALL: [TriggerMode; 3] = [
+        TriggerMode::FreeRun,
+        TriggerMode::ZeroCrossing,
+        TriggerMode::Autocorrelation,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TriggerMode::FreeRun => "Free Run",
+            TriggerMode::ZeroCrossing => "Zero Crossing",
+            TriggerMode::Autocorrelation => "Autocorrelation",
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct ScopeParamsUniform {
+    color: [f32; 4],
+    trace_length: u32,
+    history_capacity: u32,
+    newest: u32,
+    trace_count: u32,
+    thickness: f32,
+    aspect_ratio: f32,
+    persistence: f32,
+    gain: f32,
+}
+
+const SCOPE_SHADER_WGSL: &str = r#"
+struct ScopeParams {
+    color: vec4<f32>,
+    trace_length: u32,
+    history_capacity: u32,
+    newest: u32,
+    trace_count: u32,
+    thickness: f32,
+    aspect_ratio: f32,
+    persistence: f32,
+    gain: f32,
+};
+@group(0) @binding(0)
+var<uniform> params: ScopeParams;
+// Ring of traces, `trace_length` samples each
+@group(0) @binding(1)
+var<storage, read> traces: array<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // -1 / 1 on the two edges of the line
+    @location(0) edge: f32,
+    @location(1) alpha: f32,
+};
+
+// Sample `i` of the trace `age` steps back, in aspect corrected space (y is -1..1)
+fn trace_point(age: u32, i: u32) -> vec2<f32> {
+    let slot = (params.newest + params.history_capacity - age) % params.history_capacity;
+    let index = clamp(i, 0u, params.trace_length - 1u);
+    let sample = traces[slot * params.trace_length + index];
+    let x = f32(index) / f32(params.trace_length - 1u) * 2.0 - 1.0;
+    return vec2<f32>(x * params.aspect_ratio, clamp(sample * params.gain, -1.0, 1.0));
+}
+
+// One triangle strip per trace, two vertices per sample pushed out along the line normal.
+// Instances run oldest first so the newest trace ends up on top.
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    let age = params.trace_count - 1u - instance_index;
+    let i = vertex_index / 2u;
+    let side = select(-1.0, 1.0, (vertex_index & 1u) == 1u);
+
+    let point = trace_point(age, i);
+    let previous = trace_point(age, max(i, 1u) - 1u);
+    let next = trace_point(age, i + 1u);
+    let tangent = normalize(next - previous + vec2<f32>(1e-6, 0.0));
+    let normal = vec2<f32>(-tangent.y, tangent.x);
+    let offset_point = point + normal * side * params.thickness * 0.5;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(offset_point.x / params.aspect_ratio, offset_point.y, 0.0, 1.0);
+    out.edge = side;
+    // pow(0, 0) is undefined in WGSL
+    out.alpha = select(pow(params.persistence, f32(age)), 1.0, age == 0u);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Soft edges instead of MSAA
+    let coverage = 1.0 - smoothstep(0.4, 1.0, abs(in.edge));
+    return vec4<f32>(params.color.rgb, params.color.a * coverage * in.alpha);
+}
+"#;
+
+pub struct OscilloscopePrimitive {
+    params_uniform_buffer: wgpu::Buffer,
+    trace_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    render_pipeline: wgpu::RenderPipeline,
+}
+
+// Triggered time domain view of the analysed samples
+pub struct Oscilloscope {
+    primitive: Option<Arc<OscilloscopePrimitive>>,
+    // Ring buffer of the last MAX_HISTORY traces
+    traces: Vec<[f32; TRACE_LENGTH]>,
+    newest: usize,
+    trace_count: usize,
+    // Timestamp of the analysis window the newest trace came from
+    last_timestamp: Option<f64>,
+    pub trigger: TriggerMode,
+    // Line width as a fraction of the view height
+    pub thickness: f32,
+    // Alpha multiplier per step back in history, 0 shows only the newest trace
+    pub persistence: f32,
+    pub gain: f32,
+    pub color: [f32; 3],
+}
+
+impl Oscilloscope {
+    pub fn new() -> Self {
+        Oscilloscope {
+            primitive: None,
+            traces: vec![[0.0; TRACE_LENGTH]; MAX_HISTORY],
+            newest: 0,
+            trace_count: 1,
+            last_timestamp: None,
+            trigger: TriggerMode::ZeroCrossing,
+            thickness: 0.01,
+            persistence: 0.5,
+            gain: 1.5,
+            color: [0.3, 1.0, 0.5],
+        }
+    }
+
+    fn push_trace(&mut self, waveform: &[f32]) {
+        if waveform.len() < TRACE_LENGTH {
+            return;
+        }
+        let offset = match self.trigger {
+            TriggerMode::FreeRun => 0,
+            TriggerMode::ZeroCrossing => zero_crossing_offset(waveform),
+            TriggerMode::Autocorrelation => {
+                best_aligned_offset(waveform, &self.traces[self.newest])
+            }
+        };
+        self.newest = (self.newest + 1) % MAX_HISTORY;
+        self.traces[self.newest].copy_from_slice(&waveform[offset..offset + TRACE_LENGTH]);
+        self.trace_count = (self.trace_count + 1).min(MAX_HISTORY);
+    }
+
+    fn params(&self, aspect_ratio: f32) -> ScopeParamsUniform {
+        ScopeParamsUniform {
+            color: [self.color[0], self.color[1], self.color[2], 1.0],
+            trace_length: TRACE_LENGTH as u32,
+            history_capacity: MAX_HISTORY as u32,
+            newest: self.newest as u32,
+            trace_count: self.visible_traces() as u32,
+            thickness: self.thickness,
+            aspect_ratio,
+            persistence: self.persistence,
+            gain: self.gain,
+        }
+    }
+
+    // Traces worth drawing, fully faded ones are skipped
+    fn visible_traces(&self) -> usize {
+        if self.persistence <= 0.0 {
+            return 1;
+        }
+        // Ages with alpha above ~1%
+        let ages = (0.01f32.ln() / self.persistence.min(0.99).ln()).ceil() as usize + 1;
+        ages.clamp(1, self.trace_count)
+    }
+}
+
+impl Visualizer for Oscilloscope {
+    fn name(&self) -> &'static str {
+        "Oscilloscope"
+    }
+
+    fn prepare(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        target_format: wgpu::TextureFormat,
+    ) -> Result<()> {
+        if self.primitive.is_some() {
+            return Ok(());
+        }
+        tracing::info!("Preparing Oscilloscope resources...");
+
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Oscilloscope Shader"),
+            source: wgpu::ShaderSource::Wgsl(SCOPE_SHADER_WGSL.into()),
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Oscilloscope Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let params_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Oscilloscope Params Uniform Buffer"),
+            contents: bytemuck::bytes_of(&self.params(1.0)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let trace_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Oscilloscope Trace Storage Buffer"),
+            contents: bytemuck::cast_slice(&self.traces),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Oscilloscope Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: trace_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Oscilloscope Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Oscilloscope Render Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader_module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_module,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        self.primitive = Some(Arc::new(OscilloscopePrimitive {
+            params_uniform_buffer,
+            trace_buffer,
+            bind_group,
+            render_pipeline,
+        }));
+        tracing::info!("Oscilloscope resources prepared successfully.");
+        Ok(())
+    }
+
+    // New traces only arrive with new analysis windows, while paused the last one stays up
+    fn update(&mut self, input: &VisualizerInput, _dt: f32) {
+        let Some(data) = input.analysis else {
+            return;
+        };
+        if self.last_timestamp != Some(data.timestamp) {
+            self.last_timestamp = Some(data.timestamp);
+            self.push_trace(&data.waveform);
+        }
+    }
+
+    fn paint(&mut self, aspect_ratio: f32) -> Option<Box<dyn VisualizerFrame>> {
+        let primitive = self.primitive.clone()?;
+        Some(Box::new(OscilloscopeFrame {
+            primitive,
+            params: self.params(aspect_ratio),
+            traces: self.traces.clone(),
+        }))
+    }
+
+    fn settings_ui(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_id_source("scope_trigger")
+            .selected_text(self.trigger.label())
+            .show_ui(ui, |ui| {
+                for trigger in TriggerMode::ALL {
+                    ui.selectable_value(&mut self.trigger, trigger, trigger.label());
+                }
+            });
+        ui.add(egui::Slider::new(&mut self.gain, 0.5..=8.0).text("Gain"));
+        ui.add(egui::Slider::new(&mut self.thickness, 0.002..=0.04).text("Thickness"));
+        ui.add(egui::Slider::new(&mut self.persistence, 0.0..=0.95).text("Persistence"));
+        ui.color_edit_button_rgb(&mut self.color);
+    }
+}
+
+struct OscilloscopeFrame {
+    primitive: Arc<OscilloscopePrimitive>,
+    params: ScopeParamsUniform,
+    traces: Vec<[f32; TRACE_LENGTH]>,
+}
+
+impl VisualizerFrame for OscilloscopeFrame {
+    fn paint<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, queue: &wgpu::Queue) {
+        let primitive = &self.primitive;
+        queue.write_buffer(
+            &primitive.params_uniform_buffer,
+            0,
+            bytemuck::bytes_of(&self.params),
+        );
+        queue.write_buffer(
+            &primitive.trace_buffer,
+            0,
+            bytemuck::cast_slice(&self.traces),
+        );
+
+        render_pass.set_pipeline(&primitive.render_pipeline);
+        render_pass.set_bind_group(0, &primitive.bind_group, &[]);
+        render_pass.draw(0..(TRACE_LENGTH * 2) as u32, 0..self.params.trace_count);
+    }
+}
+
+// First rising zero crossing that still leaves a full trace, 0 if there is none
+fn zero_crossing_offset(waveform: &[f32]) -> usize {
+    let last_start = waveform.len() - TRACE_LENGTH;
+    let mut armed = false;
+    for (i, &sample) in waveform.iter().enumerate().take(last_start + 1) {
+        if sample < -TRIGGER_HYSTERESIS {
+            armed = true;
+        } else if armed && sample >= 0.0 {
+            return i;
+        }
+    }
+    0
+}
+
+// Offset whose trace looks most like the previous one
+fn best_aligned_offset(waveform: &[f32], previous: &[f32; TRACE_LENGTH]) -> usize {
+    let last_start = waveform.len() - TRACE_LENGTH;
+    let mut best = zero_crossing_offset(waveform);
+    if previous.iter().all(|&s| s.abs() < TRIGGER_HYSTERESIS) {
+        // Nothing to line up with yet
+        return best;
+    }
+    let mut best_score = f32::MIN;
+    for offset in 0..=last_start {
+        let mut cross = 0.0f32;
+        let mut energy = 1e-9f32;
+        for i in (0..TRACE_LENGTH).step_by(CORRELATION_STEP) {
+            let sample = waveform[offset + i];
+            cross += sample * previous[i];
+            energy += sample * sample;
+        }
+        let score = cross / energy.sqrt();
+        if score > best_score {
+            best_score = score;
+            best = offset;
+        }
+    }
+    best
+}
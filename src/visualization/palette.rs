@@ -0,0 +1,255 @@
+use eframe::egui;
+
+// Entries in a baked gradient lookup table
+pub const GRADIENT_RESOLUTION: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GradientStop {
+    // 0..1 along the gradient
+    pub position: f32,
+    pub color: [f32; 3],
+}
+
+// Piecewise linear colour gradient
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    // Kept sorted by position
+    pub stops: Vec<GradientStop>,
+}
+
+impl Gradient {
+    // Evenly spaced stops from hex colours
+    pub fn from_hex(colors: &[u32]) -> Self {
+        let last = (colors.len().max(2) - 1) as f32;
+        Gradient {
+            stops: colors
+                .iter()
+                .enumerate()
+                .map(|(i, &hex)| GradientStop {
+                    position: i as f32 / last,
+                    color: hex_to_rgb(hex),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn sample(&self, t: f32) -> [f32; 3] {
+        let (Some(first), Some(last)) = (self.stops.first(), self.stops.last()) else {
+            return [1.0, 1.0, 1.0];
+        };
+        if t <= first.position {
+            return first.color;
+        }
+        for pair in self.stops.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            if t <= b.position {
+                let span = (b.position - a.position).max(f32::EPSILON);
+                let f = (t - a.position) / span;
+                return [0, 1, 2].map(|c| a.color[c] + (b.color[c] - a.color[c]) * f);
+            }
+        }
+        last.color
+    }
+
+    // Lookup table for the GPU, vec4 per entry so it can sit in a storage buffer as is
+    pub fn bake(&self, resolution: usize) -> Vec<[f32; 4]> {
+        let last = (resolution.max(2) - 1) as f32;
+        (0..resolution)
+            .map(|i| {
+                let [r, g, b] = self.sample(i as f32 / last);
+                [r, g, b, 1.0]
+            })
+            .collect()
+    }
+
+    fn sort(&mut self) {
+        self.stops.sort_by(|a, b| a.position.total_cmp(&b.position));
+    }
+
+    // Horizontal strip showing the gradient
+    pub fn preview_ui(&self, ui: &mut egui::Ui) {
+        const SEGMENTS: usize = 64;
+        let (rect, _) = ui.allocate_exact_size(egui::vec2(200.0, 14.0), egui::Sense::hover());
+        let painter = ui.painter_at(rect);
+        let width = rect.width() / SEGMENTS as f32;
+        for i in 0..SEGMENTS {
+            let [r, g, b] = self.sample((i as f32 + 0.5) / SEGMENTS as f32);
+            let x = rect.left() + i as f32 * width;
+            painter.rect_filled(
+                egui::Rect::from_min_max(
+                    egui::pos2(x, rect.top()),
+                    egui::pos2(x + width + 0.5, rect.bottom()),
+                ),
+                0.0,
+                egui::Rgba::from_rgb(r, g, b),
+            );
+        }
+    }
+
+    // Stop list with colour pickers. Keeps at least two stops.
+    pub fn editor_ui(&mut self, ui: &mut egui::Ui) {
+        let mut remove = None;
+        let can_remove = self.stops.len() > 2;
+        for (index, stop) in self.stops.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.color_edit_button_rgb(&mut stop.color);
+                ui.add(egui::Slider::new(&mut stop.position, 0.0..=1.0).text("Position"));
+                if ui
+                    .add_enabled(can_remove, egui::Button::new("✖").small())
+                    .clicked()
+                {
+                    remove = Some(index);
+                }
+            });
+        }
+        if let Some(index) = remove {
+            self.stops.remove(index);
+        }
+        if ui.button("Add stop").clicked() {
+            // Into the widest gap, coloured like the gradient already is there
+            let (position, _) = self
+                .stops
+                .windows(2)
+                .map(|pair| {
+                    let gap = pair[1].position - pair[0].position;
+                    (pair[0].position + gap * 0.5, gap)
+                })
+                .fold((0.5, f32::MIN), |best, candidate| {
+                    if candidate.1 > best.1 {
+                        candidate
+                    } else {
+                        best
+                    }
+                });
+            self.stops.push(GradientStop {
+                position,
+                color: self.sample(position),
+            });
+        }
+        self.sort();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamedPalette {
+    Rainbow,
+    Sunset,
+    Ocean,
+    Fire,
+    Neon,
+    Viridis,
+}
+
+impl NamedPalette {
+    pub const ALL: [NamedPalette; 6] = [
+        NamedPalette::Rainbow,
+        NamedPalette::Sunset,
+        NamedPalette::Ocean,
+        NamedPalette::Fire,
+        NamedPalette::Neon,
+        NamedPalette::Viridis,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            NamedPalette::Rainbow => "Rainbow",
+            NamedPalette::Sunset => "Sunset",
+            NamedPalette::Ocean => "Ocean",
+            NamedPalette::Fire => "Fire",
+            NamedPalette::Neon => "Neon",
+            NamedPalette::Viridis => "Viridis",
+        }
+    }
+
+    pub fn gradient(&self) -> Gradient {
+        match self {
+            NamedPalette::Rainbow => Gradient::from_hex(&[
+                0xff0000, 0xffff00, 0x00ff00, 0x00ffff, 0x0000ff, 0xff00ff, 0xff0000,
+            ]),
+            NamedPalette::Sunset => {
+                Gradient::from_hex(&[0x2d1b69, 0xb5179e, 0xf72585, 0xff9e00, 0xffd60a])
+            }
+            NamedPalette::Ocean => {
+                Gradient::from_hex(&[0x03045e, 0x0077b6, 0x00b4d8, 0x90e0ef, 0xcaf0f8])
+            }
+            NamedPalette::Fire => {
+                Gradient::from_hex(&[0x200000, 0xa00000, 0xff4000, 0xffb000, 0xffffc0])
+            }
+            NamedPalette::Neon => Gradient::from_hex(&[0xff00ff, 0x00ffff, 0x39ff14]),
+            NamedPalette::Viridis => {
+                Gradient::from_hex(&[0x440154, 0x3b528b, 0x21918c, 0x5ec962, 0xfde725])
+            }
+        }
+    }
+}
+
+// A named palette, or the user's own gradient
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteChoice {
+    Named(NamedPalette),
+    Custom,
+}
+
+impl PaletteChoice {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PaletteChoice::Named(palette) => palette.label(),
+            PaletteChoice::Custom => "Custom",
+        }
+    }
+}
+
+// Palette picker plus the custom gradient it can edit
+#[derive(Debug, Clone)]
+pub struct PaletteSettings {
+    pub choice: PaletteChoice,
+    pub custom: Gradient,
+}
+
+impl PaletteSettings {
+    pub fn new(choice: PaletteChoice) -> Self {
+        PaletteSettings {
+            choice,
+            custom: NamedPalette::Sunset.gradient(),
+        }
+    }
+
+    pub fn gradient(&self) -> Gradient {
+        match self.choice {
+            PaletteChoice::Named(palette) => palette.gradient(),
+            PaletteChoice::Custom => self.custom.clone(),
+        }
+    }
+
+    pub fn settings_ui(&mut self, ui: &mut egui::Ui, id_source: &str) {
+        ui.horizontal(|ui| {
+            ui.label("Palette:");
+            egui::ComboBox::from_id_source(id_source)
+                .selected_text(self.choice.label())
+                .show_ui(ui, |ui| {
+                    for palette in NamedPalette::ALL {
+                        ui.selectable_value(
+                            &mut self.choice,
+                            PaletteChoice::Named(palette),
+                            palette.label(),
+                        );
+                    }
+                    ui.selectable_value(&mut self.choice, PaletteChoice::Custom, "Custom");
+                });
+            if let PaletteChoice::Named(palette) = self.choice {
+                if ui.small_button("Edit as custom").clicked() {
+                    self.custom = palette.gradient();
+                    self.choice = PaletteChoice::Custom;
+                }
+            }
+        });
+        self.gradient().preview_ui(ui);
+        if self.choice == PaletteChoice::Custom {
+            self.custom.editor_ui(ui);
+        }
+    }
+}
+
+fn hex_to_rgb(hex: u32) -> [f32; 3] {
+    [16, 8, 0].map(|shift| ((hex >> shift) & 0xff) as f32 / 255.0)
+}
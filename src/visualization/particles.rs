@@ -0,0 +1,845 @@
+use crate::audio::{AudioAnalysisData, PlaybackState};
+use crate::visualization::audio_features::{AudioFeatures, OnsetEdge};
+use crate::visualization::camera::OrbitCamera;
+use crate::visualization::geometry::{GeometryPoint, Shape};
+use crate::visualization::palette::{
+    NamedPalette, PaletteChoice, PaletteSettings, GRADIENT_RESOLUTION,
+};
+use crate::visualization::visualizer::{Visualizer, VisualizerFrame, VisualizerInput};
+use anyhow::{bail, Result};
+use bytemuck::{Pod, Zeroable};
+use eframe::egui;
+use glam::Mat4;
+use std::sync::Arc;
+use wgpu::util::DeviceExt;
+
+pub const MIN_PARTICLE_COUNT: usize = 10_000;
+pub const MAX_PARTICLE_COUNT: usize = 1_000_000;
+const DEFAULT_PARTICLE_COUNT: usize = 200_000;
+// Surface points particles spawn from
+const EMITTER_POINTS: usize = 4096;
+// Must match @workgroup_size in the simulation shader
+const WORKGROUP_SIZE: u32 = 256;
+// Long frames (dragging the window and such) would fling everything away
+const MAX_STEP: f32 = 0.05;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct SimParamsUniform {
+    dt: f32,
+    time: f32,
+    curl_strength: f32,
+    noise_scale: f32,
+    // Weight of the finer noise octave, 0..1
+    turbulence: f32,
+    drag: f32,
+    lifetime: f32,
+    initial_speed: f32,
+    radius: f32,
+    particle_count: u32,
+    emitter_count: u32,
+    spawn_count: u32,
+    seed: u32,
+    _padding: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct ParticleRenderUniform {
+    model_view: Mat4,
+    projection: Mat4,
+    point_size: f32,
+    opacity: f32,
+    _padding: [f32; 2],
+}
+
+// Particle layout shared by both shaders, 32 bytes each:
+// position, age, velocity, lifetime. Dead once age >= lifetime, so a zeroed buffer
+// starts out with everything dead.
+const SIMULATE_WGSL: &str = r#"
+struct Particle {
+    position: vec3<f32>,
+    age: f32,
+    velocity: vec3<f32>,
+    lifetime: f32,
+};
+
+struct SimParams {
+    dt: f32,
+    time: f32,
+    curl_strength: f32,
+    noise_scale: f32,
+    turbulence: f32,
+    drag: f32,
+    lifetime: f32,
+    initial_speed: f32,
+    radius: f32,
+    particle_count: u32,
+    emitter_count: u32,
+    spawn_count: u32,
+    seed: u32,
+};
+@group(0) @binding(0)
+var<uniform> sim: SimParams;
+@group(0) @binding(1)
+var<storage, read_write> particles: array<Particle>;
+// GeometryPoints as plain floats, 8 per point: position, normal, uv
+@group(0) @binding(2)
+var<storage, read> emitters: array<f32>;
+// Particles respawned this step, reset to 0 every frame
+@group(0) @binding(3)
+var<storage, read_write> spawned: atomic<u32>;
+
+// PCG hash
+fn hash(x: u32) -> u32 {
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn random(state: ptr<function, u32>) -> f32 {
+    *state = hash(*state);
+    return f32(*state) / 4294967295.0;
+}
+
+fn lattice(cell: vec3<i32>) -> f32 {
+    let h = hash(bitcast<u32>(cell.x) ^ hash(bitcast<u32>(cell.y) ^ hash(bitcast<u32>(cell.z))));
+    return f32(h) / 2147483647.5 - 1.0;
+}
+
+// Smoothly interpolated lattice noise in -1..1
+fn value_noise(p: vec3<f32>) -> f32 {
+    let cell = vec3<i32>(floor(p));
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+    let x00 = mix(lattice(cell), lattice(cell + vec3<i32>(1, 0, 0)), u.x);
+    let x10 = mix(lattice(cell + vec3<i32>(0, 1, 0)), lattice(cell + vec3<i32>(1, 1, 0)), u.x);
+    let x01 = mix(lattice(cell + vec3<i32>(0, 0, 1)), lattice(cell + vec3<i32>(1, 0, 1)), u.x);
+    let x11 = mix(lattice(cell + vec3<i32>(0, 1, 1)), lattice(cell + vec3<i32>(1, 1, 1)), u.x);
+    return mix(mix(x00, x10, u.y), mix(x01, x11, u.y), u.z);
+}
+
+fn fractal_noise(p: vec3<f32>) -> f32 {
+    return value_noise(p) + value_noise(p * 2.3 + vec3<f32>(5.2, 1.3, 7.1)) * sim.turbulence;
+}
+
+// Vector potential, three decorrelated noise fields drifting over time
+fn potential(p: vec3<f32>) -> vec3<f32> {
+    let drift = vec3<f32>(0.0, sim.time * 0.1, 0.0);
+    let q = p * sim.noise_scale + drift;
+    return vec3<f32>(
+        fractal_noise(q),
+        fractal_noise(q + vec3<f32>(31.4, 17.2, 5.9)),
+        fractal_noise(q + vec3<f32>(-12.7, 43.1, 23.3)),
+    );
+}
+
+// Curl of the potential, divergence free so particles swirl instead of bunching up
+fn curl(p: vec3<f32>) -> vec3<f32> {
+    let e = 0.01;
+    let dx = vec3<f32>(e, 0.0, 0.0);
+    let dy = vec3<f32>(0.0, e, 0.0);
+    let dz = vec3<f32>(0.0, 0.0, e);
+    let px = (potential(p + dx) - potential(p - dx)) / (2.0 * e);
+    let py = (potential(p + dy) - potential(p - dy)) / (2.0 * e);
+    let pz = (potential(p + dz) - potential(p - dz)) / (2.0 * e);
+    return vec3<f32>(py.z - pz.y, pz.x - px.z, px.y - py.x);
+}
+
+fn emitter_vec3(index: u32, offset: u32) -> vec3<f32> {
+    let base = index * 8u + offset;
+    return vec3<f32>(emitters[base], emitters[base + 1u], emitters[base + 2u]);
+}
+
+@compute @workgroup_size(256)
+fn cs_simulate(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= sim.particle_count) {
+        return;
+    }
+    var particle = particles[index];
+
+    if (particle.age >= particle.lifetime) {
+        // Dead, respawn while this step's budget lasts
+        if (sim.emitter_count == 0u || atomicAdd(&spawned, 1u) >= sim.spawn_count) {
+            return;
+        }
+        var state = hash(index ^ hash(sim.seed));
+        let emitter = min(u32(random(&state) * f32(sim.emitter_count)), sim.emitter_count - 1u);
+        let normal = emitter_vec3(emitter, 3u);
+        particle.position = emitter_vec3(emitter, 0u) * sim.radius;
+        particle.velocity = normal * sim.initial_speed * (0.5 + random(&state));
+        particle.age = 0.0;
+        particle.lifetime = sim.lifetime * (0.5 + random(&state));
+        particles[index] = particle;
+        return;
+    }
+
+    particle.velocity += curl(particle.position) * sim.curl_strength * sim.dt;
+    particle.velocity *= exp(-sim.drag * sim.dt);
+    particle.position += particle.velocity * sim.dt;
+    particle.age += sim.dt;
+    particles[index] = particle;
+}
+"#;
+
+const RENDER_WGSL: &str = r#"
+struct Particle {
+    position: vec3<f32>,
+    age: f32,
+    velocity: vec3<f32>,
+    lifetime: f32,
+};
+
+struct RenderParams {
+    model_view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    point_size: f32,
+    opacity: f32,
+};
+@group(0) @binding(0)
+var<uniform> params: RenderParams;
+@group(0) @binding(1)
+var<storage, read> particles: array<Particle>;
+// Palette gradient baked into a lookup table, sampled by age
+@group(0) @binding(2)
+var<storage, read> gradient: array<vec4<f32>>;
+
+fn gradient_color(t: f32) -> vec3<f32> {
+    let last = arrayLength(&gradient) - 1u;
+    let x = clamp(t, 0.0, 1.0) * f32(last);
+    let i = min(u32(floor(x)), last);
+    let j = min(i + 1u, last);
+    return mix(gradient[i].rgb, gradient[j].rgb, fract(x));
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) local: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+// Two triangles per particle, offset in view space so they always face the camera
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+    let particle = particles[instance_index];
+
+    var out: VertexOutput;
+    out.local = corner;
+    if (particle.age >= particle.lifetime) {
+        // Dead, collapse the quad so nothing gets rasterized
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+        out.color = vec4<f32>(0.0);
+        return out;
+    }
+
+    let life = particle.age / particle.lifetime;
+    // Fade in quickly, then out over the rest of the lifetime
+    let alpha = smoothstep(0.0, 0.05, life) * (1.0 - life) * params.opacity;
+    let view_position = params.model_view * vec4<f32>(particle.position, 1.0);
+    let offset = corner * params.point_size * (1.0 - life * 0.5);
+    out.clip_position = params.projection * vec4<f32>(view_position.xy + offset, view_position.zw);
+    out.color = vec4<f32>(gradient_color(life), alpha);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let distance = length(in.local);
+    if (distance > 1.0) {
+        discard;
+    }
+    let falloff = 1.0 - smoothstep(0.0, 1.0, distance);
+    return vec4<f32>(in.color.rgb, in.color.a * falloff);
+}
+"#;
+
+pub struct ParticlePrimitive {
+    sim_params_uniform_buffer: wgpu::Buffer,
+    spawn_counter_buffer: wgpu::Buffer,
+    render_params_uniform_buffer: wgpu::Buffer,
+    gradient_buffer: wgpu::Buffer,
+    compute_bind_group_layout: wgpu::BindGroupLayout,
+    render_bind_group_layout: wgpu::BindGroupLayout,
+    compute_pipeline: wgpu::ComputePipeline,
+    render_pipeline: wgpu::RenderPipeline,
+}
+
+// Per particle count / emitter shape, recreated when either changes
+pub struct ParticleBuffers {
+    count: usize,
+    emitter_shape: Shape,
+    emitter_count: usize,
+    compute_bind_group: wgpu::BindGroup,
+    render_bind_group: wgpu::BindGroup,
+}
+
+impl ParticleBuffers {
+    fn new(
+        device: &wgpu::Device,
+        primitive: &ParticlePrimitive,
+        count: usize,
+        emitter_shape: Shape,
+    ) -> Self {
+        let emitters = emitter_shape.generate(1.0, EMITTER_POINTS);
+        // Zeroed, so every particle starts out dead
+        let particle_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Storage Buffer"),
+            size: (count * 32) as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        // Storage buffers can't be empty
+        let emitter_contents: &[GeometryPoint] = if emitters.is_empty() {
+            &[GeometryPoint::zeroed()]
+        } else {
+            &emitters
+        };
+        let emitter_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particle Emitter Storage Buffer"),
+            contents: bytemuck::cast_slice(emitter_contents),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let compute_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Particle Compute Bind Group"),
+            layout: &primitive.compute_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: primitive.sim_params_uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: particle_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: emitter_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: primitive.spawn_counter_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Particle Render Bind Group"),
+            layout: &primitive.render_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: primitive.render_params_uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: particle_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: primitive.gradient_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        ParticleBuffers {
+            count,
+            emitter_shape,
+            emitter_count: emitters.len(),
+            compute_bind_group,
+            render_bind_group,
+        }
+    }
+}
+
+// Compute shader particles pushed around by curl noise, spawned from a shape's
+// surface. Loudness drives the flow strength, bass the turbulence and onsets
+// release bursts of new particles.
+pub struct ParticleSystem {
+    primitive: Option<Arc<ParticlePrimitive>>,
+    device: Option<Arc<wgpu::Device>>,
+    buffers: Option<Arc<ParticleBuffers>>,
+    pub particle_count: usize,
+    pub emitter_shape: Shape,
+    pub emitter_radius: f32,
+    // Particles per second while nothing is happening
+    pub emission_rate: f32,
+    // Particles released per onset at `onset_threshold`, louder onsets release more
+    pub burst_size: f32,
+    pub onset_threshold: f32,
+    // Seconds, each particle gets 0.5x to 1.5x of this
+    pub lifetime: f32,
+    pub initial_speed: f32,
+    pub curl_strength: f32,
+    // How much louder playback strengthens the flow
+    pub strength_reactivity: f32,
+    pub noise_scale: f32,
+    pub turbulence: f32,
+    // How much bass adds to `turbulence`
+    pub turbulence_reactivity: f32,
+    pub drag: f32,
+    pub point_size: f32,
+    pub opacity: f32,
+    pub palette: PaletteSettings,
+    pub camera: OrbitCamera,
+    audio: AudioFeatures,
+    // Fractional particles carried over to the next step
+    spawn_accumulator: f32,
+    // Seconds of simulation waiting for the next frame
+    pending_step: f32,
+    time: f32,
+    spin: f32,
+    frame_index: u32,
+    onsets: OnsetEdge,
+    // Why the adapter can't run the simulation, particles stay off then
+    unsupported: Option<String>,
+}
+
+impl ParticleSystem {
+    pub fn new() -> Self {
+        ParticleSystem {
+            primitive: None,
+            device: None,
+            buffers: None,
+            particle_count: DEFAULT_PARTICLE_COUNT,
+            emitter_shape: Shape::Sphere,
+            emitter_radius: 1.0,
+            emission_rate: 5_000.0,
+            burst_size: 20_000.0,
+            onset_threshold: 1.0,
+            lifetime: 3.0,
+            initial_speed: 0.3,
+            curl_strength: 1.5,
+            strength_reactivity: 4.0,
+            noise_scale: 1.2,
+            turbulence: 0.3,
+            turbulence_reactivity: 0.7,
+            drag: 0.8,
+            point_size: 0.008,
+            opacity: 0.6,
+            palette: PaletteSettings::new(PaletteChoice::Named(NamedPalette::Fire)),
+            camera: OrbitCamera::new(),
+            audio: AudioFeatures::default(),
+            spawn_accumulator: 0.0,
+            pending_step: 0.0,
+            time: 0.0,
+            spin: 0.0,
+            frame_index: 0,
+            onsets: OnsetEdge::default(),
+            unsupported: None,
+        }
+    }
+
+    // Rebuilds the particle buffers when the count or emitter shape changed
+    fn ensure_buffers(&mut self) {
+        let (Some(primitive), Some(device)) = (&self.primitive, &self.device) else {
+            return;
+        };
+        let up_to_date = self.buffers.as_ref().is_some_and(|buffers| {
+            buffers.count == self.particle_count && buffers.emitter_shape == self.emitter_shape
+        });
+        if !up_to_date {
+            self.buffers = Some(Arc::new(ParticleBuffers::new(
+                device,
+                primitive,
+                self.particle_count,
+                self.emitter_shape,
+            )));
+        }
+    }
+
+    // Steady trickle plus a burst for every new window with an onset over the threshold
+    fn update_audio(&mut self, analysis: Option<&AudioAnalysisData>, dt: f32) {
+        self.spawn_accumulator += self.emission_rate * dt;
+
+        self.audio.update(analysis, dt);
+        let Some(onset) = self.onsets.poll(analysis) else {
+            return;
+        };
+        if onset >= self.onset_threshold {
+            self.spawn_accumulator += self.burst_size * onset / self.onset_threshold;
+        }
+    }
+
+    // `buffers` rather than the settings, those may have changed since the last update
+    fn sim_params(&mut self, dt: f32, buffers: &ParticleBuffers) -> SimParamsUniform {
+        // Anything that doesn't fit this step is dropped, bursts shouldn't pile up
+        let spawn_count = self.spawn_accumulator.min(buffers.count as f32);
+        self.spawn_accumulator = spawn_count.fract();
+        self.frame_index = self.frame_index.wrapping_add(1);
+        SimParamsUniform {
+            dt,
+            time: self.time,
+            curl_strength: self.curl_strength * (1.0 + self.audio.rms * self.strength_reactivity),
+            noise_scale: self.noise_scale,
+            turbulence: (self.turbulence + self.audio.bass * self.turbulence_reactivity)
+                .clamp(0.0, 1.0),
+            drag: self.drag,
+            lifetime: self.lifetime,
+            initial_speed: self.initial_speed,
+            radius: self.emitter_radius,
+            particle_count: buffers.count as u32,
+            emitter_count: buffers.emitter_count as u32,
+            spawn_count: spawn_count as u32,
+            seed: self.frame_index,
+            _padding: [0; 3],
+        }
+    }
+
+    fn render_params(&self, aspect_ratio: f32) -> ParticleRenderUniform {
+        let model = Mat4::from_rotation_y(self.spin * 0.2);
+        ParticleRenderUniform {
+            model_view: self.camera.pose.view_matrix() * model,
+            // wgpu clip space depth is 0..1, not GL's -1..1
+            projection: Mat4::perspective_rh(std::f32::consts::FRAC_PI_4, aspect_ratio, 0.1, 100.0),
+            point_size: self.point_size,
+            opacity: self.opacity,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+impl Visualizer for ParticleSystem {
+    fn name(&self) -> &'static str {
+        "Particles"
+    }
+
+    fn prepare(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        target_format: wgpu::TextureFormat,
+    ) -> Result<()> {
+        if self.primitive.is_some() {
+            return Ok(());
+        }
+        if let Some(reason) = &self.unsupported {
+            bail!("{}", reason);
+        }
+        tracing::info!("Preparing Particle resources...");
+
+        let storage_entry = |binding, visibility, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let uniform_entry = |visibility| wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let compute_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Particle Compute Bind Group Layout"),
+                entries: &[
+                    uniform_entry(wgpu::ShaderStages::COMPUTE),
+                    storage_entry(1, wgpu::ShaderStages::COMPUTE, false),
+                    storage_entry(2, wgpu::ShaderStages::COMPUTE, true),
+                    storage_entry(3, wgpu::ShaderStages::COMPUTE, false),
+                ],
+            });
+        let render_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Particle Render Bind Group Layout"),
+                entries: &[
+                    uniform_entry(wgpu::ShaderStages::VERTEX),
+                    storage_entry(1, wgpu::ShaderStages::VERTEX, true),
+                    storage_entry(2, wgpu::ShaderStages::VERTEX, true),
+                ],
+            });
+
+        let sim_params_uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Sim Params Uniform Buffer"),
+            size: std::mem::size_of::<SimParamsUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let spawn_counter_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Spawn Counter Buffer"),
+            size: 4,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let render_params_uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Render Params Uniform Buffer"),
+            size: std::mem::size_of::<ParticleRenderUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let gradient_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particle Gradient Storage Buffer"),
+            contents: bytemuck::cast_slice(&self.palette.gradient().bake(GRADIENT_RESOLUTION)),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let simulate_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Particle Simulation Shader"),
+            source: wgpu::ShaderSource::Wgsl(SIMULATE_WGSL.into()),
+        });
+        let compute_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Particle Compute Pipeline Layout"),
+                bind_group_layouts: &[&compute_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Particle Compute Pipeline"),
+            layout: Some(&compute_pipeline_layout),
+            module: &simulate_module,
+            entry_point: "cs_simulate",
+        });
+
+        let render_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Particle Render Shader"),
+            source: wgpu::ShaderSource::Wgsl(RENDER_WGSL.into()),
+        });
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Particle Render Pipeline Layout"),
+                bind_group_layouts: &[&render_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Particle Render Pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &render_module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &render_module,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    // Additive, dense spots glow
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::SrcAlpha,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent::OVER,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        self.primitive = Some(Arc::new(ParticlePrimitive {
+            sim_params_uniform_buffer,
+            spawn_counter_buffer,
+            render_params_uniform_buffer,
+            gradient_buffer,
+            compute_bind_group_layout,
+            render_bind_group_layout,
+            compute_pipeline,
+            render_pipeline,
+        }));
+        self.device = Some(device.clone());
+        self.ensure_buffers();
+        tracing::info!("Particle resources prepared successfully.");
+        Ok(())
+    }
+
+    fn update(&mut self, input: &VisualizerInput, dt: f32) {
+        self.time += dt;
+        if self.camera.auto_rotate {
+            self.spin += dt;
+        }
+        let analysis = input
+            .analysis
+            .filter(|_| input.playback_state == PlaybackState::Playing);
+        self.camera.update(analysis, dt);
+        self.update_audio(analysis, dt);
+        self.pending_step += dt;
+        self.ensure_buffers();
+    }
+
+    fn paint(&mut self, aspect_ratio: f32) -> Option<Box<dyn VisualizerFrame>> {
+        let primitive = self.primitive.clone()?;
+        let buffers = self.buffers.clone()?;
+        let step = std::mem::take(&mut self.pending_step).min(MAX_STEP);
+        Some(Box::new(ParticleFrame {
+            primitive,
+            sim_params: self.sim_params(step, &buffers),
+            render_params: self.render_params(aspect_ratio),
+            gradient: self.palette.gradient().bake(GRADIENT_RESOLUTION),
+            buffers,
+        }))
+    }
+
+    fn settings_ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(
+            egui::Slider::new(
+                &mut self.particle_count,
+                MIN_PARTICLE_COUNT..=MAX_PARTICLE_COUNT,
+            )
+            .logarithmic(true)
+            .text("Particles"),
+        );
+        ui.horizontal(|ui| {
+            ui.label("Emit from:");
+            egui::ComboBox::from_id_source("particle_emitter_shape")
+                .selected_text(self.emitter_shape.label())
+                .show_ui(ui, |ui| {
+                    for shape in Shape::ALL {
+                        ui.selectable_value(&mut self.emitter_shape, shape, shape.label());
+                    }
+                });
+        });
+        ui.add(egui::Slider::new(&mut self.emitter_radius, 0.25..=2.0).text("Emitter radius"));
+
+        ui.label("Emission:");
+        ui.add(
+            egui::Slider::new(&mut self.emission_rate, 0.0..=100_000.0)
+                .logarithmic(true)
+                .suffix(" /s")
+                .text("Rate"),
+        );
+        ui.add(
+            egui::Slider::new(&mut self.burst_size, 0.0..=200_000.0)
+                .logarithmic(true)
+                .text("Burst on onset"),
+        );
+        ui.add(egui::Slider::new(&mut self.onset_threshold, 0.2..=5.0).text("Onset threshold"));
+        ui.add(
+            egui::Slider::new(&mut self.lifetime, 0.2..=10.0)
+                .suffix(" s")
+                .text("Lifetime"),
+        );
+        ui.add(egui::Slider::new(&mut self.initial_speed, 0.0..=2.0).text("Initial speed"));
+
+        ui.label("Flow:");
+        ui.add(egui::Slider::new(&mut self.curl_strength, 0.0..=10.0).text("Strength"));
+        ui.add(
+            egui::Slider::new(&mut self.strength_reactivity, 0.0..=10.0)
+                .text("Strength reactivity"),
+        );
+        ui.add(egui::Slider::new(&mut self.noise_scale, 0.1..=5.0).text("Noise scale"));
+        ui.add(egui::Slider::new(&mut self.turbulence, 0.0..=1.0).text("Turbulence"));
+        ui.add(
+            egui::Slider::new(&mut self.turbulence_reactivity, 0.0..=1.0)
+                .text("Turbulence reactivity"),
+        );
+        ui.add(egui::Slider::new(&mut self.drag, 0.0..=5.0).text("Drag"));
+
+        ui.label("Look:");
+        ui.add(
+            egui::Slider::new(&mut self.point_size, 0.001..=0.05)
+                .logarithmic(true)
+                .text("Size"),
+        );
+        ui.add(egui::Slider::new(&mut self.opacity, 0.05..=1.0).text("Opacity"));
+        self.palette.settings_ui(ui, "particle_palette");
+
+        ui.label("Camera:");
+        self.camera.settings_ui(ui);
+    }
+
+    // WebGL2 and some GLES drivers have neither compute shaders nor storage buffers in
+    // vertex shaders, the simulation and the sprites need both
+    fn set_adapter(&mut self, adapter: &Arc<wgpu::Adapter>) {
+        let flags = adapter.get_downlevel_capabilities().flags;
+        self.unsupported = if !flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS) {
+            Some("Particles need compute shaders, which this GPU doesn't support".into())
+        } else if !flags.contains(wgpu::DownlevelFlags::VERTEX_STORAGE) {
+            Some(
+                "Particles need vertex shader storage buffers, which this GPU doesn't support"
+                    .into(),
+            )
+        } else {
+            None
+        };
+    }
+
+    fn handle_input(&mut self, response: &egui::Response) {
+        self.camera.handle_input(response);
+    }
+
+    fn overlay_message(&self) -> Option<String> {
+        self.unsupported.clone()
+    }
+}
+
+struct ParticleFrame {
+    primitive: Arc<ParticlePrimitive>,
+    buffers: Arc<ParticleBuffers>,
+    sim_params: SimParamsUniform,
+    render_params: ParticleRenderUniform,
+    gradient: Vec<[f32; 4]>,
+}
+
+impl VisualizerFrame for ParticleFrame {
+    // Runs the simulation step, drawing happens in `paint`
+    fn prepare(
+        &self,
+        _device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        _size_in_pixels: [u32; 2],
+    ) {
+        let primitive = &self.primitive;
+        queue.write_buffer(
+            &primitive.sim_params_uniform_buffer,
+            0,
+            bytemuck::bytes_of(&self.sim_params),
+        );
+        queue.write_buffer(
+            &primitive.spawn_counter_buffer,
+            0,
+            bytemuck::bytes_of(&0u32),
+        );
+        queue.write_buffer(
+            &primitive.render_params_uniform_buffer,
+            0,
+            bytemuck::bytes_of(&self.render_params),
+        );
+        queue.write_buffer(
+            &primitive.gradient_buffer,
+            0,
+            bytemuck::cast_slice(&self.gradient),
+        );
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Particle Simulation Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&primitive.compute_pipeline);
+        compute_pass.set_bind_group(0, &self.buffers.compute_bind_group, &[]);
+        compute_pass.dispatch_workgroups(
+            (self.buffers.count as u32).div_ceil(WORKGROUP_SIZE),
+            1,
+            1,
+        );
+    }
+
+    fn paint<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, _queue: &wgpu::Queue) {
+        render_pass.set_pipeline(&self.primitive.render_pipeline);
+        render_pass.set_bind_group(0, &self.buffers.render_bind_group, &[]);
+        render_pass.draw(0..6, 0..self.buffers.count as u32);
+    }
+}
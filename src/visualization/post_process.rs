@@ -0,0 +1,857 @@
+use crate::audio::PlaybackState;
+use crate::visualization::audio_features::AudioFeatures;
+use crate::visualization::shader_source::with_fullscreen_vertex;
+use crate::visualization::visualizer::{VisualizerFrame, VisualizerInput};
+use anyhow::Result;
+use bytemuck::{Pod, Zeroable};
+use eframe::egui;
+use parking_lot::Mutex;
+use std::sync::{Arc, OnceLock};
+use wgpu::util::DeviceExt;
+
+// Visualizers render into this, tone mapping brings it back down to the screen format
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+// Horizontal + vertical blur rounds over the half resolution bright pass
+const BLOOM_BLUR_PASSES: usize = 2;
+// Frame rate the trails intensity is the per frame decay at, other rates get it rescaled
+// so the trails last as long at 30 fps as at 144
+const TRAIL_REFERENCE_FPS: f32 = 60.0;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct PostParamsUniform {
+    bloom_intensity: f32,
+    bloom_threshold: f32,
+    trail_decay: f32,
+    vignette: f32,
+    aberration: f32,
+    exposure: f32,
+    tone_mapping: u32,
+    _padding: f32,
+}
+
+// Shared by every pass. The scene is premultiplied RGBA over transparent black, so all of
+// these stay linear in alpha too, and the final pass blends premultiplied over egui.
+const POST_WGSL: &str = r#"
+@group(0) @binding(0)
+var source_texture: texture_2d<f32>;
+// Second input: trail history for trails, the blurred bright pass for bloom
+@group(0) @binding(1)
+var aux_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var linear_sampler: sampler;
+
+struct PostParams {
+    bloom_intensity: f32,
+    bloom_threshold: f32,
+    trail_decay: f32,
+    vignette: f32,
+    aberration: f32,
+    exposure: f32,
+    tone_mapping: u32,
+    _padding: f32,
+};
+@group(0) @binding(3)
+var<uniform> params: PostParams;
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Old frames fade out underneath the new one
+@fragment
+fn fs_trails(in: VertexOutput) -> @location(0) vec4<f32> {
+    let current = textureSample(source_texture, linear_sampler, in.uv);
+    let history = textureSample(aux_texture, linear_sampler, in.uv) * params.trail_decay;
+    return max(current, history);
+}
+
+// Keeps only what's brighter than the threshold, with a soft knee
+@fragment
+fn fs_bright(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source_texture, linear_sampler, in.uv);
+    let brightness = luminance(color.rgb);
+    let keep = max(brightness - params.bloom_threshold, 0.0) / max(brightness, 0.0001);
+    return color * keep;
+}
+
+// 9 tap gaussian, run once across and once down
+fn blur(uv: vec2<f32>, direction: vec2<f32>) -> vec4<f32> {
+    var weights = array<f32, 5>(0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+    let texel = direction / vec2<f32>(textureDimensions(source_texture));
+    var sum = textureSample(source_texture, linear_sampler, uv) * weights[0];
+    for (var i = 1; i < 5; i++) {
+        let offset = texel * f32(i);
+        sum += textureSample(source_texture, linear_sampler, uv + offset) * weights[i];
+        sum += textureSample(source_texture, linear_sampler, uv - offset) * weights[i];
+    }
+    return sum;
+}
+
+@fragment
+fn fs_blur_horizontal(in: VertexOutput) -> @location(0) vec4<f32> {
+    return blur(in.uv, vec2<f32>(1.0, 0.0));
+}
+
+@fragment
+fn fs_blur_vertical(in: VertexOutput) -> @location(0) vec4<f32> {
+    return blur(in.uv, vec2<f32>(0.0, 1.0));
+}
+
+@fragment
+fn fs_bloom(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source_texture, linear_sampler, in.uv);
+    let bloom = textureSample(aux_texture, linear_sampler, in.uv);
+    return color + bloom * params.bloom_intensity;
+}
+
+// Red and blue get pulled apart towards the edges, like a cheap lens
+@fragment
+fn fs_chromatic(in: VertexOutput) -> @location(0) vec4<f32> {
+    let offset = (in.uv - 0.5) * params.aberration;
+    let red = textureSample(source_texture, linear_sampler, in.uv + offset);
+    let green = textureSample(source_texture, linear_sampler, in.uv);
+    let blue = textureSample(source_texture, linear_sampler, in.uv - offset);
+    return vec4<f32>(red.r, green.g, blue.b, max(max(red.a, green.a), blue.a));
+}
+
+@fragment
+fn fs_vignette(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source_texture, linear_sampler, in.uv);
+    // 0 in the centre, 1 in the corners
+    let distance = length(in.uv - 0.5) * 1.41421356;
+    let darken = 1.0 - params.vignette * smoothstep(0.3, 1.0, distance);
+    return vec4<f32>(color.rgb * darken, color.a);
+}
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    return clamp(
+        (color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14),
+        vec3<f32>(0.0),
+        vec3<f32>(1.0),
+    );
+}
+
+@fragment
+fn fs_tonemap(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source_texture, linear_sampler, in.uv);
+    let exposed = color.rgb * params.exposure;
+    var mapped: vec3<f32>;
+    switch params.tone_mapping {
+        case 1u: {
+            mapped = exposed / (1.0 + exposed);
+        }
+        case 2u: {
+            mapped = aces(exposed);
+        }
+        default: {
+            mapped = clamp(exposed, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+    }
+    // Glow spilling over transparent areas needs some coverage to show up
+    let alpha = clamp(max(color.a, max(mapped.r, max(mapped.g, mapped.b))), 0.0, 1.0);
+    return vec4<f32>(mapped, alpha);
+}
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostEffectKind {
+    Bloom,
+    Trails,
+    Vignette,
+    ChromaticAberration,
+}
+
+impl PostEffectKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PostEffectKind::Bloom => "Bloom",
+            PostEffectKind::Trails => "Trails",
+            PostEffectKind::Vignette => "Vignette",
+            PostEffectKind::ChromaticAberration => "Chromatic Aberration",
+        }
+    }
+
+    // Range of the intensity slider, audio drive is clamped to it as well
+    fn intensity_range(&self) -> std::ops::RangeInclusive<f32> {
+        match self {
+            PostEffectKind::Bloom => 0.0..=3.0,
+            // Fraction of the previous frame that survives
+            PostEffectKind::Trails => 0.0..=0.98,
+            PostEffectKind::Vignette => 0.0..=1.0,
+            // Channel offset at the corners, in fractions of the rect
+            PostEffectKind::ChromaticAberration => 0.0..=0.05,
+        }
+    }
+}
+
+// Audio feature an effect intensity can follow, all normalised to 0..1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioDrive {
+    None,
+    Rms,
+    Peak,
+    Bass,
+    Onset,
+}
+
+impl AudioDrive {
+    pub const ALL: [AudioDrive; 5] = [
+        AudioDrive::None,
+        AudioDrive::Rms,
+        AudioDrive::Peak,
+        AudioDrive::Bass,
+        AudioDrive::Onset,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            AudioDrive::None => "None",
+            AudioDrive::Rms => "RMS",
+            AudioDrive::Peak => "Peak",
+            AudioDrive::Bass => "Bass",
+            AudioDrive::Onset => "Onset",
+        }
+    }
+
+    fn level(&self, features: &AudioFeatures) -> f32 {
+        match self {
+            AudioDrive::None => 0.0,
+            AudioDrive::Rms => features.rms,
+            AudioDrive::Peak => features.peak,
+            AudioDrive::Bass => features.bass,
+            AudioDrive::Onset => features.onset,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMapping {
+    Clamp,
+    Reinhard,
+    Aces,
+}
+
+impl ToneMapping {
+    pub const ALL: [ToneMapping; 3] =
+        [ToneMapping::Clamp, ToneMapping::Reinhard, ToneMapping::Aces];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ToneMapping::Clamp => "Clamp",
+            ToneMapping::Reinhard => "Reinhard",
+            ToneMapping::Aces => "ACES",
+        }
+    }
+
+    // CPU copy of fs_tonemap's curves, for frames drawn without a GPU
+    pub fn apply(&self, color: [f32; 3], exposure: f32) -> [f32; 3] {
+        color.map(|channel| {
+            let x = channel * exposure;
+            match self {
+                ToneMapping::Clamp => x.clamp(0.0, 1.0),
+                ToneMapping::Reinhard => x / (1.0 + x),
+                ToneMapping::Aces => {
+                    ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+                }
+            }
+        })
+    }
+}
+
+// One link of the chain
+#[derive(Debug, Clone)]
+pub struct PostEffect {
+    pub kind: PostEffectKind,
+    pub enabled: bool,
+    pub intensity: f32,
+    pub drive: AudioDrive,
+    // Added to the intensity at full feature level, can be negative
+    pub drive_amount: f32,
+}
+
+impl PostEffect {
+    fn new(kind: PostEffectKind, intensity: f32) -> Self {
+        PostEffect {
+            kind,
+            enabled: false,
+            intensity,
+            drive: AudioDrive::None,
+            drive_amount: 0.0,
+        }
+    }
+}
+
+struct PostTexture {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+}
+
+// Everything sized after the rect
+struct PostTargets {
+    size: [u32; 2],
+    scene: PostTexture,
+    ping: PostTexture,
+    pong: PostTexture,
+    // Last frame's output for trails
+    history: PostTexture,
+    // Half resolution bloom buffers
+    bloom_a: PostTexture,
+    bloom_b: PostTexture,
+    // Whether `history` holds last frame, it's stale once trails have been off for a frame
+    history_valid: bool,
+}
+
+pub struct PostProcessPrimitive {
+    params_uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    trails_pipeline: wgpu::RenderPipeline,
+    bright_pipeline: wgpu::RenderPipeline,
+    blur_horizontal_pipeline: wgpu::RenderPipeline,
+    blur_vertical_pipeline: wgpu::RenderPipeline,
+    bloom_pipeline: wgpu::RenderPipeline,
+    chromatic_pipeline: wgpu::RenderPipeline,
+    vignette_pipeline: wgpu::RenderPipeline,
+    // Renders into egui's pass, in the screen format
+    tonemap_pipeline: wgpu::RenderPipeline,
+    targets: Mutex<Option<PostTargets>>,
+}
+
+impl PostProcessPrimitive {
+    fn create_texture(device: &wgpu::Device, label: &str, size: [u32; 2]) -> PostTexture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size[0].max(1),
+                height: size[1].max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        PostTexture { texture, view }
+    }
+
+    fn create_targets(device: &wgpu::Device, size: [u32; 2]) -> PostTargets {
+        let half = [size[0] / 2, size[1] / 2];
+        PostTargets {
+            size,
+            scene: Self::create_texture(device, "Post Scene Texture", size),
+            ping: Self::create_texture(device, "Post Ping Texture", size),
+            pong: Self::create_texture(device, "Post Pong Texture", size),
+            history: Self::create_texture(device, "Post Trail History Texture", size),
+            bloom_a: Self::create_texture(device, "Post Bloom Texture A", half),
+            bloom_b: Self::create_texture(device, "Post Bloom Texture B", half),
+            history_valid: false,
+        }
+    }
+
+    fn bind_group(
+        &self,
+        device: &wgpu::Device,
+        source: &wgpu::TextureView,
+        aux: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Post Process Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(aux),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.params_uniform_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    // One fullscreen pass from `source` (and `aux`) into `target`
+    fn run_pass(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        source: &wgpu::TextureView,
+        aux: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+    ) {
+        let bind_group = self.bind_group(device, source, aux);
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Post Process Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
+    }
+}
+
+// Configurable chain of fullscreen effects between the visualizer and the screen
+pub struct PostProcessor {
+    primitive: Option<Arc<PostProcessPrimitive>>,
+    // Run in this order, tone mapping always comes last
+    pub effects: Vec<PostEffect>,
+    // Luminance the bloom starts at, anything above 1 is HDR only
+    pub bloom_threshold: f32,
+    pub tone_mapping: ToneMapping,
+    pub exposure: f32,
+    // Seconds the frame being drawn covers, the trails decay over this much time
+    frame_dt: f32,
+    features: AudioFeatures,
+    // Added to the bloom intensity by the active mode's modulation routes. Takes the place
+    // of the bloom's own audio drive while set, so the two don't stack.
+    bloom_modulation: Option<f32>,
+}
+
+impl PostProcessor {
+    pub fn new() -> Self {
+        PostProcessor {
+            primitive: None,
+            effects: vec![
+                PostEffect::new(PostEffectKind::Trails, 0.85),
+                PostEffect::new(PostEffectKind::Bloom, 0.8),
+                PostEffect::new(PostEffectKind::ChromaticAberration, 0.01),
+                PostEffect::new(PostEffectKind::Vignette, 0.5),
+            ],
+            bloom_threshold: 0.6,
+            tone_mapping: ToneMapping::Clamp,
+            exposure: 1.0,
+            frame_dt: 1.0 / TRAIL_REFERENCE_FPS,
+            features: AudioFeatures::default(),
+            bloom_modulation: None,
+        }
+    }
+
+    pub fn prepare(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        target_format: wgpu::TextureFormat,
+    ) -> Result<()> {
+        if self.primitive.is_some() {
+            return Ok(());
+        }
+
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Post Process Shader"),
+            source: wgpu::ShaderSource::Wgsl(with_fullscreen_vertex(POST_WGSL).into()),
+        });
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Post Process Bind Group Layout"),
+            entries: &[
+                texture_entry(0),
+                texture_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let params_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post Process Params Uniform Buffer"),
+            contents: bytemuck::bytes_of(&self.params()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Process Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Process Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |entry_point: &str, format, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader_module,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader_module,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+
+        self.primitive = Some(Arc::new(PostProcessPrimitive {
+            params_uniform_buffer,
+            bind_group_layout,
+            sampler,
+            trails_pipeline: create_pipeline("fs_trails", HDR_FORMAT, None),
+            bright_pipeline: create_pipeline("fs_bright", HDR_FORMAT, None),
+            blur_horizontal_pipeline: create_pipeline("fs_blur_horizontal", HDR_FORMAT, None),
+            blur_vertical_pipeline: create_pipeline("fs_blur_vertical", HDR_FORMAT, None),
+            bloom_pipeline: create_pipeline("fs_bloom", HDR_FORMAT, None),
+            chromatic_pipeline: create_pipeline("fs_chromatic", HDR_FORMAT, None),
+            vignette_pipeline: create_pipeline("fs_vignette", HDR_FORMAT, None),
+            tonemap_pipeline: create_pipeline(
+                "fs_tonemap",
+                target_format,
+                Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+            ),
+            targets: Mutex::new(None),
+        }));
+        tracing::info!("Post processing resources prepared successfully.");
+        Ok(())
+    }
+
+    pub fn update(&mut self, input: &VisualizerInput, dt: f32) {
+        let playing = input.playback_state == PlaybackState::Playing;
+        self.frame_dt = dt;
+        self.features.update(input.analysis.filter(|_| playing), dt);
+    }
+
+    pub fn set_bloom_modulation(&mut self, amount: Option<f32>) {
+        self.bloom_modulation = amount;
+    }
+
+    // Intensity after audio drive, None when the effect is off
+    fn intensity(&self, kind: PostEffectKind) -> Option<f32> {
+        let effect = self.effects.iter().find(|e| e.kind == kind && e.enabled)?;
+        let range = kind.intensity_range();
+        let drive = match self.bloom_modulation {
+            Some(modulation) if kind == PostEffectKind::Bloom => modulation,
+            _ => effect.drive_amount * effect.drive.level(&self.features),
+        };
+        Some((effect.intensity + drive).clamp(*range.start(), *range.end()))
+    }
+
+    fn params(&self) -> PostParamsUniform {
+        PostParamsUniform {
+            bloom_intensity: self.intensity(PostEffectKind::Bloom).unwrap_or(0.0),
+            bloom_threshold: self.bloom_threshold,
+            trail_decay: self
+                .intensity(PostEffectKind::Trails)
+                .map_or(0.0, |decay| decay.powf(self.frame_dt * TRAIL_REFERENCE_FPS)),
+            vignette: self.intensity(PostEffectKind::Vignette).unwrap_or(0.0),
+            aberration: self
+                .intensity(PostEffectKind::ChromaticAberration)
+                .unwrap_or(0.0),
+            exposure: self.exposure,
+            tone_mapping: self.tone_mapping as u32,
+            _padding: 0.0,
+        }
+    }
+
+    // Runs `frame` through the chain. None until `prepare` has succeeded.
+    pub fn wrap(&self, frame: Box<dyn VisualizerFrame>) -> Option<Box<dyn VisualizerFrame>> {
+        Some(Box::new(PostProcessFrame {
+            primitive: self.primitive.clone()?,
+            inner: frame,
+            chain: self
+                .effects
+                .iter()
+                .filter(|e| e.enabled)
+                .map(|e| e.kind)
+                .collect(),
+            params: self.params(),
+            output: OnceLock::new(),
+        }))
+    }
+
+    pub fn settings_ui(&mut self, ui: &mut egui::Ui) {
+        let mut move_up = None;
+        let effect_count = self.effects.len();
+        let bloom_routed = self.bloom_modulation.is_some();
+        for (index, effect) in self.effects.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut effect.enabled, effect.kind.label());
+                if ui
+                    .add_enabled(index > 0, egui::Button::new("⏶").small())
+                    .clicked()
+                {
+                    move_up = Some(index);
+                }
+                if ui
+                    .add_enabled(index + 1 < effect_count, egui::Button::new("⏷").small())
+                    .clicked()
+                {
+                    move_up = Some(index + 1);
+                }
+            });
+            if !effect.enabled {
+                continue;
+            }
+            ui.indent(effect.kind.label(), |ui| {
+                ui.add(
+                    egui::Slider::new(&mut effect.intensity, effect.kind.intensity_range())
+                        .text("Intensity"),
+                );
+                if effect.kind == PostEffectKind::Bloom && bloom_routed {
+                    ui.label("Audio drive: from the preset's modulation routes");
+                } else {
+                    ui.horizontal(|ui| {
+                        ui.label("Audio drive:");
+                        egui::ComboBox::from_id_source(("post_effect_drive", index))
+                            .selected_text(effect.drive.label())
+                            .show_ui(ui, |ui| {
+                                for drive in AudioDrive::ALL {
+                                    ui.selectable_value(&mut effect.drive, drive, drive.label());
+                                }
+                            });
+                        if effect.drive != AudioDrive::None {
+                            let range = effect.kind.intensity_range();
+                            let span = range.end() - range.start();
+                            ui.add(
+                                egui::Slider::new(&mut effect.drive_amount, -span..=span)
+                                    .text("Amount"),
+                            );
+                        }
+                    });
+                }
+                if effect.kind == PostEffectKind::Bloom {
+                    ui.add(
+                        egui::Slider::new(&mut self.bloom_threshold, 0.0..=2.0).text("Threshold"),
+                    );
+                }
+            });
+        }
+        if let Some(index) = move_up {
+            self.effects.swap(index - 1, index);
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Tone mapping:");
+            egui::ComboBox::from_id_source("post_tone_mapping")
+                .selected_text(self.tone_mapping.label())
+                .show_ui(ui, |ui| {
+                    for mapping in ToneMapping::ALL {
+                        ui.selectable_value(&mut self.tone_mapping, mapping, mapping.label());
+                    }
+                });
+            ui.add(
+                egui::Slider::new(&mut self.exposure, 0.1..=4.0)
+                    .logarithmic(true)
+                    .text("Exposure"),
+            );
+        });
+    }
+}
+
+struct PostProcessFrame {
+    primitive: Arc<PostProcessPrimitive>,
+    inner: Box<dyn VisualizerFrame>,
+    // Enabled effects in order
+    chain: Vec<PostEffectKind>,
+    params: PostParamsUniform,
+    // Bind group reading the chain's last output, for the tone mapping pass
+    output: OnceLock<wgpu::BindGroup>,
+}
+
+impl VisualizerFrame for PostProcessFrame {
+    fn prepare(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        size_in_pixels: [u32; 2],
+    ) {
+        let primitive = &self.primitive;
+        queue.write_buffer(
+            &primitive.params_uniform_buffer,
+            0,
+            bytemuck::bytes_of(&self.params),
+        );
+
+        self.inner.prepare(device, queue, encoder, size_in_pixels);
+
+        let mut targets = primitive.targets.lock();
+        if targets.as_ref().map(|t| t.size) != Some(size_in_pixels) {
+            *targets = Some(PostProcessPrimitive::create_targets(device, size_in_pixels));
+        }
+        let Some(targets) = targets.as_mut() else {
+            return;
+        };
+
+        // The visualizer draws exactly like it would straight into egui's pass
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Post Scene Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &targets.scene.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            self.inner.paint(&mut render_pass, queue);
+        }
+
+        // 0 = scene, 1 = ping, 2 = pong
+        let mut current = 0;
+        let mut trails_ran = false;
+        for kind in &self.chain {
+            let output = if current == 1 { 2 } else { 1 };
+            let textures = [&targets.scene, &targets.ping, &targets.pong];
+            let (source, target) = (&textures[current].view, &textures[output].view);
+            match kind {
+                PostEffectKind::Trails => {
+                    if !targets.history_valid {
+                        // Nothing to fade out yet, start from the current frame
+                        encoder.copy_texture_to_texture(
+                            textures[current].texture.as_image_copy(),
+                            targets.history.texture.as_image_copy(),
+                            targets.history.texture.size(),
+                        );
+                    }
+                    primitive.run_pass(
+                        device,
+                        encoder,
+                        &primitive.trails_pipeline,
+                        source,
+                        &targets.history.view,
+                        target,
+                    );
+                    encoder.copy_texture_to_texture(
+                        textures[output].texture.as_image_copy(),
+                        targets.history.texture.as_image_copy(),
+                        targets.history.texture.size(),
+                    );
+                    trails_ran = true;
+                }
+                PostEffectKind::Bloom => {
+                    primitive.run_pass(
+                        device,
+                        encoder,
+                        &primitive.bright_pipeline,
+                        source,
+                        source,
+                        &targets.bloom_a.view,
+                    );
+                    for _ in 0..BLOOM_BLUR_PASSES {
+                        primitive.run_pass(
+                            device,
+                            encoder,
+                            &primitive.blur_horizontal_pipeline,
+                            &targets.bloom_a.view,
+                            &targets.bloom_a.view,
+                            &targets.bloom_b.view,
+                        );
+                        primitive.run_pass(
+                            device,
+                            encoder,
+                            &primitive.blur_vertical_pipeline,
+                            &targets.bloom_b.view,
+                            &targets.bloom_b.view,
+                            &targets.bloom_a.view,
+                        );
+                    }
+                    primitive.run_pass(
+                        device,
+                        encoder,
+                        &primitive.bloom_pipeline,
+                        source,
+                        &targets.bloom_a.view,
+                        target,
+                    );
+                }
+                PostEffectKind::Vignette => primitive.run_pass(
+                    device,
+                    encoder,
+                    &primitive.vignette_pipeline,
+                    source,
+                    source,
+                    target,
+                ),
+                PostEffectKind::ChromaticAberration => primitive.run_pass(
+                    device,
+                    encoder,
+                    &primitive.chromatic_pipeline,
+                    source,
+                    source,
+                    target,
+                ),
+            }
+            current = output;
+        }
+        targets.history_valid = trails_ran;
+
+        let last = &[&targets.scene, &targets.ping, &targets.pong][current].view;
+        let output = primitive.bind_group(device, last, last);
+        let _ = self.output.set(output);
+    }
+
+    fn paint<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, _queue: &wgpu::Queue) {
+        let Some(output) = self.output.get() else {
+            return;
+        };
+        render_pass.set_pipeline(&self.primitive.tonemap_pipeline);
+        render_pass.set_bind_group(0, output, &[]);
+        render_pass.draw(0..6, 0..1);
+    }
+}
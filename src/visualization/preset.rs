@@ -0,0 +1,649 @@
+use crate::audio::cues::config_dir;
+use crate::visualization::modulation::{ModRoute, ModSource, ModTarget, ModulationMatrix};
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PRESET_DIRECTORY_NAME: &str = "presets";
+const DEFAULT_TRANSITION_SECONDS: f32 = 1.5;
+// What presets from before the modulation matrix used when a field was left out
+const LEGACY_AMPLITUDE_GAIN: f32 = 3.0;
+const LEGACY_SATURATION_RANGE: f32 = 0.9;
+const LEGACY_SCALE_RANGE: f32 = 2.5;
+
+// How the point cloud reacts to the music and what its points look like. Missing
+// fields in a file fall back to the defaults, so old presets keep loading.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VisualPreset {
+    // Saturation at silence, routes to Saturation add on top
+    pub saturation_floor: f32,
+    // Saturation while nothing is playing
+    pub idle_saturation: f32,
+    // Same for the overall scale
+    pub scale_floor: f32,
+    pub idle_scale: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    // Share of the way to the targets covered each frame, 1 jumps straight there
+    pub smoothing: f32,
+    // Hue cycles per second
+    pub hue_speed: f32,
+    // Model rotation in radians per second of spin, around Y and X
+    pub spin_rate_y: f32,
+    pub spin_rate_x: f32,
+    // How far a band at full level pushes its points along their normals
+    pub displacement: f32,
+    // Sprite diameter in world units, before audio reactivity
+    pub point_size: f32,
+    // 0 = hard edged discs, 1 = fully soft
+    pub softness: f32,
+    // How much a band at full level grows its points, 1 doubles them
+    pub size_reactivity: f32,
+    pub opacity: f32,
+    // 0 = off, 1 = the back of the shape fades out completely
+    pub depth_fade: f32,
+    // Gradient cycles per second, 0 keeps it still
+    pub palette_cycle_speed: f32,
+    // Which audio features drive which of the above while playing
+    pub modulation: ModulationMatrix,
+}
+
+impl Default for VisualPreset {
+    fn default() -> Self {
+        VisualPreset {
+            saturation_floor: 0.1,
+            idle_saturation: 0.25,
+            scale_floor: 0.75,
+            idle_scale: 1.33,
+            min_scale: 0.75,
+            max_scale: 7.5,
+            smoothing: 0.08,
+            hue_speed: 0.05,
+            spin_rate_y: 0.4,
+            spin_rate_x: 0.25,
+            displacement: 0.35,
+            point_size: 0.03,
+            softness: 0.6,
+            size_reactivity: 1.5,
+            opacity: 0.8,
+            depth_fade: 0.5,
+            palette_cycle_speed: 0.0,
+            modulation: ModulationMatrix::default(),
+        }
+    }
+}
+
+impl VisualPreset {
+    // Slow and small, for ambient tracks
+    pub(crate) fn calm() -> Self {
+        VisualPreset {
+            scale_floor: 0.9,
+            smoothing: 0.03,
+            hue_speed: 0.02,
+            spin_rate_y: 0.15,
+            spin_rate_x: 0.1,
+            displacement: 0.2,
+            softness: 0.9,
+            size_reactivity: 0.75,
+            opacity: 0.6,
+            modulation: ModulationMatrix {
+                routes: vec![
+                    ModRoute {
+                        range: [0.0, 0.5],
+                        ..ModRoute::new(ModSource::Rms, ModTarget::Scale, 0.8)
+                    },
+                    ModRoute {
+                        range: [0.0, 0.5],
+                        ..ModRoute::new(ModSource::Rms, ModTarget::Saturation, 0.9)
+                    },
+                    ModRoute::new(ModSource::Lfo, ModTarget::Hue, 0.1),
+                ],
+                lfo_rate: 0.05,
+            },
+            ..Self::default()
+        }
+    }
+
+    // Fast attack and big swings, for anything with drums
+    pub(crate) fn punchy() -> Self {
+        VisualPreset {
+            saturation_floor: 0.3,
+            smoothing: 0.25,
+            hue_speed: 0.1,
+            spin_rate_y: 0.8,
+            spin_rate_x: 0.5,
+            displacement: 0.6,
+            point_size: 0.025,
+            softness: 0.3,
+            size_reactivity: 3.0,
+            opacity: 0.9,
+            modulation: ModulationMatrix {
+                routes: vec![
+                    ModRoute {
+                        range: [0.0, 0.2],
+                        ..ModRoute::new(ModSource::Rms, ModTarget::Scale, 3.5)
+                    },
+                    ModRoute {
+                        range: [0.0, 0.2],
+                        ..ModRoute::new(ModSource::Rms, ModTarget::Saturation, 0.7)
+                    },
+                    ModRoute {
+                        smoothing: 0.1,
+                        ..ModRoute::new(ModSource::Onset, ModTarget::PointSize, 0.6)
+                    },
+                    ModRoute {
+                        smoothing: 0.2,
+                        ..ModRoute::new(ModSource::Band(0), ModTarget::RotationSpeed, 1.0)
+                    },
+                ],
+                ..ModulationMatrix::default()
+            },
+            ..Self::default()
+        }
+    }
+
+    // Field by field blend, `t` = 0 gives self and 1 gives `other`
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        VisualPreset {
+            saturation_floor: mix(self.saturation_floor, other.saturation_floor),
+            idle_saturation: mix(self.idle_saturation, other.idle_saturation),
+            scale_floor: mix(self.scale_floor, other.scale_floor),
+            idle_scale: mix(self.idle_scale, other.idle_scale),
+            min_scale: mix(self.min_scale, other.min_scale),
+            max_scale: mix(self.max_scale, other.max_scale),
+            smoothing: mix(self.smoothing, other.smoothing),
+            hue_speed: mix(self.hue_speed, other.hue_speed),
+            spin_rate_y: mix(self.spin_rate_y, other.spin_rate_y),
+            spin_rate_x: mix(self.spin_rate_x, other.spin_rate_x),
+            displacement: mix(self.displacement, other.displacement),
+            point_size: mix(self.point_size, other.point_size),
+            softness: mix(self.softness, other.softness),
+            size_reactivity: mix(self.size_reactivity, other.size_reactivity),
+            opacity: mix(self.opacity, other.opacity),
+            depth_fade: mix(self.depth_fade, other.depth_fade),
+            palette_cycle_speed: mix(self.palette_cycle_speed, other.palette_cycle_speed),
+            modulation: self.modulation.lerp(&other.modulation, t),
+        }
+    }
+
+    // Sliders for the reaction constants, the look ones sit with the rest of the point settings
+    pub fn reaction_ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(egui::Slider::new(&mut self.smoothing, 0.01..=1.0).text("Smoothing"));
+        ui.add(egui::Slider::new(&mut self.saturation_floor, 0.0..=1.0).text("Saturation floor"));
+        ui.add(egui::Slider::new(&mut self.idle_saturation, 0.0..=1.0).text("Idle saturation"));
+        ui.add(egui::Slider::new(&mut self.scale_floor, 0.1..=3.0).text("Scale floor"));
+        ui.add(egui::Slider::new(&mut self.idle_scale, 0.1..=3.0).text("Idle scale"));
+        ui.horizontal(|ui| {
+            ui.label("Scale limits:");
+            ui.add(
+                egui::DragValue::new(&mut self.min_scale)
+                    .speed(0.01)
+                    .clamp_range(0.05..=self.max_scale),
+            );
+            ui.add(
+                egui::DragValue::new(&mut self.max_scale)
+                    .speed(0.05)
+                    .clamp_range(self.min_scale..=20.0),
+            );
+        });
+        ui.add(
+            egui::Slider::new(&mut self.hue_speed, 0.0..=0.5)
+                .suffix(" /s")
+                .text("Hue speed"),
+        );
+        ui.add(egui::Slider::new(&mut self.spin_rate_y, -2.0..=2.0).text("Spin Y"));
+        ui.add(egui::Slider::new(&mut self.spin_rate_x, -2.0..=2.0).text("Spin X"));
+    }
+}
+
+// Eases from one preset to another over a few seconds
+pub struct PresetTransition {
+    from: VisualPreset,
+    to: VisualPreset,
+    elapsed: f32,
+    duration: f32,
+}
+
+impl PresetTransition {
+    pub fn new(from: VisualPreset, to: VisualPreset, duration: f32) -> Self {
+        PresetTransition {
+            from,
+            to,
+            elapsed: 0.0,
+            duration,
+        }
+    }
+
+    // The blended preset after `dt` more seconds, and whether the transition is over
+    pub fn advance(&mut self, dt: f32) -> (VisualPreset, bool) {
+        self.elapsed += dt;
+        if self.elapsed >= self.duration {
+            return (self.to.clone(), true);
+        }
+        let t = self.elapsed / self.duration;
+        // Smoothstep, so the change neither starts nor stops abruptly
+        let eased = t * t * (3.0 - 2.0 * t);
+        (self.from.lerp(&self.to, eased), false)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresetFormat {
+    Json,
+    Toml,
+}
+
+impl PresetFormat {
+    pub const ALL: [PresetFormat; 2] = [PresetFormat::Json, PresetFormat::Toml];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PresetFormat::Json => "JSON",
+            PresetFormat::Toml => "TOML",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            PresetFormat::Json => "json",
+            PresetFormat::Toml => "toml",
+        }
+    }
+
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "json" => Some(PresetFormat::Json),
+            "toml" => Some(PresetFormat::Toml),
+            _ => None,
+        }
+    }
+}
+
+pub struct PresetEntry {
+    pub name: String,
+    pub preset: VisualPreset,
+    // None for the built-in ones, which can't be deleted
+    pub path: Option<PathBuf>,
+}
+
+// Built-in presets plus whatever is in the presets directory under the config dir
+pub struct PresetLibrary {
+    directory: Option<PathBuf>,
+    pub entries: Vec<PresetEntry>,
+    // Name of the last preset picked or saved
+    pub selected: Option<String>,
+    pub name_input: String,
+    pub format: PresetFormat,
+    pub transition_seconds: f32,
+    // Name typed for a save that would replace an existing file, waiting for a yes
+    overwrite_pending: Option<String>,
+    status: Option<String>,
+}
+
+impl PresetLibrary {
+    pub fn new() -> Self {
+        let mut library = PresetLibrary {
+            directory: config_dir().map(|dir| dir.join(PRESET_DIRECTORY_NAME)),
+            entries: Vec::new(),
+            selected: Some("Default".to_string()),
+            name_input: String::new(),
+            format: PresetFormat::Json,
+            transition_seconds: DEFAULT_TRANSITION_SECONDS,
+            overwrite_pending: None,
+            status: None,
+        };
+        library.rescan();
+        library
+    }
+
+    // Reloads the directory. Unreadable files are skipped with a warning.
+    pub fn rescan(&mut self) {
+        self.entries = built_in_presets()
+            .into_iter()
+            .map(|(name, preset)| PresetEntry {
+                name: name.to_string(),
+                preset,
+                path: None,
+            })
+            .collect();
+
+        let Some(directory) = &self.directory else {
+            return;
+        };
+        let Ok(read_dir) = fs::read_dir(directory) else {
+            return;
+        };
+        let mut files: Vec<PresetEntry> = read_dir
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter_map(|path| match load_preset(&path)? {
+                Ok(preset) => Some(PresetEntry {
+                    name: path.file_stem()?.to_string_lossy().into_owned(),
+                    preset,
+                    path: Some(path),
+                }),
+                Err(e) => {
+                    tracing::warn!("Skipping preset {}: {}", path.display(), e);
+                    None
+                }
+            })
+            .collect();
+        files.sort_by_key(|entry| entry.name.to_lowercase());
+        self.entries.extend(files);
+    }
+
+    // Saved file with this name, in either format
+    fn existing_file(&self, name: &str) -> Option<&Path> {
+        self.entries
+            .iter()
+            .find(|entry| entry.name.eq_ignore_ascii_case(name.trim()))
+            .and_then(|entry| entry.path.as_deref())
+    }
+
+    // Replaces an existing file of the same name, the browser asks before getting here
+    pub fn save(&mut self, name: &str, preset: &VisualPreset) -> Result<PathBuf, String> {
+        let name = name.trim();
+        if name.is_empty() || name.contains(['/', '\\']) {
+            return Err("Preset names can't be empty or contain slashes".to_string());
+        }
+        if let Some((built_in, _)) = built_in_presets()
+            .into_iter()
+            .find(|(built_in, _)| built_in.eq_ignore_ascii_case(name))
+        {
+            return Err(format!(
+                "'{}' is a built-in preset, pick another name",
+                built_in
+            ));
+        }
+        let Some(directory) = &self.directory else {
+            return Err("No config directory available for presets.".to_string());
+        };
+        fs::create_dir_all(directory)
+            .map_err(|e| format!("Failed to create '{}': {}", directory.display(), e))?;
+        let contents = match self.format {
+            PresetFormat::Json => serde_json::to_string_pretty(preset).map_err(|e| e.to_string()),
+            // Through JSON, which writes f32s as 0.3 rather than 0.30000001192092896
+            PresetFormat::Toml => serde_json::to_string(preset)
+                .and_then(|json| serde_json::from_str::<toml::Value>(&json))
+                .map_err(|e| e.to_string())
+                .and_then(|value| toml::to_string_pretty(&value).map_err(|e| e.to_string())),
+        }
+        .map_err(|e| format!("Failed to serialize preset: {}", e))?;
+        let path = directory.join(format!("{}.{}", name, self.format.extension()));
+        let temporary = path.with_extension(format!("{}.tmp", self.format.extension()));
+        fs::write(&temporary, contents)
+            .map_err(|e| format!("Failed to write '{}': {}", temporary.display(), e))?;
+        // The old file goes (it may be in the other format or differ in case, and would show
+        // up as a second entry) before the new one is moved in, not after: on case-insensitive
+        // file systems "mine.json" and "Mine.json" are the same file.
+        if let Some(old) = self.existing_file(name) {
+            if let Err(e) = fs::remove_file(old) {
+                tracing::warn!("Failed to remove old preset {}: {}", old.display(), e);
+            }
+        }
+        fs::rename(&temporary, &path)
+            .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+        self.rescan();
+        self.selected = Some(name.to_string());
+        Ok(path)
+    }
+
+    fn save_with_status(&mut self, name: &str, preset: &VisualPreset) {
+        self.status = Some(match self.save(name, preset) {
+            Ok(path) => format!("Saved {}", path.display()),
+            Err(e) => e,
+        });
+    }
+
+    // Preset browser. Returns the preset to switch to when one gets picked.
+    pub fn browser_ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        current: &VisualPreset,
+    ) -> Option<VisualPreset> {
+        let mut picked = None;
+        let mut delete = None;
+        egui::ScrollArea::vertical()
+            .id_source("preset_browser")
+            .max_height(150.0)
+            .show(ui, |ui| {
+                for (index, entry) in self.entries.iter().enumerate() {
+                    // Built-ins come first, a line keeps them apart from the saved ones
+                    if index > 0 && entry.path.is_some() && self.entries[index - 1].path.is_none() {
+                        ui.separator();
+                    }
+                    ui.horizontal(|ui| {
+                        let selected = self.selected.as_deref() == Some(entry.name.as_str());
+                        if ui.selectable_label(selected, &entry.name).clicked() {
+                            picked = Some(index);
+                        }
+                        if entry.path.is_some() && ui.small_button("Delete").clicked() {
+                            delete = Some(index);
+                        }
+                    });
+                }
+            });
+        ui.add(
+            egui::Slider::new(&mut self.transition_seconds, 0.0..=10.0)
+                .suffix(" s")
+                .text("Transition"),
+        );
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.name_input)
+                    .hint_text("Preset name")
+                    .desired_width(120.0),
+            );
+            egui::ComboBox::from_id_source("preset_format")
+                .selected_text(self.format.label())
+                .show_ui(ui, |ui| {
+                    for format in PresetFormat::ALL {
+                        ui.selectable_value(&mut self.format, format, format.label());
+                    }
+                });
+            if ui.button("Save").clicked() {
+                let name = self.name_input.trim().to_string();
+                if self.existing_file(&name).is_some() {
+                    self.overwrite_pending = Some(name);
+                } else {
+                    self.save_with_status(&name, current);
+                }
+            }
+            if ui.button("Rescan").clicked() {
+                self.rescan();
+            }
+        });
+        if let Some(name) = self.overwrite_pending.clone() {
+            ui.horizontal(|ui| {
+                ui.label(format!("'{}' already exists.", name));
+                if ui.button("Overwrite").clicked() {
+                    self.overwrite_pending = None;
+                    self.save_with_status(&name, current);
+                }
+                // Typing a different name drops the question as well
+                if ui.button("Cancel").clicked() || self.name_input.trim() != name {
+                    self.overwrite_pending = None;
+                }
+            });
+        }
+        if let Some(status) = &self.status {
+            ui.label(status);
+        }
+
+        if let Some(index) = delete {
+            if let Some(path) = self.entries[index].path.clone() {
+                self.status = match fs::remove_file(&path) {
+                    Ok(()) => Some(format!("Deleted {}", path.display())),
+                    Err(e) => Some(format!("Failed to delete '{}': {}", path.display(), e)),
+                };
+                self.rescan();
+            }
+            return None;
+        }
+        let entry = &self.entries[picked?];
+        self.selected = Some(entry.name.clone());
+        self.name_input = entry.name.clone();
+        Some(entry.preset.clone())
+    }
+}
+
+// Listed first in the browser, saving under these names is refused
+fn built_in_presets() -> [(&'static str, VisualPreset); 3] {
+    [
+        ("Default", VisualPreset::default()),
+        ("Calm", VisualPreset::calm()),
+        ("Punchy", VisualPreset::punchy()),
+    ]
+}
+
+// Reaction fields presets had before the modulation matrix took over. Read from the same
+// file next to the preset itself, a file with any of these and no matrix gets the RMS
+// routes that do the same job.
+#[derive(Deserialize)]
+struct LegacyReaction {
+    amplitude_gain: Option<f32>,
+    saturation_range: Option<f32>,
+    scale_range: Option<f32>,
+    modulation: Option<serde::de::IgnoredAny>,
+}
+
+impl LegacyReaction {
+    fn migrate(self, preset: &mut VisualPreset) {
+        let legacy = self.amplitude_gain.is_some()
+            || self.saturation_range.is_some()
+            || self.scale_range.is_some();
+        if !legacy || self.modulation.is_some() {
+            return;
+        }
+        // Amplitude used to be RMS * gain clamped to 1, so full level was reached at 1 / gain
+        let gain = self.amplitude_gain.unwrap_or(LEGACY_AMPLITUDE_GAIN);
+        let range = [0.0, 1.0 / gain.max(f32::EPSILON)];
+        let scale = self.scale_range.unwrap_or(LEGACY_SCALE_RANGE);
+        let saturation = self.saturation_range.unwrap_or(LEGACY_SATURATION_RANGE);
+        preset.modulation = ModulationMatrix {
+            routes: vec![
+                ModRoute {
+                    range,
+                    ..ModRoute::new(ModSource::Rms, ModTarget::Scale, scale)
+                },
+                ModRoute {
+                    range,
+                    ..ModRoute::new(ModSource::Rms, ModTarget::Saturation, saturation)
+                },
+            ],
+            ..ModulationMatrix::default()
+        };
+    }
+}
+
+// None for files that aren't presets at all (by extension)
+fn load_preset(path: &Path) -> Option<Result<VisualPreset, String>> {
+    let format = PresetFormat::from_path(path)?;
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => return Some(Err(e.to_string())),
+    };
+    Some(parse_preset(format, &contents))
+}
+
+fn parse_preset(format: PresetFormat, contents: &str) -> Result<VisualPreset, String> {
+    let (mut preset, legacy): (VisualPreset, LegacyReaction) = match format {
+        PresetFormat::Json => serde_json::from_str(contents)
+            .and_then(|preset| Ok((preset, serde_json::from_str(contents)?)))
+            .map_err(|e| e.to_string()),
+        PresetFormat::Toml => toml::from_str(contents)
+            .and_then(|preset| Ok((preset, toml::from_str(contents)?)))
+            .map_err(|e| e.to_string()),
+    }?;
+    legacy.migrate(&mut preset);
+    Ok(preset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library_in(test_name: &str) -> PresetLibrary {
+        let directory = std::env::temp_dir().join(format!(
+            "audio_visualizer_{}_{}",
+            test_name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&directory);
+        let mut library = PresetLibrary::new();
+        library.directory = Some(directory);
+        library.rescan();
+        library
+    }
+
+    #[test]
+    fn old_reaction_fields_become_routes() {
+        let old = r#"{ "amplitude_gain": 2.0, "scale_range": 4.0, "smoothing": 0.2 }"#;
+        let preset = parse_preset(PresetFormat::Json, old).unwrap();
+        assert_eq!(preset.smoothing, 0.2);
+        let routes = &preset.modulation.routes;
+        assert_eq!(routes.len(), 2);
+        assert_eq!(
+            (routes[0].target, routes[0].amount),
+            (ModTarget::Scale, 4.0)
+        );
+        assert_eq!(
+            (routes[1].target, routes[1].amount),
+            (ModTarget::Saturation, LEGACY_SATURATION_RANGE)
+        );
+        assert!(routes
+            .iter()
+            .all(|route| route.source == ModSource::Rms && route.range == [0.0, 0.5]));
+
+        // Files written since the matrix keep theirs, leftover fields or not
+        let punchy = VisualPreset::punchy();
+        let mut value = serde_json::to_value(&punchy).unwrap();
+        value["saturation_range"] = 0.5.into();
+        let current = toml::to_string(&value).unwrap();
+        assert_eq!(parse_preset(PresetFormat::Toml, &current).unwrap(), punchy);
+    }
+
+    #[test]
+    fn save_refuses_built_in_names() {
+        let mut library = library_in("built_in_names");
+        for name in ["Default", "calm", " PUNCHY "] {
+            assert!(library.save(name, &VisualPreset::default()).is_err());
+        }
+        assert_eq!(library.entries.len(), built_in_presets().len());
+    }
+
+    #[test]
+    fn saving_in_another_format_replaces_the_old_file() {
+        let mut library = library_in("format_switch");
+        let json = library.save("Mine", &VisualPreset::default()).unwrap();
+        library.format = PresetFormat::Toml;
+        let toml = library.save("mine", &VisualPreset::calm()).unwrap();
+        assert!(!json.exists() && toml.exists());
+        let saved: Vec<_> = library
+            .entries
+            .iter()
+            .filter(|e| e.path.is_some())
+            .collect();
+        assert_eq!(saved.len(), 1);
+        fs::remove_dir_all(toml.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn saving_under_a_different_case_keeps_one_file() {
+        let mut library = library_in("case_change");
+        library.save("Mine", &VisualPreset::default()).unwrap();
+        let path = library.save("mine", &VisualPreset::calm()).unwrap();
+        let saved: Vec<_> = library
+            .entries
+            .iter()
+            .filter(|e| e.path.is_some())
+            .collect();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].preset, VisualPreset::calm());
+        let files = fs::read_dir(path.parent().unwrap()).unwrap().count();
+        assert_eq!(files, 1);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
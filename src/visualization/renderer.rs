@@ -1,90 +1,887 @@
 use crate::audio::{AudioAnalysisData, PlaybackState};
+use crate::visualization::camera::OrbitCamera;
+use crate::visualization::cpu_raster::{CpuCanvas, Splat};
+use crate::visualization::geometry::{GeometryPoint, Shape};
+use crate::visualization::modulation::{ModTarget, ModulationOutput, Modulator};
+use crate::visualization::palette::{
+    NamedPalette, PaletteChoice, PaletteSettings, GRADIENT_RESOLUTION,
+};
+use crate::visualization::preset::{PresetLibrary, PresetTransition, VisualPreset};
+use crate::visualization::shader_source::{
+    capture_validation, shader_path, with_fullscreen_vertex, WatchedShader,
+};
+use crate::visualization::spectrum::SpectrumBands;
+use crate::visualization::visualizer::{Visualizer, VisualizerFrame, VisualizerInput};
 use anyhow::Result;
 use bytemuck::{Pod, Zeroable};
+use eframe::egui;
 use glam::{Mat4, Vec3A};
-use std::sync::Arc;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, OnceLock};
 use wgpu::util::DeviceExt;
 
+const DEFAULT_POINT_COUNT: usize = 2000;
+pub const MIN_POINT_COUNT: usize = 100;
+pub const MAX_POINT_COUNT: usize = 1_000_000;
+const DEFAULT_RADIUS: f32 = 1.0;
+// Number of log spaced bands uploaded to the vertex shader
+pub const SPECTRUM_BANDS: usize = 64;
+const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+// With depth writes on, sprite edges fainter than this get dropped. Otherwise their
+// (invisible) corners would hide the points behind them.
+const DEPTH_ALPHA_CUTOFF: f32 = 0.3;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct CameraUniform {
+    model_view: Mat4,
+    projection: Mat4,
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Pod, Zeroable)]
 struct VisualParamsUniform {
     color: [f32; 4],
+    point_size: f32,
+    softness: f32,
+    size_reactivity: f32,
+    opacity: f32,
+    depth_fade: f32,
+    // View distances where depth fading starts and ends
+    fade_near: f32,
+    fade_far: f32,
+    alpha_cutoff: f32,
+    color_source: u32,
+    // Added to the gradient coordinate (wrapping), animates the palette
+    gradient_shift: f32,
+    _padding: [f32; 2],
 }
 
-// Shader source (WGSL)
-const SHADERS_WGSL: &str = r#"
-// === UNIFORMS ===
-@group(0) @binding(0)
-var<uniform> mvp: mat4x4<f32>;
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct SpectrumParamsUniform {
+    mapping: u32,
+    num_bands: u32,
+    displacement: f32,
+    _padding: f32,
+}
 
-// Group 1: Visual parameters (Color only)
-struct VisualParams { // Define struct as VisualParams
-    color: vec4<f32>,
-};
-@group(1) @binding(0)
-var<uniform> visual_params: VisualParams; // Use the defined struct name VisualParams
+// How a point picks its frequency band from its uv. Bass always sits at 0.
+// Named after the sphere, other shapes use the same uv directions (see `GeometryPoint`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpectrumMapping {
+    // North pole (v = 0) bass, south pole treble
+    Latitude,
+    // Bass at both poles, treble around the equator
+    MirroredLatitude,
+    // Bass on one side (u = 0), treble on the other, mirrored so there's no seam
+    Longitude,
+}
 
-// === VERTEX SHADER ===
-struct VertexInput {
-    @location(0) position: vec3<f32>,
-};
+impl SpectrumMapping {
+    pub const ALL: [SpectrumMapping; 3] = [
+        SpectrumMapping::Latitude,
+        SpectrumMapping::MirroredLatitude,
+        SpectrumMapping::Longitude,
+    ];
 
-struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
-};
+    pub fn label(&self) -> &'static str {
+        match self {
+            SpectrumMapping::Latitude => "Latitude",
+            SpectrumMapping::MirroredLatitude => "Mirrored Latitude",
+            SpectrumMapping::Longitude => "Longitude",
+        }
+    }
+
+    // Same as spectrum_coordinate in the shader
+    fn coordinate(&self, uv: [f32; 2]) -> f32 {
+        match self {
+            SpectrumMapping::Latitude => uv[1],
+            SpectrumMapping::MirroredLatitude => 1.0 - (uv[1] * 2.0 - 1.0).abs(),
+            SpectrumMapping::Longitude => 1.0 - (uv[0] * 2.0 - 1.0).abs(),
+        }
+    }
+}
+
+// How overlapping sprites combine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpriteBlend {
+    // Overlaps add up and glow, dense areas burn out towards white
+    Additive,
+    // Regular "over" blending, dense areas stay the point colour
+    Alpha,
+}
 
-@vertex
-fn vs_main(model: VertexInput) -> VertexOutput {
-    var out: VertexOutput;
-    out.clip_position = mvp * vec4<f32>(model.position, 1.0);
-    return out;
+impl SpriteBlend {
+    pub const ALL: [SpriteBlend; 2] = [SpriteBlend::Additive, SpriteBlend::Alpha];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SpriteBlend::Additive => "Additive",
+            SpriteBlend::Alpha => "Alpha",
+        }
+    }
 }
 
-// === FRAGMENT SHADER ===
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MsaaLevel {
+    Off,
+    X4,
+    X8,
+}
+
+impl MsaaLevel {
+    pub const ALL: [MsaaLevel; 3] = [MsaaLevel::Off, MsaaLevel::X4, MsaaLevel::X8];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            MsaaLevel::Off => "Off",
+            MsaaLevel::X4 => "4x",
+            MsaaLevel::X8 => "8x",
+        }
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        match self {
+            MsaaLevel::Off => 1,
+            MsaaLevel::X4 => 4,
+            MsaaLevel::X8 => 8,
+        }
+    }
+}
+
+// What picks a point's spot on the palette gradient
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSource {
+    // One colour for everything, the hue slowly cycles over time
+    HueCycle,
+    // The point's place on the spectrum, bass at the start of the gradient
+    Frequency,
+    // How far the point is pushed out right now
+    Displacement,
+    // Front of the shape at the start, back at the end
+    Depth,
+}
+
+impl ColorSource {
+    pub const ALL: [ColorSource; 4] = [
+        ColorSource::HueCycle,
+        ColorSource::Frequency,
+        ColorSource::Displacement,
+        ColorSource::Depth,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ColorSource::HueCycle => "Hue Cycle",
+            ColorSource::Frequency => "Frequency",
+            ColorSource::Displacement => "Displacement",
+            ColorSource::Depth => "Depth",
+        }
+    }
+}
+
+// Per frame spectrum state handed to `SphereFrame`
+#[derive(Debug, Clone)]
+pub struct SpectrumFrame {
+    pub values: Vec<f32>,
+    pub mapping: SpectrumMapping,
+    pub displacement: f32,
+}
+
+// Built-in point shader. With the live shader setting on, the same file is also
+// loaded from disk and picked up again whenever it's saved.
+const SPHERE_SHADER_PATH: &str = "shaders/sphere.wgsl";
+const SHADERS_WGSL: &str = include_str!("../../shaders/sphere.wgsl");
+
+// Draws the offscreen image over the callback rect
+const COMPOSITE_WGSL: &str = r#"
+@group(0) @binding(0)
+var frame_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var frame_sampler: sampler;
+
 @fragment
-fn fs_main() -> @location(0) vec4<f32> {
-    return visual_params.color; // Access color field of the VisualParams uniform
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(frame_texture, frame_sampler, in.uv);
 }
 "#;
 
+// Point positions live apart from the pipeline, so the geometry can change without rebuilding it
+pub struct SphereVertexBuffer {
+    buffer: wgpu::Buffer,
+    // Points that fit, smaller point clouds reuse the buffer
+    capacity: usize,
+    // Generation of the points written into it, set once a frame has actually uploaded them
+    uploaded: AtomicU64,
+}
+
+impl SphereVertexBuffer {
+    fn new(device: &wgpu::Device, points: &[GeometryPoint], generation: u64) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sphere Vertex Buffer"),
+            contents: bytemuck::cast_slice(points),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        SphereVertexBuffer {
+            buffer,
+            capacity: points.len(),
+            uploaded: AtomicU64::new(generation),
+        }
+    }
+}
+
+// What a point pipeline gets specialised on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct PipelineKey {
+    blend: SpriteBlend,
+    sample_count: u32,
+    depth_test: bool,
+}
+
+// Offscreen render target for one rect size / sample count
+struct SphereTarget {
+    size: [u32; 2],
+    sample_count: u32,
+    // Multisampled colour, None without MSAA (the points go straight into `resolve_view`)
+    msaa_view: Option<wgpu::TextureView>,
+    depth_view: wgpu::TextureView,
+    resolve_view: wgpu::TextureView,
+    composite_bind_group: wgpu::BindGroup,
+}
+
 pub struct SphereWgpuPrimitive {
-    vertex_buffer: wgpu::Buffer,
-    num_vertices: u32,
-    mvp_uniform_buffer: wgpu::Buffer,
-    mvp_bind_group: wgpu::BindGroup,
+    camera_uniform_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
     visual_params_uniform_buffer: wgpu::Buffer,
+    gradient_buffer: wgpu::Buffer,
     visual_params_bind_group: wgpu::BindGroup,
-    render_pipeline: wgpu::RenderPipeline,
+    spectrum_buffer: wgpu::Buffer,
+    spectrum_params_uniform_buffer: wgpu::Buffer,
+    spectrum_bind_group: wgpu::BindGroup,
+    // Swapped out when a live shader reloads
+    shader_module: Mutex<wgpu::ShaderModule>,
+    pipeline_layout: wgpu::PipelineLayout,
+    target_format: wgpu::TextureFormat,
+    // Point pipelines, built the first time a blend / MSAA / depth combination is used
+    pipelines: Mutex<HashMap<PipelineKey, Arc<wgpu::RenderPipeline>>>,
+    composite_bind_group_layout: wgpu::BindGroupLayout,
+    composite_sampler: wgpu::Sampler,
+    // Draws the resolved offscreen image into egui's render pass
+    composite_pipeline: wgpu::RenderPipeline,
+    // Recreated whenever the rect size or the sample count changes
+    target: Mutex<Option<Arc<SphereTarget>>>,
 }
 
+impl SphereWgpuPrimitive {
+    fn pipeline(&self, device: &wgpu::Device, key: PipelineKey) -> Arc<wgpu::RenderPipeline> {
+        self.pipelines
+            .lock()
+            .entry(key)
+            .or_insert_with(|| {
+                Arc::new(self.create_pipeline(device, &self.shader_module.lock(), key))
+            })
+            .clone()
+    }
+
+    // Compiles `source` and builds a pipeline with it to check it fits the layout. Only
+    // when both work does it replace the current shader, the cached pipelines go with it.
+    fn reload_shader(
+        &self,
+        device: &wgpu::Device,
+        source: &str,
+        key: PipelineKey,
+    ) -> Result<(), String> {
+        let (shader_module, pipeline) = capture_validation(device, || {
+            let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Sphere Shader"),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+            let pipeline = self.create_pipeline(device, &shader_module, key);
+            (shader_module, pipeline)
+        })?;
+        *self.shader_module.lock() = shader_module;
+        let mut pipelines = self.pipelines.lock();
+        pipelines.clear();
+        pipelines.insert(key, Arc::new(pipeline));
+        Ok(())
+    }
+
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        shader_module: &wgpu::ShaderModule,
+        key: PipelineKey,
+    ) -> wgpu::RenderPipeline {
+        let blend = match key.blend {
+            // Overlapping sprites add up like light
+            SpriteBlend::Additive => wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent::OVER,
+            },
+            SpriteBlend::Alpha => wgpu::BlendState::ALPHA_BLENDING,
+        };
+        // The target always has a depth attachment, without depth testing it's just ignored
+        let depth_stencil = wgpu::DepthStencilState {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: key.depth_test,
+            depth_compare: if key.depth_test {
+                wgpu::CompareFunction::Less
+            } else {
+                wgpu::CompareFunction::Always
+            },
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        };
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Sphere Render Pipeline"),
+            layout: Some(&self.pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: shader_module,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<GeometryPoint>() as wgpu::BufferAddress,
+                    // Every point is an instance of the 6 vertex sprite quad
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &wgpu::vertex_attr_array![
+                        0 => Float32x3,
+                        1 => Float32x3,
+                        2 => Float32x2
+                    ],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader_module,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: self.target_format,
+                    blend: Some(blend),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(depth_stencil),
+            multisample: wgpu::MultisampleState {
+                count: key.sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        })
+    }
+
+    // Current offscreen target, recreated when the rect or sample count changed
+    fn target(
+        &self,
+        device: &wgpu::Device,
+        size: [u32; 2],
+        sample_count: u32,
+    ) -> Arc<SphereTarget> {
+        let mut target = self.target.lock();
+        match &*target {
+            Some(existing) if existing.size == size && existing.sample_count == sample_count => {
+                existing.clone()
+            }
+            _ => {
+                let created = Arc::new(self.create_target(device, size, sample_count));
+                *target = Some(created.clone());
+                created
+            }
+        }
+    }
+
+    fn create_target(
+        &self,
+        device: &wgpu::Device,
+        size: [u32; 2],
+        sample_count: u32,
+    ) -> SphereTarget {
+        let extent = wgpu::Extent3d {
+            width: size[0],
+            height: size[1],
+            depth_or_array_layers: 1,
+        };
+        let create_view = |label: &str, format, sample_count, usage| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: extent,
+                    mip_level_count: 1,
+                    sample_count,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+
+        let resolve_view = create_view(
+            "Sphere Resolve Texture",
+            self.target_format,
+            1,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        );
+        let msaa_view = (sample_count > 1).then(|| {
+            create_view(
+                "Sphere MSAA Texture",
+                self.target_format,
+                sample_count,
+                wgpu::TextureUsages::RENDER_ATTACHMENT,
+            )
+        });
+        let depth_view = create_view(
+            "Sphere Depth Texture",
+            DEPTH_FORMAT,
+            sample_count,
+            wgpu::TextureUsages::RENDER_ATTACHMENT,
+        );
+        let composite_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Sphere Composite Bind Group"),
+            layout: &self.composite_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&resolve_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.composite_sampler),
+                },
+            ],
+        });
+
+        SphereTarget {
+            size,
+            sample_count,
+            msaa_view,
+            depth_view,
+            resolve_view,
+            composite_bind_group,
+        }
+    }
+}
+
+// Shape, point count and radius a point cloud is generated from
+type GeometryKey = (Shape, usize, f32);
+
 pub struct WgpuSphereRenderer {
     primitive: Option<Arc<SphereWgpuPrimitive>>,
-    points: Vec<[f32; 3]>,
-    camera_position: Vec3A,
+    device: Option<Arc<wgpu::Device>>,
+    points: Arc<Vec<GeometryPoint>>,
+    // Bumped whenever `points` is regenerated, frames upload them while `vertices` is behind
+    points_generation: u64,
+    vertices: Option<Arc<SphereVertexBuffer>>,
+    // Points being generated off the UI thread, with the shape, count and radius asked for
+    geometry_job: Option<(GeometryKey, mpsc::Receiver<Vec<GeometryPoint>>)>,
+    pub shape: Shape,
+    pub point_count: usize,
+    pub radius: f32,
+    // Shape, point count and radius `points` was generated with
+    built_for: GeometryKey,
+    pub camera: OrbitCamera,
+    // Model rotation angles around Y and X, only advance while auto-rotate is on.
+    // Accumulated rather than rate * time, so rate changes don't make it jump.
+    spin: [f32; 2],
     pub time: f32,
     current_scale: f32,
     current_hue: f32,
     current_saturation: f32,
     current_value: f32,
     pub current_color_rgb: [f32; 3],
+    spectrum: SpectrumBands,
+    pub spectrum_mapping: SpectrumMapping,
+    // Reaction constants and point look, what the preset browser saves and loads
+    pub preset: VisualPreset,
+    // Set while easing into a newly picked preset
+    transition: Option<PresetTransition>,
+    pub presets: PresetLibrary,
+    // Runs the preset's modulation routes, `modulation` is what they add up to this frame
+    modulator: Modulator,
+    modulation: ModulationOutput,
+    pub blend: SpriteBlend,
+    pub msaa: MsaaLevel,
+    // Sample count actually in use, lower than requested when the device can't do it
+    effective_sample_count: u32,
+    adapter: Option<Arc<wgpu::Adapter>>,
+    pub depth_test: bool,
+    pub color_source: ColorSource,
+    pub palette: PaletteSettings,
+    // Use SPHERE_SHADER_PATH from disk instead of the built-in copy, reloaded on save
+    pub live_shader: bool,
+    shader_file: WatchedShader,
+    // Whether the primitive currently runs the shader from disk
+    shader_is_live: bool,
+    // Why the file on disk isn't what's running, shown over the view
+    shader_error: Option<String>,
 }
 
 impl WgpuSphereRenderer {
-    pub fn new(points_data: Vec<[f32; 3]>) -> Self {
+    pub fn new(shape: Shape, point_count: usize, radius: f32) -> Self {
         Self {
             primitive: None,
-            points: points_data,
-            camera_position: Vec3A::new(0.0, 0.0, 4.0),
+            device: None,
+            points: Arc::new(shape.generate(radius, point_count)),
+            points_generation: 0,
+            vertices: None,
+            geometry_job: None,
+            shape,
+            point_count,
+            radius,
+            built_for: (shape, point_count, radius),
+            camera: OrbitCamera::new(),
+            spin: [0.0; 2],
             time: 0.0,
             current_scale: 1.15,
             current_hue: 0.0,
             current_saturation: 0.25,
             current_value: 1.0,
             current_color_rgb: hsv_to_rgb(0.0, 0.5, 1.0),
+            spectrum: SpectrumBands::new(SPECTRUM_BANDS),
+            spectrum_mapping: SpectrumMapping::Latitude,
+            preset: VisualPreset::default(),
+            transition: None,
+            presets: PresetLibrary::new(),
+            modulator: Modulator::new(),
+            modulation: ModulationOutput::default(),
+            blend: SpriteBlend::Additive,
+            msaa: MsaaLevel::X4,
+            effective_sample_count: 1,
+            adapter: None,
+            depth_test: false,
+            color_source: ColorSource::HueCycle,
+            palette: PaletteSettings::new(PaletteChoice::Named(NamedPalette::Sunset)),
+            live_shader: false,
+            shader_file: WatchedShader::new(shader_path(SPHERE_SHADER_PATH)),
+            shader_is_live: false,
+            shader_error: None,
         }
     }
 
-    pub fn prepare(
+    /// Update visual state (color, scale) based on audio playback state and analysis data
+    pub fn update_visual_state(
+        &mut self,
+        playback_state: PlaybackState,
+        audio_data: Option<&AudioAnalysisData>,
+    ) {
+        let preset = &self.preset;
+        let modulation = &self.modulation;
+        // Hue cycles based on time, routes can push it around on top
+        self.current_hue =
+            (self.time * preset.hue_speed + modulation.get(ModTarget::Hue)).rem_euclid(1.0);
+
+        // Spectrum falls back to a plain sphere whenever nothing is playing
+        self.spectrum
+            .update(audio_data.filter(|_| playback_state == PlaybackState::Playing));
+
+        let target_saturation;
+        let target_scale;
+
+        if playback_state == PlaybackState::Playing {
+            if audio_data.is_some() {
+                target_saturation = (preset.saturation_floor
+                    + modulation.get(ModTarget::Saturation))
+                .clamp(0.0, 1.0);
+                target_scale = preset.scale_floor + modulation.get(ModTarget::Scale);
+            } else {
+                target_saturation = preset.idle_saturation;
+                target_scale = preset.scale_floor;
+            }
+        } else {
+            target_saturation = preset.idle_saturation;
+            target_scale = preset.idle_scale;
+        }
+
+        // Smooth towards target values
+        let lerp_factor = preset.smoothing;
+        self.current_saturation += (target_saturation - self.current_saturation) * lerp_factor;
+        self.current_scale += (target_scale - self.current_scale) * lerp_factor;
+
+        self.current_scale = self
+            .current_scale
+            .clamp(preset.min_scale, preset.max_scale.max(preset.min_scale));
+
+        // Convert final HSV to RGB
+        self.current_color_rgb = hsv_to_rgb(
+            self.current_hue,
+            self.current_saturation,
+            self.current_value,
+        );
+    }
+
+    // Preset displacement plus whatever routes add to it
+    fn displacement(&self) -> f32 {
+        (self.preset.displacement + self.modulation.get(ModTarget::Displacement)).max(0.0)
+    }
+
+    fn point_size(&self) -> f32 {
+        self.preset.point_size * (1.0 + self.modulation.get(ModTarget::PointSize)).max(0.0)
+    }
+
+    // Eases into `preset` over `duration` seconds, 0 switches straight away
+    pub fn switch_preset(&mut self, preset: VisualPreset, duration: f32) {
+        if duration <= 0.0 {
+            self.preset = preset;
+            self.transition = None;
+        } else {
+            self.transition = Some(PresetTransition::new(self.preset.clone(), preset, duration));
+        }
+    }
+
+    // Calculate the camera matrices, re-applying the overall scale
+    fn calculate_camera(&self, aspect_ratio: f32) -> CameraUniform {
+        let view = self.camera.pose.view_matrix();
+        // Apply rotation AND scale from self.current_scale
+        let model = Mat4::from_rotation_y(self.spin[0])
+            * Mat4::from_rotation_x(self.spin[1])
+            * Mat4::from_scale(Vec3A::splat(self.current_scale).into());
+
+        // wgpu clip space depth is 0..1, not GL's -1..1
+        let proj = Mat4::perspective_rh(std::f32::consts::FRAC_PI_4, aspect_ratio, 0.1, 100.0);
+        CameraUniform {
+            model_view: view * model,
+            projection: proj,
+        }
+    }
+
+    fn pipeline_key(&self) -> PipelineKey {
+        PipelineKey {
+            blend: self.blend,
+            sample_count: self.effective_sample_count,
+            depth_test: self.depth_test,
+        }
+    }
+
+    // Follows the live shader file while that's on, back to the built-in shader after
+    fn update_shader(&mut self, dt: f32) {
+        let (Some(primitive), Some(device)) = (&self.primitive, &self.device) else {
+            return;
+        };
+        let source = match (self.live_shader, self.shader_is_live) {
+            (true, false) => {
+                self.shader_file.reload();
+                self.shader_file.poll(dt)
+            }
+            (true, true) => self.shader_file.poll(dt),
+            (false, true) => Some(Ok(SHADERS_WGSL.to_string())),
+            (false, false) => None,
+        };
+        self.shader_is_live = self.live_shader;
+        let path = self.shader_file.path().display();
+        let result = match source {
+            Some(Ok(source)) => primitive.reload_shader(device, &source, self.pipeline_key()),
+            // No file to follow, so the built-in shader it is until one shows up
+            Some(Err(e)) => primitive
+                .reload_shader(device, SHADERS_WGSL, self.pipeline_key())
+                .and(Err(format!("{}, using the built-in shader", e))),
+            None => return,
+        };
+        match result {
+            Ok(()) if self.live_shader => {
+                tracing::info!("Loaded point shader from {}", path);
+                self.shader_error = None;
+            }
+            Ok(()) => self.shader_error = None,
+            Err(e) => {
+                tracing::warn!("Point shader from {} failed to load", path);
+                self.shader_error = Some(e);
+            }
+        }
+    }
+
+    fn visual_params(&self) -> VisualParamsUniform {
+        // Depth fading spans the shape from front to back
+        let camera_distance = self.camera.pose.eye().length();
+        let extent = (self.radius + self.displacement()) * self.current_scale;
+        VisualParamsUniform {
+            color: [
+                self.current_color_rgb[0],
+                self.current_color_rgb[1],
+                self.current_color_rgb[2],
+                1.0,
+            ],
+            point_size: self.point_size(),
+            softness: self.preset.softness,
+            size_reactivity: self.preset.size_reactivity,
+            opacity: self.preset.opacity,
+            depth_fade: self.preset.depth_fade,
+            fade_near: (camera_distance - extent).max(0.0),
+            fade_far: camera_distance + extent,
+            alpha_cutoff: if self.depth_test {
+                DEPTH_ALPHA_CUTOFF
+            } else {
+                0.0
+            },
+            color_source: self.color_source as u32,
+            gradient_shift: (self.time * self.preset.palette_cycle_speed).fract(),
+            _padding: [0.0; 2],
+        }
+    }
+
+    pub fn spectrum_frame(&self) -> SpectrumFrame {
+        SpectrumFrame {
+            values: self.spectrum.values().to_vec(),
+            mapping: self.spectrum_mapping,
+            displacement: self.displacement(),
+        }
+    }
+
+    fn spectrum_params(&self) -> SpectrumParamsUniform {
+        spectrum_params_uniform(self.spectrum_mapping, self.displacement())
+    }
+
+    // Draws the current state into `canvas` without touching the GPU. Follows the built-in
+    // shader point for point (minus MSAA), so frames match what `paint` would show.
+    pub fn render_cpu(&self, canvas: &mut CpuCanvas) {
+        let [width, height] = canvas.size();
+        let camera = self.calculate_camera(width as f32 / height as f32);
+        let params = self.visual_params();
+        let gradient = self.palette.gradient();
+        let gradient_color = |t: f32| {
+            // Only wrap when the palette is cycling, otherwise the ends would meet at 1
+            if params.gradient_shift != 0.0 {
+                gradient.sample((t + params.gradient_shift).fract())
+            } else {
+                gradient.sample(t.clamp(0.0, 1.0))
+            }
+        };
+        let bands = self.spectrum.values();
+        let spectrum_energy = |coordinate: f32| {
+            let last = bands.len().saturating_sub(1);
+            let x = coordinate * last as f32;
+            let i = (x.floor().max(0.0) as usize).min(last);
+            let j = (i + 1).min(last);
+            let (a, b) = (bands.get(i), bands.get(j));
+            a.zip(b).map_or(0.0, |(a, b)| a + (b - a) * x.fract())
+        };
+
+        let edge = params.softness.max(0.01);
+        let coverage = |local: [f32; 2]| {
+            let distance = (local[0] * local[0] + local[1] * local[1]).sqrt();
+            if distance > 1.0 {
+                return None;
+            }
+            let falloff = 1.0 - smoothstep(1.0 - edge, 1.0, distance);
+            (falloff >= params.alpha_cutoff).then_some(falloff)
+        };
+
+        canvas.clear();
+        let depth_range = (params.fade_far - params.fade_near).max(0.001);
+        for point in self.points.iter() {
+            let coordinate = self.spectrum_mapping.coordinate(point.uv);
+            let energy = spectrum_energy(coordinate);
+            let position = glam::Vec3::from(point.position)
+                + glam::Vec3::from(point.normal) * energy * self.displacement();
+            let size = params.point_size * (1.0 + energy * params.size_reactivity);
+
+            let view_position = camera.model_view * position.extend(1.0);
+            let clip = camera.projection * view_position;
+            // The GPU clips against the near and far planes, whole sprites go here
+            if clip.w <= 0.0 || clip.z < 0.0 || clip.z > clip.w {
+                continue;
+            }
+            let fade_depth = ((-view_position.z - params.fade_near) / depth_range).clamp(0.0, 1.0);
+            // The quad sits in a plane facing the camera, so it projects to a rectangle
+            let half_extent = size * 0.5 / clip.w;
+            let splat = Splat {
+                center: [
+                    (clip.x / clip.w + 1.0) * 0.5 * width as f32,
+                    (1.0 - clip.y / clip.w) * 0.5 * height as f32,
+                ],
+                half_size: [
+                    camera.projection.x_axis.x * half_extent * 0.5 * width as f32,
+                    camera.projection.y_axis.y * half_extent * 0.5 * height as f32,
+                ],
+                depth: clip.z / clip.w,
+                color: match self.color_source {
+                    ColorSource::HueCycle => self.current_color_rgb,
+                    ColorSource::Frequency => gradient_color(coordinate),
+                    ColorSource::Displacement => gradient_color(energy),
+                    ColorSource::Depth => gradient_color(fade_depth),
+                },
+                // Points towards the back of the shape fade out
+                alpha: params.opacity * (1.0 - params.depth_fade * fade_depth),
+            };
+            canvas.splat(&splat, self.blend, self.depth_test, coverage);
+        }
+    }
+
+    // Regenerates the points after a shape / count / radius change. A million points take a
+    // while, so they're generated on a worker and only one job runs at a time, which also
+    // means dragging a slider doesn't queue up a generation per step. The vertex buffer is
+    // only reallocated when it's too small, otherwise the next frame writes into it.
+    fn rebuild_geometry(&mut self) {
+        if let Some((built_for, receiver)) = &self.geometry_job {
+            match receiver.try_recv() {
+                Ok(points) => {
+                    let built_for = *built_for;
+                    self.geometry_job = None;
+                    self.set_points(points, built_for);
+                }
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => self.geometry_job = None,
+            }
+        }
+
+        self.point_count = self.point_count.clamp(MIN_POINT_COUNT, MAX_POINT_COUNT);
+        let wanted = (self.shape, self.point_count, self.radius);
+        if self.built_for == wanted {
+            return;
+        }
+        let (sender, receiver) = mpsc::channel();
+        let (shape, point_count, radius) = wanted;
+        std::thread::spawn(move || {
+            let _ = sender.send(shape.generate(radius, point_count));
+        });
+        self.geometry_job = Some((wanted, receiver));
+    }
+
+    fn set_points(&mut self, points: Vec<GeometryPoint>, built_for: GeometryKey) {
+        self.points = Arc::new(points);
+        self.points_generation += 1;
+        self.built_for = built_for;
+
+        let fits = self
+            .vertices
+            .as_ref()
+            .is_some_and(|v| v.capacity >= self.points.len());
+        if let (Some(device), false) = (&self.device, fits) {
+            self.vertices = Some(Arc::new(SphereVertexBuffer::new(
+                device,
+                &self.points,
+                self.points_generation,
+            )));
+        }
+    }
+}
+
+impl Default for WgpuSphereRenderer {
+    fn default() -> Self {
+        Self::new(Shape::Sphere, DEFAULT_POINT_COUNT, DEFAULT_RADIUS)
+    }
+}
+
+impl Visualizer for WgpuSphereRenderer {
+    fn name(&self) -> &'static str {
+        "Point Cloud"
+    }
+
+    fn prepare(
         &mut self,
         device: &Arc<wgpu::Device>,
         target_format: wgpu::TextureFormat,
@@ -99,10 +896,10 @@ impl WgpuSphereRenderer {
             source: wgpu::ShaderSource::Wgsl(SHADERS_WGSL.into()),
         });
 
-        // --- MVP Resources (Group 0) ---
-        let mvp_bind_group_layout =
+        // --- Camera Resources (Group 0) ---
+        let camera_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("MVP Bind Group Layout"),
+                label: Some("Camera Bind Group Layout"),
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
                     visibility: wgpu::ShaderStages::VERTEX,
@@ -114,220 +911,603 @@ impl WgpuSphereRenderer {
                     count: None,
                 }],
             });
-        let mvp_matrix_initial = Mat4::IDENTITY;
-        let mvp_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Sphere MVP Uniform Buffer"),
-            contents: bytemuck::cast_slice(&[mvp_matrix_initial]),
+        let camera_initial = CameraUniform {
+            model_view: Mat4::IDENTITY,
+            projection: Mat4::IDENTITY,
+        };
+        let camera_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sphere Camera Uniform Buffer"),
+            contents: bytemuck::bytes_of(&camera_initial),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
-        let mvp_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Sphere MVP Bind Group"),
-            layout: &mvp_bind_group_layout,
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Sphere Camera Bind Group"),
+            layout: &camera_bind_group_layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
-                resource: mvp_uniform_buffer.as_entire_binding(),
+                resource: camera_uniform_buffer.as_entire_binding(),
             }],
         });
 
-        // --- Visual Params Resources (Group 1 - Color only) ---
+        // --- Visual Params Resources (Group 1 - Color and sprite shape) ---
         let visual_params_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("Visual Params Bind Group Layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: Some(
-                            std::num::NonZeroU64::new(
-                                std::mem::size_of::<VisualParamsUniform>() as u64
-                            )
-                            .unwrap(),
-                        ),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: Some(
+                                std::num::NonZeroU64::new(
+                                    std::mem::size_of::<VisualParamsUniform>() as u64,
+                                )
+                                .unwrap(),
+                            ),
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
             });
-        let visual_params_initial = VisualParamsUniform {
-            color: [
-                self.current_color_rgb[0],
-                self.current_color_rgb[1],
-                self.current_color_rgb[2],
-                1.0,
-            ],
-        };
+        let visual_params_initial = self.visual_params();
         let visual_params_uniform_buffer =
             device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("Visual Params Uniform Buffer"),
                 contents: bytemuck::bytes_of(&visual_params_initial),
                 usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             });
+        let gradient_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sphere Gradient Storage Buffer"),
+            contents: bytemuck::cast_slice(&self.palette.gradient().bake(GRADIENT_RESOLUTION)),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
         let visual_params_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Visual Params Bind Group"),
             layout: &visual_params_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: visual_params_uniform_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: visual_params_uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: gradient_buffer.as_entire_binding(),
+                },
+            ],
         });
 
-        // --- Vertex Buffer ---
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Sphere Vertex Buffer"),
-            contents: bytemuck::cast_slice(&self.points),
-            usage: wgpu::BufferUsages::VERTEX,
+        // --- Spectrum Resources (Group 2) ---
+        let spectrum_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Spectrum Bind Group Layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let spectrum_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sphere Spectrum Storage Buffer"),
+            contents: bytemuck::cast_slice(&[0.0f32; SPECTRUM_BANDS]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let spectrum_params_uniform_buffer =
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Spectrum Params Uniform Buffer"),
+                contents: bytemuck::bytes_of(&self.spectrum_params()),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+        let spectrum_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Spectrum Bind Group"),
+            layout: &spectrum_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: spectrum_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: spectrum_params_uniform_buffer.as_entire_binding(),
+                },
+            ],
         });
-        let num_vertices = self.points.len() as u32;
+
+        // --- Vertex Buffer ---
+        self.vertices = Some(Arc::new(SphereVertexBuffer::new(
+            device,
+            &self.points,
+            self.points_generation,
+        )));
+        // Kept around so point count changes can grow the vertex buffer later
+        self.device = Some(device.clone());
 
         // --- Pipeline ---
+        // The point pipelines depend on the MSAA / depth settings, so they're built
+        // on first use (see `SphereWgpuPrimitive::pipeline`)
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Sphere Render Pipeline Layout"),
-            bind_group_layouts: &[&mvp_bind_group_layout, &visual_params_bind_group_layout],
+            bind_group_layouts: &[
+                &camera_bind_group_layout,
+                &visual_params_bind_group_layout,
+                &spectrum_bind_group_layout,
+            ],
             push_constant_ranges: &[],
         });
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Sphere Render Pipeline"),
-            layout: Some(&pipeline_layout),
+
+        // --- Composite Resources ---
+        let composite_shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Sphere Composite Shader"),
+            source: wgpu::ShaderSource::Wgsl(with_fullscreen_vertex(COMPOSITE_WGSL).into()),
+        });
+        let composite_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Sphere Composite Bind Group Layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+        let composite_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Sphere Composite Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let composite_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Sphere Composite Pipeline Layout"),
+                bind_group_layouts: &[&composite_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let composite_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Sphere Composite Pipeline"),
+            layout: Some(&composite_pipeline_layout),
             vertex: wgpu::VertexState {
-                module: &shader_module,
+                module: &composite_shader_module,
                 entry_point: "vs_main",
-                buffers: &[wgpu::VertexBufferLayout {
-                    array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    step_mode: wgpu::VertexStepMode::Vertex,
-                    attributes: &wgpu::vertex_attr_array![0 => Float32x3],
-                }],
+                buffers: &[],
             },
             fragment: Some(wgpu::FragmentState {
-                module: &shader_module,
+                module: &composite_shader_module,
                 entry_point: "fs_main",
                 targets: &[Some(wgpu::ColorTargetState {
                     format: target_format,
-                    blend: Some(wgpu::BlendState::REPLACE),
+                    // The offscreen image is drawn over transparent black, so it's premultiplied
+                    blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
             }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::PointList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-            },
+            primitive: wgpu::PrimitiveState::default(),
             depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
+            multisample: wgpu::MultisampleState::default(),
             multiview: None,
         });
 
         self.primitive = Some(Arc::new(SphereWgpuPrimitive {
-            vertex_buffer,
-            num_vertices,
-            mvp_uniform_buffer,
-            mvp_bind_group,
+            camera_uniform_buffer,
+            camera_bind_group,
             visual_params_uniform_buffer,
+            gradient_buffer,
             visual_params_bind_group,
-            render_pipeline,
+            spectrum_buffer,
+            spectrum_params_uniform_buffer,
+            spectrum_bind_group,
+            shader_module: Mutex::new(shader_module),
+            pipeline_layout,
+            target_format,
+            pipelines: Mutex::new(HashMap::new()),
+            composite_bind_group_layout,
+            composite_sampler,
+            composite_pipeline,
+            target: Mutex::new(None),
         }));
         tracing::info!("WgpuSphereRenderer resources prepared successfully.");
         Ok(())
     }
 
-    /// Update visual state (color, scale) based on audio playback state and analysis data
-    pub fn update_visual_state(
-        &mut self,
-        playback_state: PlaybackState,
-        audio_data: &Option<AudioAnalysisData>,
-    ) {
-        // Hue cycles based on time
-        self.current_hue = (self.time * 0.05).fract();
+    fn update(&mut self, input: &VisualizerInput, dt: f32) {
+        self.time += dt;
+        if let Some(transition) = &mut self.transition {
+            let (preset, finished) = transition.advance(dt);
+            self.preset = preset;
+            if finished {
+                self.transition = None;
+            }
+        }
+        let playing = input
+            .analysis
+            .filter(|_| input.playback_state == PlaybackState::Playing);
+        self.modulation = self.modulator.process(&self.preset.modulation, playing, dt);
+        if self.camera.auto_rotate {
+            let speed = (1.0 + self.modulation.get(ModTarget::RotationSpeed)).max(0.0);
+            self.spin[0] += dt * self.preset.spin_rate_y * speed;
+            self.spin[1] += dt * self.preset.spin_rate_x * speed;
+        }
+        self.camera.update(playing, dt);
+        if self.geometry_job.is_some()
+            || self.built_for != (self.shape, self.point_count, self.radius)
+        {
+            self.rebuild_geometry();
+        }
+        self.update_visual_state(input.playback_state, input.analysis);
+        self.update_shader(dt);
+    }
 
-        let target_saturation;
-        let target_scale;
+    fn paint(&mut self, aspect_ratio: f32) -> Option<Box<dyn VisualizerFrame>> {
+        let primitive = self.primitive.clone()?;
+        let vertices = self.vertices.clone()?;
+        if let Some(device) = &self.device {
+            self.effective_sample_count = supported_sample_count(
+                device,
+                self.adapter.as_deref(),
+                primitive.target_format,
+                self.msaa.sample_count(),
+            );
+        }
+        Some(Box::new(SphereFrame {
+            primitive,
+            vertices,
+            num_vertices: self.points.len() as u32,
+            points: self.points.clone(),
+            points_generation: self.points_generation,
+            camera: self.calculate_camera(aspect_ratio),
+            visual_params: self.visual_params(),
+            gradient: self.palette.gradient().bake(GRADIENT_RESOLUTION),
+            blend: self.blend,
+            depth_test: self.depth_test,
+            sample_count: self.effective_sample_count,
+            spectrum: self.spectrum_frame(),
+            target: OnceLock::new(),
+        }))
+    }
 
-        if playback_state == PlaybackState::Playing {
-            if let Some(data) = audio_data {
-                let amplitude_factor = (data.rms_amplitude * 3.0).clamp(0.0, 1.0);
-                target_saturation = 0.1 + amplitude_factor * 0.9;
-                target_scale = 0.75 + (amplitude_factor * 2.5);
-            } else {
-                target_saturation = 0.25;
-                target_scale = 0.75;
-            }
-        } else {
-            target_saturation = 0.25;
-            target_scale = 1.33;
+    fn settings_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("Presets:");
+        if let Some(preset) = self.presets.browser_ui(ui, &self.preset) {
+            let duration = self.presets.transition_seconds;
+            self.switch_preset(preset, duration);
         }
+        // Every transition step overwrites the preset, hand edits made meanwhile end it
+        let before_edits = self.transition.is_some().then(|| self.preset.clone());
 
-        // Smooth towards target values
-        let lerp_factor = 0.08;
-        self.current_saturation += (target_saturation - self.current_saturation) * lerp_factor;
-        self.current_scale += (target_scale - self.current_scale) * lerp_factor;
+        ui.label("Shape:");
+        egui::ComboBox::from_id_source("point_cloud_shape")
+            .selected_text(self.shape.label())
+            .show_ui(ui, |ui| {
+                for shape in Shape::ALL {
+                    ui.selectable_value(&mut self.shape, shape, shape.label());
+                }
+            });
+        ui.label("Spectrum mapping:");
+        egui::ComboBox::from_id_source("spectrum_mapping")
+            .selected_text(self.spectrum_mapping.label())
+            .show_ui(ui, |ui| {
+                for mapping in SpectrumMapping::ALL {
+                    ui.selectable_value(&mut self.spectrum_mapping, mapping, mapping.label());
+                }
+            });
+        ui.add(egui::Slider::new(&mut self.preset.displacement, 0.0..=1.0).text("Displacement"));
+        ui.add(
+            egui::Slider::new(&mut self.point_count, MIN_POINT_COUNT..=MAX_POINT_COUNT)
+                .logarithmic(true)
+                .text("Points"),
+        );
+        ui.add(egui::Slider::new(&mut self.radius, 0.25..=2.0).text("Radius"));
 
-        self.current_scale = self.current_scale.clamp(0.75, 7.50);
-        // self.current_saturation = self.current_saturation.clamp(0.0, 1.0);
+        ui.label("Point sprites:");
+        egui::ComboBox::from_id_source("point_sprite_blend")
+            .selected_text(self.blend.label())
+            .show_ui(ui, |ui| {
+                for blend in SpriteBlend::ALL {
+                    ui.selectable_value(&mut self.blend, blend, blend.label());
+                }
+            });
+        ui.add(
+            egui::Slider::new(&mut self.preset.point_size, 0.002..=0.2)
+                .logarithmic(true)
+                .text("Size"),
+        );
+        ui.add(egui::Slider::new(&mut self.preset.softness, 0.0..=1.0).text("Softness"));
+        ui.add(
+            egui::Slider::new(&mut self.preset.size_reactivity, 0.0..=4.0).text("Size reactivity"),
+        );
+        ui.add(egui::Slider::new(&mut self.preset.opacity, 0.05..=1.0).text("Opacity"));
 
-        // Convert final HSV to RGB
-        self.current_color_rgb = hsv_to_rgb(
-            self.current_hue,
-            self.current_saturation,
-            self.current_value,
+        ui.label("Rendering:");
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("point_cloud_msaa")
+                .selected_text(format!("MSAA {}", self.msaa.label()))
+                .show_ui(ui, |ui| {
+                    for level in MsaaLevel::ALL {
+                        ui.selectable_value(&mut self.msaa, level, level.label());
+                    }
+                });
+            if self.primitive.is_some() && self.effective_sample_count != self.msaa.sample_count() {
+                ui.label(format!(
+                    "(not supported, using {}x)",
+                    self.effective_sample_count
+                ));
+            }
+        });
+        ui.checkbox(&mut self.depth_test, "Depth test");
+        ui.add(egui::Slider::new(&mut self.preset.depth_fade, 0.0..=1.0).text("Depth fade"));
+        ui.checkbox(
+            &mut self.live_shader,
+            format!(
+                "Live shader ({}, reloads on save)",
+                self.shader_file.path().display()
+            ),
         );
+
+        ui.label("Colour:");
+        egui::ComboBox::from_id_source("point_cloud_color_source")
+            .selected_text(self.color_source.label())
+            .show_ui(ui, |ui| {
+                for source in ColorSource::ALL {
+                    ui.selectable_value(&mut self.color_source, source, source.label());
+                }
+            });
+        if self.color_source != ColorSource::HueCycle {
+            self.palette.settings_ui(ui, "point_cloud_palette");
+            ui.add(
+                egui::Slider::new(&mut self.preset.palette_cycle_speed, 0.0..=1.0)
+                    .suffix(" /s")
+                    .text("Palette cycling"),
+            );
+        }
+
+        egui::CollapsingHeader::new("Reaction")
+            .id_source("point_cloud_reaction")
+            .show(ui, |ui| {
+                self.preset.reaction_ui(ui);
+            });
+        egui::CollapsingHeader::new("Modulation")
+            .id_source("point_cloud_modulation")
+            .show(ui, |ui| {
+                self.preset.modulation.settings_ui(ui, &mut self.modulator);
+            });
+        if before_edits.is_some_and(|before| before != self.preset) {
+            self.transition = None;
+        }
+
+        ui.label("Camera:");
+        self.camera.settings_ui(ui);
     }
 
-    // Calculate MVP matrix, re-applying the overall scale
-    pub fn calculate_mvp(&self, aspect_ratio: f32) -> Mat4 {
-        let view = Mat4::look_at_rh(
-            self.camera_position.into(),
-            Vec3A::ZERO.into(),
-            Vec3A::Y.into(),
-        );
-        // Apply rotation AND scale from self.current_scale
-        let model = Mat4::from_rotation_y(self.time * 0.4)
-            * Mat4::from_rotation_x(self.time * 0.25)
-            * Mat4::from_scale(Vec3A::splat(self.current_scale).into());
+    fn handle_input(&mut self, response: &egui::Response) {
+        self.camera.handle_input(response);
+    }
+
+    fn set_adapter(&mut self, adapter: &Arc<wgpu::Adapter>) {
+        self.adapter = Some(adapter.clone());
+    }
 
-        let proj = Mat4::perspective_rh_gl(std::f32::consts::FRAC_PI_4, aspect_ratio, 0.1, 100.0);
-        proj * view * model
+    fn bloom_modulation(&self) -> Option<f32> {
+        self.preset
+            .modulation
+            .drives(ModTarget::Bloom)
+            .then(|| self.modulation.get(ModTarget::Bloom))
     }
 
-    pub fn get_primitive_arc(&self) -> Option<Arc<SphereWgpuPrimitive>> {
-        self.primitive.clone()
+    fn overlay_message(&self) -> Option<String> {
+        self.shader_error.clone()
     }
+}
 
-    // Paint primitive - updates color uniform only
-    pub fn paint_primitive<'rp_lifetime>(
-        primitive: &'rp_lifetime SphereWgpuPrimitive,
-        mvp_matrix: &Mat4,
-        color: &[f32; 3],
-        rpass: &mut wgpu::RenderPass<'rp_lifetime>,
-        queue: &Arc<wgpu::Queue>,
+struct SphereFrame {
+    primitive: Arc<SphereWgpuPrimitive>,
+    vertices: Arc<SphereVertexBuffer>,
+    num_vertices: u32,
+    // Uploaded before drawing when `vertices` holds an older generation
+    points: Arc<Vec<GeometryPoint>>,
+    points_generation: u64,
+    camera: CameraUniform,
+    visual_params: VisualParamsUniform,
+    gradient: Vec<[f32; 4]>,
+    blend: SpriteBlend,
+    depth_test: bool,
+    sample_count: u32,
+    spectrum: SpectrumFrame,
+    // Rendered by `prepare`, composited by `paint`
+    target: OnceLock<Arc<SphereTarget>>,
+}
+
+impl VisualizerFrame for SphereFrame {
+    // The points get their own pass, egui's has no depth buffer and a single sample
+    fn prepare(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        size_in_pixels: [u32; 2],
     ) {
+        let primitive = &self.primitive;
+        // Only ship the points across when they changed, a million of them is ~32 MB.
+        // Marked done here rather than when the frame is built, a frame that never gets
+        // prepared leaves the upload to the next one.
+        if self.vertices.uploaded.load(Ordering::Acquire) != self.points_generation {
+            queue.write_buffer(
+                &self.vertices.buffer,
+                0,
+                bytemuck::cast_slice(self.points.as_slice()),
+            );
+            self.vertices
+                .uploaded
+                .store(self.points_generation, Ordering::Release);
+        }
+
         queue.write_buffer(
-            &primitive.mvp_uniform_buffer,
+            &primitive.camera_uniform_buffer,
             0,
-            bytemuck::cast_slice(&[*mvp_matrix]),
+            bytemuck::bytes_of(&self.camera),
         );
-
-        let visual_data = VisualParamsUniform {
-            color: [color[0], color[1], color[2], 1.0],
-        };
         queue.write_buffer(
             &primitive.visual_params_uniform_buffer,
             0,
-            bytemuck::bytes_of(&visual_data),
+            bytemuck::bytes_of(&self.visual_params),
+        );
+        queue.write_buffer(
+            &primitive.gradient_buffer,
+            0,
+            bytemuck::cast_slice(&self.gradient),
         );
 
-        rpass.set_pipeline(&primitive.render_pipeline);
-        rpass.set_bind_group(0, &primitive.mvp_bind_group, &[]);
-        rpass.set_bind_group(1, &primitive.visual_params_bind_group, &[]);
-        rpass.set_vertex_buffer(0, primitive.vertex_buffer.slice(..));
-        rpass.draw(0..primitive.num_vertices, 0..1);
+        let band_count = self.spectrum.values.len().min(SPECTRUM_BANDS);
+        queue.write_buffer(
+            &primitive.spectrum_buffer,
+            0,
+            bytemuck::cast_slice(&self.spectrum.values[..band_count]),
+        );
+        queue.write_buffer(
+            &primitive.spectrum_params_uniform_buffer,
+            0,
+            bytemuck::bytes_of(&spectrum_params_uniform(
+                self.spectrum.mapping,
+                self.spectrum.displacement,
+            )),
+        );
+
+        let target = primitive.target(device, size_in_pixels, self.sample_count);
+        let pipeline = primitive.pipeline(
+            device,
+            PipelineKey {
+                blend: self.blend,
+                sample_count: self.sample_count,
+                depth_test: self.depth_test,
+            },
+        );
+        // With MSAA the points go into the multisampled texture and get resolved on store
+        let (view, resolve_target) = match &target.msaa_view {
+            Some(msaa_view) => (msaa_view, Some(&target.resolve_view)),
+            None => (&target.resolve_view, None),
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Sphere Offscreen Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &target.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Discard,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&pipeline);
+        render_pass.set_bind_group(0, &primitive.camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &primitive.visual_params_bind_group, &[]);
+        render_pass.set_bind_group(2, &primitive.spectrum_bind_group, &[]);
+        // The buffer can be bigger than the current point cloud, only draw what's in use
+        let used_bytes = self.num_vertices as u64 * std::mem::size_of::<GeometryPoint>() as u64;
+        render_pass.set_vertex_buffer(0, self.vertices.buffer.slice(..used_bytes));
+        render_pass.draw(0..6, 0..self.num_vertices);
+        drop(render_pass);
+
+        let _ = self.target.set(target);
+    }
+
+    fn paint<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, _queue: &wgpu::Queue) {
+        let Some(target) = self.target.get() else {
+            return;
+        };
+        render_pass.set_pipeline(&self.primitive.composite_pipeline);
+        render_pass.set_bind_group(0, &target.composite_bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
+    }
+}
+
+// Features the point cloud makes use of when the adapter has them, for every device we create.
+// Adapter specific format features are what lets it go past 4x MSAA.
+pub fn optional_device_features(adapter: &wgpu::Adapter) -> wgpu::Features {
+    adapter.features() & wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+}
+
+// Highest sample count up to `requested` that the colour and depth formats both support.
+// 4x always works, 8x needs the adapter's own format features, which the device only
+// allows with TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES enabled.
+fn supported_sample_count(
+    device: &wgpu::Device,
+    adapter: Option<&wgpu::Adapter>,
+    format: wgpu::TextureFormat,
+    requested: u32,
+) -> u32 {
+    let adapter_specific = device
+        .features()
+        .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+    let features = |format: wgpu::TextureFormat| match adapter {
+        Some(adapter) if adapter_specific => adapter.get_texture_format_features(format),
+        _ => format.guaranteed_format_features(device.features()),
+    };
+    let color = features(format);
+    let depth = features(DEPTH_FORMAT);
+    [8, 4, 2, 1]
+        .into_iter()
+        .find(|&count| {
+            count <= requested
+                && color.flags.sample_count_supported(count)
+                && depth.flags.sample_count_supported(count)
+        })
+        .unwrap_or(1)
+}
+
+fn spectrum_params_uniform(mapping: SpectrumMapping, displacement: f32) -> SpectrumParamsUniform {
+    SpectrumParamsUniform {
+        mapping: mapping as u32,
+        num_bands: SPECTRUM_BANDS as u32,
+        displacement,
+        _padding: 0.0,
     }
 }
 
@@ -352,3 +1532,9 @@ fn hsv_to_rgb(h: f32, s: f32, v: f32) -> [f32; 3] {
         _ => unreachable!(),
     }
 }
+
+// WGSL's smoothstep
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
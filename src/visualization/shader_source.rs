@@ -0,0 +1,114 @@
+use crate::audio::cues::config_dir;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// How often the file's modification time gets checked, in seconds
+const POLL_INTERVAL: f32 = 0.5;
+
+// Vertex stage for passes that cover the whole target: `vs_main` draws six vertices as
+// two triangles and hands `VertexOutput.uv` to the fragment stage, top down like texture rows
+const FULLSCREEN_VERTEX_WGSL: &str = r#"
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0), vec2<f32>(1.0, 0.0), vec2<f32>(0.0, 1.0),
+        vec2<f32>(0.0, 1.0), vec2<f32>(1.0, 0.0), vec2<f32>(1.0, 1.0),
+    );
+    let uv = corners[vertex_index];
+    var out: VertexOutput;
+    // Texture rows run top down, clip space bottom up
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+"#;
+
+// `source` with the fullscreen vertex stage prepended, for fragment only shaders
+pub fn with_fullscreen_vertex(source: &str) -> String {
+    format!("{}\n{}", FULLSCREEN_VERTEX_WGSL, source)
+}
+
+// Where a file under the shaders directory lives, `relative` being e.g. "shaders/sphere.wgsl".
+// Looks next to the executable first (how a build gets shipped), then in the config
+// directory, and falls back to the working directory, which is what `cargo run` has.
+pub fn shader_path(relative: &str) -> PathBuf {
+    let beside_exe = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join(relative)));
+    beside_exe
+        .into_iter()
+        .chain(config_dir().map(|dir| dir.join(relative)))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| PathBuf::from(relative))
+}
+
+// A WGSL file on disk that gets picked up again whenever it changes. Polls the
+// modification time, cheap enough at this rate and works the same everywhere.
+pub struct WatchedShader {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    since_check: f32,
+    // Forces a read on the next poll, set initially and by `reload`
+    pending: bool,
+}
+
+impl WatchedShader {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        WatchedShader {
+            path: path.into(),
+            modified: None,
+            since_check: 0.0,
+            pending: true,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Reads the file again on the next poll even if it looks unchanged
+    pub fn reload(&mut self) {
+        self.pending = true;
+    }
+
+    // The file's contents when it's new or changed since the last call, `dt` is in seconds
+    pub fn poll(&mut self, dt: f32) -> Option<Result<String, String>> {
+        self.since_check += dt;
+        if !self.pending && self.since_check < POLL_INTERVAL {
+            return None;
+        }
+        self.since_check = 0.0;
+
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if !self.pending && modified == self.modified {
+            return None;
+        }
+        self.pending = false;
+        self.modified = modified;
+        Some(
+            std::fs::read_to_string(&self.path)
+                .map_err(|e| format!("Failed to read {}: {}", self.path.display(), e)),
+        )
+    }
+}
+
+// Runs `create` with wgpu validation errors captured instead of going to the
+// uncaptured error handler (which panics), so a broken shader doesn't take the app down
+pub fn capture_validation<T>(
+    device: &wgpu::Device,
+    create: impl FnOnce() -> T,
+) -> Result<T, String> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let created = create();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(error) => Err(error.to_string()),
+        None => Ok(created),
+    }
+}
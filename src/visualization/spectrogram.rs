@@ -0,0 +1,425 @@
+use crate::visualization::shader_source::with_fullscreen_vertex;
+use crate::visualization::spectrum::{FrequencyScale, MAX_FREQUENCY_HZ, MIN_FREQUENCY_HZ};
+use crate::visualization::visualizer::{Visualizer, VisualizerFrame, VisualizerInput};
+use anyhow::Result;
+use bytemuck::{Pod, Zeroable};
+use eframe::egui;
+use std::sync::Arc;
+use wgpu::util::DeviceExt;
+
+// Analysis windows kept on screen, ~12s at 1024 sample windows
+const HISTORY_ROWS: u32 = 512;
+// Enough for FFT sizes up to 2048
+const MAX_BINS: u32 = 1025;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMap {
+    Viridis,
+    Magma,
+    Grayscale,
+}
+
+impl ColorMap {
+    pub const ALL: [ColorMap; 3] = [ColorMap::Viridis, ColorMap::Magma, ColorMap::Grayscale];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ColorMap::Viridis => "Viridis",
+            ColorMap::Magma => "Magma",
+            ColorMap::Grayscale => "Grayscale",
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct SpectrogramParamsUniform {
+    // Ring row the next window goes into, the newest one is just before it
+    write_row: u32,
+    history_rows: u32,
+    bin_count: u32,
+    color_map: u32,
+    bin_width_hz: f32,
+    min_frequency_hz: f32,
+    max_frequency_hz: f32,
+    log_frequency: u32,
+    db_scale: u32,
+    db_range: f32,
+    _padding: [f32; 2],
+}
+
+const SPECTROGRAM_SHADER_WGSL: &str = r#"
+struct SpectrogramParams {
+    write_row: u32,
+    history_rows: u32,
+    bin_count: u32,
+    color_map: u32,
+    bin_width_hz: f32,
+    min_frequency_hz: f32,
+    max_frequency_hz: f32,
+    log_frequency: u32,
+    db_scale: u32,
+    db_range: f32,
+    _padding: vec2<f32>,
+};
+@group(0) @binding(0)
+var<uniform> params: SpectrogramParams;
+// x = FFT bin, y = ring row, raw linear magnitudes
+@group(0) @binding(1)
+var history: texture_2d<f32>;
+
+// Polynomial fits of the matplotlib colour maps
+fn viridis(t: f32) -> vec3<f32> {
+    let c0 = vec3<f32>(0.2777273272234177, 0.005407344544966578, 0.3340998053353061);
+    let c1 = vec3<f32>(0.1050930431085774, 1.404613529898575, 1.384590162594685);
+    let c2 = vec3<f32>(-0.3308618287255563, 0.214847559468213, 0.09509516302823659);
+    let c3 = vec3<f32>(-4.634230498983486, -5.799100973351585, -19.33244095627987);
+    let c4 = vec3<f32>(6.228269936347081, 14.17993336680509, 56.69055260068105);
+    let c5 = vec3<f32>(4.776384997670288, -13.74514537774601, -65.35303263337234);
+    let c6 = vec3<f32>(-5.435455855934631, 4.645852612178535, 26.3124352495832);
+    return c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
+}
+
+fn magma(t: f32) -> vec3<f32> {
+    let c0 = vec3<f32>(-0.002136485053939582, -0.000749655052795221, -0.005386127855323933);
+    let c1 = vec3<f32>(0.2516605407371642, 0.6775232436837668, 2.494026599312351);
+    let c2 = vec3<f32>(8.353717279216625, -3.577719514958484, 0.3144679030132573);
+    let c3 = vec3<f32>(-27.66873308576866, 14.26473078096533, -13.64921318813922);
+    let c4 = vec3<f32>(52.17613981234068, -27.94360607168351, 12.94416944238394);
+    let c5 = vec3<f32>(-50.76852536473588, 29.04658282127291, 4.23415299384598);
+    let c6 = vec3<f32>(18.65570506591883, -11.48977351997711, -5.601961508734096);
+    return c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
+}
+
+fn magnitude_at(bin: u32, row: u32) -> f32 {
+    return textureLoad(history, vec2<u32>(min(bin, params.bin_count - 1u), row), 0).r;
+}
+
+// Time runs left to right with the newest window on the right edge, frequency bottom to top
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let age = u32((1.0 - in.uv.x) * f32(params.history_rows - 1u));
+    let row = (params.write_row + params.history_rows - 1u - age) % params.history_rows;
+    // uv runs top down
+    let height = 1.0 - in.uv.y;
+
+    var frequency: f32;
+    if params.log_frequency == 1u {
+        frequency = params.min_frequency_hz
+            * pow(params.max_frequency_hz / params.min_frequency_hz, height);
+    } else {
+        frequency = mix(params.min_frequency_hz, params.max_frequency_hz, height);
+    }
+    // Bins are interpolated, at the bottom of a log axis they span many pixels
+    let bin = frequency / params.bin_width_hz;
+    let low = u32(floor(bin));
+    let magnitude = mix(magnitude_at(low, row), magnitude_at(low + 1u, row), fract(bin));
+
+    // Hann windowed magnitudes come out at a quarter of the sine amplitude
+    let amplitude = magnitude * 4.0;
+    var level: f32;
+    if params.db_scale == 1u {
+        let db = 20.0 * log2(max(amplitude, 1e-9)) / log2(10.0);
+        level = clamp(1.0 + db / params.db_range, 0.0, 1.0);
+    } else {
+        level = clamp(amplitude, 0.0, 1.0);
+    }
+
+    var color: vec3<f32>;
+    switch params.color_map {
+        case 1u: {
+            color = magma(level);
+        }
+        case 2u: {
+            color = vec3<f32>(level);
+        }
+        default: {
+            color = viridis(level);
+        }
+    }
+    return vec4<f32>(clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)), 1.0);
+}
+"#;
+
+pub struct SpectrogramPrimitive {
+    params_uniform_buffer: wgpu::Buffer,
+    history_texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+    render_pipeline: wgpu::RenderPipeline,
+}
+
+// Scrolling waterfall of the analysis windows
+pub struct Spectrogram {
+    primitive: Option<Arc<SpectrogramPrimitive>>,
+    // Rows received since the last paint, with the ring row each one goes into
+    pending_rows: Vec<(u32, Vec<f32>)>,
+    write_row: u32,
+    bin_count: u32,
+    bin_width_hz: f32,
+    last_timestamp: Option<f64>,
+    pub color_map: ColorMap,
+    pub scale: FrequencyScale,
+    pub db_scale: bool,
+    // Dynamic range shown in dB mode, anything quieter is black
+    pub db_range: f32,
+}
+
+impl Spectrogram {
+    pub fn new() -> Self {
+        Spectrogram {
+            primitive: None,
+            pending_rows: Vec::new(),
+            write_row: 0,
+            bin_count: 1,
+            bin_width_hz: 1.0,
+            last_timestamp: None,
+            color_map: ColorMap::Viridis,
+            scale: FrequencyScale::Log,
+            db_scale: true,
+            db_range: 80.0,
+        }
+    }
+
+    fn params(&self) -> SpectrogramParamsUniform {
+        SpectrogramParamsUniform {
+            write_row: self.write_row,
+            history_rows: HISTORY_ROWS,
+            bin_count: self.bin_count,
+            color_map: self.color_map as u32,
+            bin_width_hz: self.bin_width_hz,
+            min_frequency_hz: MIN_FREQUENCY_HZ,
+            max_frequency_hz: MAX_FREQUENCY_HZ,
+            log_frequency: (self.scale == FrequencyScale::Log) as u32,
+            db_scale: self.db_scale as u32,
+            db_range: self.db_range,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+impl Visualizer for Spectrogram {
+    fn name(&self) -> &'static str {
+        "Spectrogram"
+    }
+
+    fn prepare(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        target_format: wgpu::TextureFormat,
+    ) -> Result<()> {
+        if self.primitive.is_some() {
+            return Ok(());
+        }
+        tracing::info!("Preparing Spectrogram resources...");
+
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Spectrogram Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                with_fullscreen_vertex(SPECTROGRAM_SHADER_WGSL).into(),
+            ),
+        });
+
+        // Zeroed on creation, so the waterfall starts out black
+        let history_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Spectrogram History Texture"),
+            size: wgpu::Extent3d {
+                width: MAX_BINS,
+                height: HISTORY_ROWS,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let history_view = history_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Spectrogram Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    // R32Float isn't filterable everywhere, the shader only uses textureLoad
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let params_uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Spectrogram Params Uniform Buffer"),
+            contents: bytemuck::bytes_of(&self.params()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Spectrogram Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&history_view),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Spectrogram Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Spectrogram Render Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader_module,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_module,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        self.primitive = Some(Arc::new(SpectrogramPrimitive {
+            params_uniform_buffer,
+            history_texture,
+            bind_group,
+            render_pipeline,
+        }));
+        tracing::info!("Spectrogram resources prepared successfully.");
+        Ok(())
+    }
+
+    // Every new analysis window becomes a row, paused playback freezes the waterfall
+    fn update(&mut self, input: &VisualizerInput, _dt: f32) {
+        let Some(data) = input.analysis else {
+            return;
+        };
+        if self.last_timestamp == Some(data.timestamp) {
+            return;
+        }
+        self.last_timestamp = Some(data.timestamp);
+
+        let bins = data.frequency_magnitudes.len().min(MAX_BINS as usize);
+        if bins == 0 {
+            return;
+        }
+        self.bin_count = bins as u32;
+        self.bin_width_hz = data.sample_rate as f32 / data.fft_size.max(1) as f32;
+        self.pending_rows
+            .push((self.write_row, data.frequency_magnitudes[..bins].to_vec()));
+        self.write_row = (self.write_row + 1) % HISTORY_ROWS;
+        // Not being painted (mode hidden, window minimised), older rows would be overwritten anyway
+        if self.pending_rows.len() > HISTORY_ROWS as usize {
+            self.pending_rows.remove(0);
+        }
+    }
+
+    fn paint(&mut self, _aspect_ratio: f32) -> Option<Box<dyn VisualizerFrame>> {
+        let primitive = self.primitive.clone()?;
+        Some(Box::new(SpectrogramFrame {
+            primitive,
+            params: self.params(),
+            new_rows: std::mem::take(&mut self.pending_rows),
+        }))
+    }
+
+    fn settings_ui(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_id_source("spectrogram_color_map")
+            .selected_text(self.color_map.label())
+            .show_ui(ui, |ui| {
+                for color_map in ColorMap::ALL {
+                    ui.selectable_value(&mut self.color_map, color_map, color_map.label());
+                }
+            });
+        let mut log_scale = self.scale == FrequencyScale::Log;
+        if ui.checkbox(&mut log_scale, "Log frequency").changed() {
+            self.scale = if log_scale {
+                FrequencyScale::Log
+            } else {
+                FrequencyScale::Linear
+            };
+        }
+        ui.checkbox(&mut self.db_scale, "dB");
+        ui.add_enabled(
+            self.db_scale,
+            egui::Slider::new(&mut self.db_range, 30.0..=120.0).text("Range (dB)"),
+        );
+    }
+}
+
+struct SpectrogramFrame {
+    primitive: Arc<SpectrogramPrimitive>,
+    params: SpectrogramParamsUniform,
+    new_rows: Vec<(u32, Vec<f32>)>,
+}
+
+impl VisualizerFrame for SpectrogramFrame {
+    fn paint<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, queue: &wgpu::Queue) {
+        let primitive = &self.primitive;
+        for (row, magnitudes) in &self.new_rows {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &primitive.history_texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: *row,
+                        z: 0,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                bytemuck::cast_slice(magnitudes),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(std::mem::size_of_val(magnitudes.as_slice()) as u32),
+                    rows_per_image: None,
+                },
+                wgpu::Extent3d {
+                    width: magnitudes.len() as u32,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        queue.write_buffer(
+            &primitive.params_uniform_buffer,
+            0,
+            bytemuck::bytes_of(&self.params),
+        );
+
+        render_pass.set_pipeline(&primitive.render_pipeline);
+        render_pass.set_bind_group(0, &primitive.bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
+    }
+}
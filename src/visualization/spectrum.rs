@@ -0,0 +1,91 @@
+use crate::audio::AudioAnalysisData;
+
+// Frequency range spread over the bands, anything outside is ignored
+pub const MIN_FREQUENCY_HZ: f32 = 30.0;
+pub const MAX_FREQUENCY_HZ: f32 = 16_000.0;
+// Levels below this read as zero, 0dB (a full scale sine) reads as one
+const FLOOR_DB: f32 = -60.0;
+// Per frame smoothing, rises fast and falls back slowly so peaks stay visible
+const ATTACK: f32 = 0.6;
+const RELEASE: f32 = 0.12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrequencyScale {
+    Linear,
+    Log,
+}
+
+impl FrequencyScale {
+    // Where `position` (0..1 along the axis) lands in Hz
+    pub fn frequency_at(&self, position: f32) -> f32 {
+        match self {
+            FrequencyScale::Linear => {
+                MIN_FREQUENCY_HZ + (MAX_FREQUENCY_HZ - MIN_FREQUENCY_HZ) * position
+            }
+            FrequencyScale::Log => {
+                MIN_FREQUENCY_HZ * (MAX_FREQUENCY_HZ / MIN_FREQUENCY_HZ).powf(position)
+            }
+        }
+    }
+}
+
+// FFT magnitudes regrouped into bands with values in 0..1, ready for the GPU
+pub struct SpectrumBands {
+    values: Vec<f32>,
+    scale: FrequencyScale,
+}
+
+impl SpectrumBands {
+    pub fn new(num_bands: usize) -> Self {
+        SpectrumBands {
+            values: vec![0.0; num_bands],
+            scale: FrequencyScale::Log,
+        }
+    }
+
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+
+    pub fn set_num_bands(&mut self, num_bands: usize) {
+        self.values.resize(num_bands, 0.0);
+    }
+
+    pub fn set_scale(&mut self, scale: FrequencyScale) {
+        self.scale = scale;
+    }
+
+    // Moves the bands towards the latest analysis window, or back to zero without one
+    pub fn update(&mut self, audio_data: Option<&AudioAnalysisData>) {
+        let num_bands = self.values.len();
+        for (band, value) in self.values.iter_mut().enumerate() {
+            let target = audio_data.map_or(0.0, |data| {
+                let low_hz = self.scale.frequency_at(band as f32 / num_bands as f32);
+                let high_hz = self
+                    .scale
+                    .frequency_at((band + 1) as f32 / num_bands as f32);
+                band_level(data, low_hz, high_hz)
+            });
+            let factor = if target > *value { ATTACK } else { RELEASE };
+            *value += (target - *value) * factor;
+        }
+    }
+}
+
+// Strongest bin between the two frequencies, mapped from dB into 0..1
+fn band_level(data: &AudioAnalysisData, low_hz: f32, high_hz: f32) -> f32 {
+    let bins = &data.frequency_magnitudes;
+    if bins.len() < 2 || data.sample_rate == 0 {
+        return 0.0;
+    }
+    let bin_width = data.sample_rate as f32 / data.fft_size as f32;
+
+    // Low bands can be narrower than a single bin, they still get the bin they fall into
+    let first = ((low_hz / bin_width).floor() as usize).clamp(1, bins.len() - 1);
+    let last = ((high_hz / bin_width).ceil() as usize).clamp(first + 1, bins.len());
+    let peak = bins[first..last].iter().fold(0.0f32, |a, &b| a.max(b));
+
+    // Hann windowed magnitudes come out at a quarter of the sine amplitude
+    let db = 20.0 * (peak * 4.0).max(1e-9).log10();
+    ((db - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0)
+}
@@ -0,0 +1,230 @@
+use crate::audio::{AudioAnalysisData, PlaybackState};
+use crate::visualization::bar_analyzer::BarAnalyzer;
+use crate::visualization::capture::CaptureTarget;
+use crate::visualization::custom_shader::CustomShader;
+use crate::visualization::goniometer::Goniometer;
+use crate::visualization::oscilloscope::Oscilloscope;
+use crate::visualization::particles::ParticleSystem;
+use crate::visualization::post_process::{PostProcessor, HDR_FORMAT};
+use crate::visualization::renderer::WgpuSphereRenderer;
+use crate::visualization::spectrogram::Spectrogram;
+use anyhow::Result;
+use eframe::{
+    egui,
+    egui_wgpu::{CallbackTrait, ScreenDescriptor},
+};
+use std::sync::Arc;
+use type_map::concurrent::TypeMap;
+
+// What a visualizer gets to look at every frame
+pub struct VisualizerInput<'a> {
+    pub playback_state: PlaybackState,
+    // Latest analysis window, kept around between windows
+    pub analysis: Option<&'a AudioAnalysisData>,
+}
+
+// A visualization mode. The app only talks to these hooks, so new modes just need
+// an implementation and an entry in `VisualizerRegistry::with_defaults`.
+pub trait Visualizer: Send {
+    fn name(&self) -> &'static str;
+
+    // Creates GPU resources. Called again whenever the mode gets activated, so this
+    // should return early when everything already exists.
+    fn prepare(
+        &mut self,
+        device: &Arc<wgpu::Device>,
+        target_format: wgpu::TextureFormat,
+    ) -> Result<()>;
+
+    // Adapter the device came from, for modes that can use more than wgpu guarantees
+    // every device (like higher MSAA sample counts)
+    fn set_adapter(&mut self, _adapter: &Arc<wgpu::Adapter>) {}
+
+    // Advances animation state, `dt` is in seconds
+    fn update(&mut self, input: &VisualizerInput, dt: f32);
+
+    // Snapshot of everything needed to draw the current frame into a rect with this
+    // aspect ratio. None when `prepare` hasn't succeeded. Mutable so incremental GPU
+    // uploads (new spectrogram rows and such) can be handed over to the frame.
+    fn paint(&mut self, aspect_ratio: f32) -> Option<Box<dyn VisualizerFrame>>;
+
+    // Mode specific controls, shown below the mode selector
+    fn settings_ui(&mut self, _ui: &mut egui::Ui) {}
+
+    // Mouse / keyboard input over the visualization rect
+    fn handle_input(&mut self, _response: &egui::Response) {}
+
+    // Problem to show on top of the visualization, like a shader that failed to compile
+    fn overlay_message(&self) -> Option<String> {
+        None
+    }
+
+    // What the mode's own modulation routes add to the bloom intensity this frame.
+    // None when no route drives the bloom, its audio drive in post processing runs then.
+    fn bloom_modulation(&self) -> Option<f32> {
+        None
+    }
+}
+
+// One frame worth of draw data. Lives inside the egui paint callback, so it has to own
+// (or hold Arcs to) everything it touches.
+pub trait VisualizerFrame: Send + Sync {
+    // Runs before egui's render pass begins. Frames that need their own render passes
+    // (depth, MSAA) record them here and then composite the result in `paint`.
+    // `size_in_pixels` is the size of the rect the frame ends up in.
+    fn prepare(
+        &self,
+        _device: &wgpu::Device,
+        _queue: &wgpu::Queue,
+        _encoder: &mut wgpu::CommandEncoder,
+        _size_in_pixels: [u32; 2],
+    ) {
+    }
+
+    fn paint<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, queue: &wgpu::Queue);
+}
+
+// Hands a visualizer frame to egui_wgpu
+pub struct VisualizerPaintCallback {
+    pub frame: Box<dyn VisualizerFrame>,
+    pub queue: Arc<wgpu::Queue>,
+    pub size_in_pixels: [u32; 2],
+    // Also draws the frame into this, for screenshots and clips
+    pub capture: Option<Arc<CaptureTarget>>,
+}
+
+impl CallbackTrait for VisualizerPaintCallback {
+    fn prepare(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        _screen_descriptor: &ScreenDescriptor,
+        egui_encoder: &mut wgpu::CommandEncoder,
+        _resources: &mut TypeMap,
+    ) -> Vec<wgpu::CommandBuffer> {
+        self.frame
+            .prepare(device, queue, egui_encoder, self.size_in_pixels);
+        if let Some(capture) = &self.capture {
+            capture.record(self.frame.as_ref(), queue, egui_encoder);
+        }
+        Vec::new()
+    }
+
+    fn paint<'a>(
+        &'a self,
+        _info: egui::PaintCallbackInfo,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        _resources: &'a TypeMap,
+    ) {
+        self.frame.paint(render_pass, &self.queue);
+    }
+}
+
+// All available modes plus the one currently on screen. Modes render in HDR and
+// go through the post processing chain on their way to the screen.
+pub struct VisualizerRegistry {
+    visualizers: Vec<Box<dyn Visualizer>>,
+    active: usize,
+    device: Option<Arc<wgpu::Device>>,
+    post: PostProcessor,
+}
+
+impl VisualizerRegistry {
+    pub fn new() -> Self {
+        VisualizerRegistry {
+            visualizers: Vec::new(),
+            active: 0,
+            device: None,
+            post: PostProcessor::new(),
+        }
+    }
+
+    // Every built-in mode, the sphere first
+    pub fn with_defaults() -> Self {
+        let mut registry = Self::new();
+        registry.register(Box::new(WgpuSphereRenderer::default()));
+        registry.register(Box::new(ParticleSystem::new()));
+        registry.register(Box::new(BarAnalyzer::new()));
+        registry.register(Box::new(Oscilloscope::new()));
+        registry.register(Box::new(Spectrogram::new()));
+        registry.register(Box::new(Goniometer::new()));
+        registry.register(Box::new(CustomShader::new()));
+        registry
+    }
+
+    pub fn register(&mut self, visualizer: Box<dyn Visualizer>) {
+        self.visualizers.push(visualizer);
+    }
+
+    // Remembers the device so modes can be prepared when they're first shown.
+    // `target_format` is the screen's, the modes themselves get `HDR_FORMAT`.
+    pub fn set_gpu(
+        &mut self,
+        device: Arc<wgpu::Device>,
+        adapter: &Arc<wgpu::Adapter>,
+        target_format: wgpu::TextureFormat,
+    ) {
+        if let Err(e) = self.post.prepare(&device, target_format) {
+            tracing::error!("Failed to prepare post processing: {}", e);
+        }
+        for visualizer in &mut self.visualizers {
+            visualizer.set_adapter(adapter);
+        }
+        self.device = Some(device);
+        self.prepare_active();
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.visualizers.iter().map(|v| v.name()).collect()
+    }
+
+    pub fn active_index(&self) -> usize {
+        self.active
+    }
+
+    pub fn set_active(&mut self, index: usize) {
+        if index < self.visualizers.len() && index != self.active {
+            self.active = index;
+            self.prepare_active();
+        }
+    }
+
+    pub fn active_mut(&mut self) -> Option<&mut (dyn Visualizer + 'static)> {
+        self.visualizers.get_mut(self.active).map(|v| v.as_mut())
+    }
+
+    pub fn post_mut(&mut self) -> &mut PostProcessor {
+        &mut self.post
+    }
+
+    // Advances the active mode and the audio driven post effects
+    pub fn update(&mut self, input: &VisualizerInput, dt: f32) {
+        let bloom = self.active_mut().and_then(|visualizer| {
+            visualizer.update(input, dt);
+            visualizer.bloom_modulation()
+        });
+        self.post.set_bloom_modulation(bloom);
+        self.post.update(input, dt);
+    }
+
+    // Active mode's frame, wrapped in the post processing chain
+    pub fn paint(&mut self, aspect_ratio: f32) -> Option<Box<dyn VisualizerFrame>> {
+        let frame = self.active_mut()?.paint(aspect_ratio)?;
+        self.post.wrap(frame)
+    }
+
+    fn prepare_active(&mut self) {
+        let Some(device) = self.device.clone() else {
+            return;
+        };
+        if let Some(visualizer) = self.visualizers.get_mut(self.active) {
+            if let Err(e) = visualizer.prepare(&device, HDR_FORMAT) {
+                tracing::error!(
+                    "Failed to prepare visualizer '{}': {}",
+                    visualizer.name(),
+                    e
+                );
+            }
+        }
+    }
+}